@@ -0,0 +1,700 @@
+//! Canonical XML serialization, of parsed documents by `Canonicalizer` and of element trees by
+//! `Element::canonicalize`
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::rc::Rc;
+
+use xml::reader::{ParserConfig, XmlEvent};
+
+use crate::traverse::{Traverse, TraverseEvent};
+use crate::{Element, TreexmlError};
+
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// A Canonical XML algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Canonicalization {
+    /// Canonical XML 1.0, omitting comments
+    C14n10,
+    /// Canonical XML 1.0, keeping comments
+    C14n10WithComments,
+    /// Canonical XML 1.1, omitting comments
+    C14n11,
+    /// Canonical XML 1.1, keeping comments
+    C14n11WithComments,
+    /// Exclusive XML Canonicalization 1.0, omitting comments
+    ExcC14n,
+    /// Exclusive XML Canonicalization 1.0, keeping comments
+    ExcC14nWithComments,
+}
+
+impl Canonicalization {
+    /// The URI identifying the algorithm, as used in XML signatures
+    pub fn uri(self) -> &'static str {
+        match self {
+            Canonicalization::C14n10 => "http://www.w3.org/TR/2001/REC-xml-c14n-20010315",
+            Canonicalization::C14n10WithComments => {
+                "http://www.w3.org/TR/2001/REC-xml-c14n-20010315#WithComments"
+            }
+            Canonicalization::C14n11 => "http://www.w3.org/2006/12/xml-c14n11",
+            Canonicalization::C14n11WithComments => {
+                "http://www.w3.org/2006/12/xml-c14n11#WithComments"
+            }
+            Canonicalization::ExcC14n => "http://www.w3.org/2001/10/xml-exc-c14n#",
+            Canonicalization::ExcC14nWithComments => {
+                "http://www.w3.org/2001/10/xml-exc-c14n#WithComments"
+            }
+        }
+    }
+
+    /// Whether the algorithm keeps comments
+    pub fn with_comments(self) -> bool {
+        match self {
+            Canonicalization::C14n10WithComments
+            | Canonicalization::C14n11WithComments
+            | Canonicalization::ExcC14nWithComments => true,
+            Canonicalization::C14n10 | Canonicalization::C14n11 | Canonicalization::ExcC14n => {
+                false
+            }
+        }
+    }
+
+    /// Whether the algorithm is Exclusive XML Canonicalization, which only writes the
+    /// namespace declarations an element uses
+    pub fn exclusive(self) -> bool {
+        matches!(
+            self,
+            Canonicalization::ExcC14n | Canonicalization::ExcC14nWithComments
+        )
+    }
+}
+
+impl fmt::Display for Canonicalization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.uri())
+    }
+}
+
+/// Writes the canonical form of a document, or of subtrees of it, from its source
+///
+/// Working from the parser's events keeps what an `Element` tree loses: comments, processing
+/// instructions, whitespace and the order of text and children in mixed content. The DTD isn't
+/// processed, so documents that rely on it for default attributes, entities or attribute
+/// types won't reproduce their canonical form.
+#[derive(Clone)]
+pub struct Canonicalizer {
+    method: Canonicalization,
+    inclusive_prefixes: Vec<String>,
+    subtree: Option<Rc<Apex>>,
+}
+
+/// Picks the elements whose subtrees a `Canonicalizer` writes
+type Apex = dyn Fn(&Element) -> bool;
+
+impl fmt::Debug for Canonicalizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Canonicalizer")
+            .field("method", &self.method)
+            .field("inclusive_prefixes", &self.inclusive_prefixes)
+            .field("subtree", &self.subtree.is_some())
+            .finish()
+    }
+}
+
+impl Canonicalizer {
+    /// Create a canonicalizer for `method`, writing the whole document
+    pub fn new(method: Canonicalization) -> Canonicalizer {
+        Canonicalizer {
+            method,
+            inclusive_prefixes: Vec::new(),
+            subtree: None,
+        }
+    }
+
+    /// Set the `InclusiveNamespaces` prefix list of an exclusive method, where `#default`
+    /// stands for the default namespace
+    pub fn inclusive_prefixes(&mut self, prefixes: &[&str]) -> &mut Self {
+        self.inclusive_prefixes = prefixes.iter().map(|&prefix| prefix.to_owned()).collect();
+        self
+    }
+
+    /// Only write the subtrees of elements `apex` returns true for, in document order
+    ///
+    /// `apex` is passed each element before its content is parsed, with its attributes and the
+    /// namespaces it declares but no children. Inclusive methods write the namespaces in scope
+    /// on each apex, and its ancestors' `xml:` attributes: all of them in Canonical XML 1.0,
+    /// and `xml:lang` and `xml:space` in 1.1, which fails if an ancestor has an `xml:base` to
+    /// fix up.
+    pub fn subtree<F: Fn(&Element) -> bool + 'static>(&mut self, apex: F) -> &mut Self {
+        self.subtree = Some(Rc::new(apex));
+        self
+    }
+
+    /// Parse the document in `r` and write its canonical form to `w`
+    ///
+    /// The source is read whole, to normalize line endings and whitespace in attribute values
+    /// as an XML processor would before parsing it.
+    ///
+    /// # Failures
+    ///
+    /// Passes on errors from parsing `r` and writing to `w`
+    pub fn canonicalize<R: Read, W: Write>(&self, r: R, w: &mut W) -> Result<(), TreexmlError> {
+        use xml::namespace::Namespace;
+
+        let mut input = Vec::new();
+        let mut r = r;
+        r.read_to_end(&mut input)?;
+        let input = normalize(&input);
+        let mut reader = ParserConfig::new()
+            .trim_whitespace(false)
+            .whitespace_to_characters(true)
+            .cdata_to_characters(true)
+            .coalesce_characters(true)
+            .ignore_comments(!self.method.with_comments())
+            .create_reader(&input[..]);
+
+        let listed = self
+            .inclusive_prefixes
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        let exclusive = Some(&listed[..]).filter(|_| self.method.exclusive());
+
+        let mut out = String::new();
+        let mut open: Vec<Open> = Vec::new();
+        // The depth of the element whose subtree is being written
+        let mut apex = None;
+        let mut after_root = false;
+        loop {
+            out.clear();
+            match reader.next()? {
+                XmlEvent::StartElement {
+                    name,
+                    attributes,
+                    namespace,
+                } => {
+                    let mut xml = open.last().map(|el| el.xml.clone()).unwrap_or_default();
+                    for attr in &attributes {
+                        if attr.name.prefix.as_deref() == Some("xml") {
+                            xml.insert(attr.name.local_name.clone(), attr.value.clone());
+                        }
+                    }
+
+                    let output = apex.is_some()
+                        || match self.subtree {
+                            None => true,
+                            Some(ref subtree) => {
+                                let empty = Namespace::empty();
+                                let parent = open.last().map_or(&empty, |el| &el.namespace);
+                                subtree(&Element::from_start_event(
+                                    name.clone(),
+                                    attributes.clone(),
+                                    &namespace,
+                                    parent,
+                                ))
+                            }
+                        };
+                    let qualified = qualified_name(name.prefix.as_deref(), &name.local_name);
+                    let mut rendered = Rc::default();
+                    if output {
+                        let mut attributes = attributes
+                            .iter()
+                            .map(|attr| {
+                                let prefix = attr.name.prefix.as_deref();
+                                let name = qualified_name(prefix, &attr.name.local_name);
+                                (name, attr.value.clone())
+                            })
+                            .collect::<Vec<_>>();
+                        match (apex, open.last()) {
+                            (None, Some(parent)) => {
+                                attributes.extend(self.inherited(&parent.xml, &attributes)?);
+                                apex = Some(open.len());
+                            }
+                            (None, None) => apex = Some(0),
+                            (Some(_), _) => {}
+                        }
+
+                        let attributes = attributes
+                            .iter()
+                            .map(|(name, value)| (name.as_str(), value.as_str()))
+                            .collect::<Vec<_>>();
+                        let scope = namespace
+                            .0
+                            .iter()
+                            .map(|(prefix, uri)| (prefix.as_str(), uri.as_str()))
+                            .collect();
+                        let parent = match open.last() {
+                            Some(parent) if parent.output => parent.rendered.clone(),
+                            _ => Rc::default(),
+                        };
+                        rendered = start_tag(
+                            &mut out,
+                            &qualified,
+                            &attributes,
+                            &scope,
+                            &parent,
+                            exclusive,
+                        );
+                    }
+                    open.push(Open {
+                        name: qualified,
+                        output,
+                        rendered,
+                        namespace,
+                        xml,
+                    });
+                }
+                XmlEvent::EndElement { .. } => {
+                    if let Some(el) = open.pop() {
+                        if el.output {
+                            end_tag(&mut out, &el.name);
+                        }
+                    }
+                    if apex == Some(open.len()) {
+                        apex = None;
+                    }
+                    after_root = open.is_empty();
+                }
+                XmlEvent::Characters(text) if apex.is_some() => escape_text(&mut out, &text),
+                XmlEvent::Comment(text) => {
+                    if let Some(top) = self.position(apex, open.is_empty()) {
+                        outside_root(&mut out, top, after_root, |out| comment(out, &text));
+                    }
+                }
+                XmlEvent::ProcessingInstruction { name, data } => {
+                    if let Some(top) = self.position(apex, open.is_empty()) {
+                        outside_root(&mut out, top, after_root, |out| {
+                            processing_instruction(out, &name, data.as_deref())
+                        });
+                    }
+                }
+                XmlEvent::EndDocument => break,
+                _ => {}
+            }
+            w.write_all(out.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Whether a comment or processing instruction is written, and if so whether it's outside
+    /// the document element, given the `apex` being written and whether it's at the `top` level
+    fn position(&self, apex: Option<usize>, top: bool) -> Option<bool> {
+        match (apex, &self.subtree) {
+            (Some(_), _) => Some(false),
+            (None, None) if top => Some(true),
+            (None, _) => None,
+        }
+    }
+
+    /// The `xml:` attributes an apex inherits from its ancestors, where `xml` holds those in
+    /// effect by local name and `own` its own attributes
+    fn inherited(
+        &self,
+        xml: &BTreeMap<String, String>,
+        own: &[(String, String)],
+    ) -> Result<Vec<(String, String)>, TreexmlError> {
+        let mut inherited = Vec::new();
+        for (name, value) in xml {
+            let qualified = format!("xml:{}", name);
+            if own.iter().any(|(own, _)| *own == qualified) {
+                continue;
+            }
+            let inherits = match self.method {
+                Canonicalization::C14n10 | Canonicalization::C14n10WithComments => true,
+                Canonicalization::C14n11 | Canonicalization::C14n11WithComments => {
+                    match name.as_str() {
+                        "base" => {
+                            return Err(TreexmlError::Canonicalization {
+                                msg: format!(
+                                    "{} needs xml:base fix-up, which isn't supported",
+                                    self.method
+                                ),
+                            })
+                        }
+                        "id" => false,
+                        _ => true,
+                    }
+                }
+                Canonicalization::ExcC14n | Canonicalization::ExcC14nWithComments => false,
+            };
+            if inherits {
+                inherited.push((qualified, value.clone()));
+            }
+        }
+        Ok(inherited)
+    }
+}
+
+/// An element being parsed by a `Canonicalizer`
+struct Open {
+    /// Qualified name
+    name: String,
+    /// Whether it's written
+    output: bool,
+    /// The namespace declarations written on it and its output ancestors
+    rendered: Rc<Rendered>,
+    /// The namespaces in scope
+    namespace: xml::namespace::Namespace,
+    /// The `xml:` attributes in effect, by local name
+    xml: BTreeMap<String, String>,
+}
+
+/// Normalize the line endings of `input` to line feeds, and the whitespace characters in its
+/// attribute values to spaces, which the parser leaves as they are
+fn normalize(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        let rest = &input[i..];
+        let end: Option<&[u8]> = if rest.starts_with(b"<!--") {
+            Some(b"-->")
+        } else if rest.starts_with(b"<![CDATA[") {
+            Some(b"]]>")
+        } else if rest.starts_with(b"<?") {
+            Some(b"?>")
+        } else {
+            None
+        };
+        let len = match end {
+            Some(end) => find(rest, end).map_or(rest.len(), |at| at + end.len()),
+            None if rest[0] == b'<' => markup_len(rest),
+            None => find(rest, b"<").unwrap_or(rest.len()),
+        };
+        let tag = end.is_none() && rest[0] == b'<' && !rest.starts_with(b"<!");
+        push_normalized(&mut out, &rest[..len], tag);
+        i += len;
+    }
+    out
+}
+
+/// The length of the tag or declaration at the start of `markup`, which ends at the first `>`
+/// outside quotes and the brackets of an internal subset
+fn markup_len(markup: &[u8]) -> usize {
+    let mut quote = None;
+    let mut depth = 0usize;
+    for (i, &c) in markup.iter().enumerate() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                b'"' | b'\'' => quote = Some(c),
+                b'[' => depth += 1,
+                b']' => depth = depth.saturating_sub(1),
+                b'>' if depth == 0 => return i + 1,
+                _ => {}
+            },
+        }
+    }
+    markup.len()
+}
+
+/// Push `bytes` with their line endings normalized, and if they're a `tag`, the whitespace in
+/// its quoted values replaced by spaces
+fn push_normalized(out: &mut Vec<u8>, bytes: &[u8], tag: bool) {
+    let mut quote = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let in_value = quote.is_some() && quote != Some(c);
+        match c {
+            b'\r' => {
+                if bytes.get(i + 1) == Some(&b'\n') {
+                    i += 1;
+                }
+                out.push(if in_value { b' ' } else { b'\n' });
+            }
+            b'\n' | b'\t' if in_value => out.push(b' '),
+            _ => {
+                match quote {
+                    Some(q) if c == q => quote = None,
+                    None if tag && (c == b'"' || c == b'\'') => quote = Some(c),
+                    _ => {}
+                }
+                out.push(c);
+            }
+        }
+        i += 1;
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// The namespace declarations written on an element and its output ancestors, by prefix
+type Rendered = BTreeMap<String, String>;
+
+/// Write a node that's `top` if it's outside the root element, where it's separated from the
+/// root by a newline
+fn outside_root<F: FnOnce(&mut String)>(out: &mut String, top: bool, after_root: bool, node: F) {
+    if top && after_root {
+        out.push('\n');
+    }
+    node(out);
+    if top && !after_root {
+        out.push('\n');
+    }
+}
+
+/// The canonical form of `root` and its descendants under `method`, with `inclusive` as the
+/// `InclusiveNamespaces` prefix list of an exclusive method
+///
+/// The namespaces in scope for an element are those declared in the `namespaces` or `xmlns`
+/// attributes of it or its ancestors. A tree has no comments and doesn't keep the order of
+/// mixed content, so methods with comments and elements with mixed content are errors.
+pub(crate) fn canonicalize(
+    root: &Element,
+    method: Canonicalization,
+    inclusive: &[&str],
+) -> Result<String, TreexmlError> {
+    let exclusive = Some(inclusive).filter(|_| method.exclusive());
+    if method.with_comments() {
+        return Err(TreexmlError::Canonicalization {
+            msg: format!(
+                "an element tree has no comments to keep for {}; use a Canonicalizer",
+                method
+            ),
+        });
+    }
+
+    let mut out = String::new();
+    let mut rendered: Vec<Rc<Rendered>> = Vec::new();
+    let mut scopes: Vec<BTreeMap<&str, &str>> = Vec::new();
+    for event in Traverse::new(root) {
+        let el = match event {
+            TraverseEvent::Enter { element, .. } => element,
+            TraverseEvent::Leave { element, .. } => {
+                rendered.pop();
+                scopes.pop();
+                end_tag(&mut out, &element.qualified_name());
+                continue;
+            }
+        };
+
+        let texts = el.text.iter().chain(el.cdata.iter()).count();
+        if texts > 1 || (texts == 1 && !el.children.is_empty()) {
+            return Err(TreexmlError::Canonicalization {
+                msg: format!(
+                    "the order of the mixed content of '{}' isn't kept in an element tree; use a \
+                     Canonicalizer",
+                    el.qualified_name()
+                ),
+            });
+        }
+
+        let mut scope = scopes.last().cloned().unwrap_or_default();
+        scope.extend(el.declared_namespaces());
+        let attributes = el
+            .attributes
+            .iter()
+            .filter(|(name, _)| declared_prefix(name).is_none())
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect::<Vec<_>>();
+        let prefixes = el
+            .prefix
+            .as_deref()
+            .into_iter()
+            .chain(attributes.iter().filter_map(|(name, _)| prefix_of(name)));
+        for prefix in prefixes {
+            if prefix != "xml" && !scope.contains_key(prefix) {
+                return Err(TreexmlError::Canonicalization {
+                    msg: format!(
+                        "prefix '{}' isn't bound on '{}'",
+                        prefix,
+                        el.qualified_name()
+                    ),
+                });
+            }
+        }
+
+        let name = el.qualified_name();
+        let parent = rendered.last().cloned().unwrap_or_default();
+        rendered.push(start_tag(
+            &mut out,
+            &name,
+            &attributes,
+            &scope,
+            &parent,
+            exclusive,
+        ));
+        for text in el.text.iter().chain(el.cdata.iter()) {
+            escape_text(&mut out, text);
+        }
+        scopes.push(scope);
+    }
+    Ok(out)
+}
+
+/// Write the start tag of the element `name`, where `scope` holds the namespaces in scope and
+/// `parent` those written on its output ancestors, returning the declarations written on the
+/// element and its ancestors
+///
+/// A declaration is only written where it differs from what's been written for the ancestors,
+/// and if `exclusive` lists the inclusive prefixes, only where the element or its attributes
+/// use it or its prefix is listed.
+fn start_tag(
+    out: &mut String,
+    name: &str,
+    attributes: &[(&str, &str)],
+    scope: &BTreeMap<&str, &str>,
+    parent: &Rc<Rendered>,
+    exclusive: Option<&[&str]>,
+) -> Rc<Rendered> {
+    let utilized = |prefix: &str| match exclusive {
+        None => true,
+        Some(listed) => {
+            prefix_of(name).unwrap_or("") == prefix
+                || listed.contains(&if prefix.is_empty() {
+                    "#default"
+                } else {
+                    prefix
+                })
+                || attributes
+                    .iter()
+                    .any(|(name, _)| prefix_of(name) == Some(prefix))
+        }
+    };
+
+    // An empty default namespace is written where it unsets one written on an ancestor
+    let mut namespaces = Vec::new();
+    let default = scope.get("").copied().unwrap_or("");
+    if utilized("") && parent.get("").map_or("", String::as_str) != default {
+        namespaces.push(("", default));
+    }
+    for (&prefix, &uri) in scope {
+        if !prefix.is_empty()
+            && prefix != "xml"
+            && prefix != "xmlns"
+            && utilized(prefix)
+            && parent.get(prefix).map(String::as_str) != Some(uri)
+        {
+            namespaces.push((prefix, uri));
+        }
+    }
+
+    let mut attributes = attributes
+        .iter()
+        .map(|&(name, value)| (attribute_key(scope, name), name, value))
+        .collect::<Vec<_>>();
+    attributes.sort();
+
+    out.push('<');
+    out.push_str(name);
+    for &(prefix, uri) in &namespaces {
+        out.push_str(" xmlns");
+        if !prefix.is_empty() {
+            out.push(':');
+            out.push_str(prefix);
+        }
+        out.push_str("=\"");
+        escape_attribute(out, uri);
+        out.push('"');
+    }
+    for (_, name, value) in attributes {
+        out.push(' ');
+        out.push_str(name);
+        out.push_str("=\"");
+        escape_attribute(out, value);
+        out.push('"');
+    }
+    out.push('>');
+
+    if namespaces.is_empty() {
+        return parent.clone();
+    }
+    let mut rendered = Rendered::clone(parent);
+    for (prefix, uri) in namespaces {
+        rendered.insert(prefix.to_owned(), uri.to_owned());
+    }
+    Rc::new(rendered)
+}
+
+fn end_tag(out: &mut String, name: &str) {
+    out.push_str("</");
+    out.push_str(name);
+    out.push('>');
+}
+
+fn comment(out: &mut String, text: &str) {
+    out.push_str("<!--");
+    out.push_str(text);
+    out.push_str("-->");
+}
+
+fn processing_instruction(out: &mut String, target: &str, data: Option<&str>) {
+    out.push_str("<?");
+    out.push_str(target);
+    // The whitespace after the target separates it from the data
+    if let Some(data) = data.map(str::trim_start).filter(|data| !data.is_empty()) {
+        out.push(' ');
+        out.push_str(data);
+    }
+    out.push_str("?>");
+}
+
+fn qualified_name(prefix: Option<&str>, name: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}:{}", prefix, name),
+        None => name.to_owned(),
+    }
+}
+
+/// The prefix of the qualified name `name`, if it has one
+fn prefix_of(name: &str) -> Option<&str> {
+    name.find(':').map(|colon| &name[..colon])
+}
+
+/// The prefix an attribute named `name` declares, if it's a namespace declaration
+fn declared_prefix(name: &str) -> Option<&str> {
+    if name == "xmlns" {
+        Some("")
+    } else {
+        name.strip_prefix("xmlns:")
+    }
+}
+
+/// The namespace URI and local name attributes are sorted by, where unqualified attributes
+/// have an empty URI and so come first
+fn attribute_key<'a>(scope: &BTreeMap<&'a str, &'a str>, name: &'a str) -> (&'a str, &'a str) {
+    match name.find(':') {
+        Some(colon) => {
+            let prefix = &name[..colon];
+            let uri = match prefix {
+                "xml" => XML_NAMESPACE,
+                _ => scope.get(prefix).copied().unwrap_or(prefix),
+            };
+            (uri, &name[colon + 1..])
+        }
+        None => ("", name),
+    }
+}
+
+fn escape_text(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn escape_attribute(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' => out.push_str("&#x9;"),
+            '\n' => out.push_str("&#xA;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
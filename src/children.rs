@@ -0,0 +1,100 @@
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::{Deref, DerefMut};
+use std::slice::{Iter, IterMut};
+use std::sync::Arc;
+
+use crate::Element;
+
+/// The children of an `Element`
+///
+/// Cloning is O(1): clones share the same list until one of them is modified, at which
+/// point the modified copy is detached (copy-on-write).
+///
+/// Up to 0.7 `Element::children` was a `Vec<Element>`. `Children` dereferences to one, and
+/// converts from and into one with `From`, so code built on the `Vec` needs at most an
+/// `.into()` or `into_vec()`.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Children(Arc<Vec<Element>>);
+
+impl Children {
+    /// Create an empty list of children
+    pub fn new() -> Children {
+        Children::default()
+    }
+
+    /// Returns `true` if both lists share the same storage
+    pub fn ptr_eq(this: &Children, other: &Children) -> bool {
+        Arc::ptr_eq(&this.0, &other.0)
+    }
+
+    /// Unwrap into a `Vec`, copying only if the storage is shared
+    pub fn into_vec(self) -> Vec<Element> {
+        Arc::try_unwrap(self.0).unwrap_or_else(|shared| (*shared).clone())
+    }
+}
+
+impl Deref for Children {
+    type Target = Vec<Element>;
+
+    fn deref(&self) -> &Vec<Element> {
+        &self.0
+    }
+}
+
+impl DerefMut for Children {
+    fn deref_mut(&mut self) -> &mut Vec<Element> {
+        Arc::make_mut(&mut self.0)
+    }
+}
+
+impl fmt::Debug for Children {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl From<Vec<Element>> for Children {
+    fn from(children: Vec<Element>) -> Children {
+        Children(Arc::new(children))
+    }
+}
+
+impl From<Children> for Vec<Element> {
+    fn from(children: Children) -> Vec<Element> {
+        children.into_vec()
+    }
+}
+
+impl FromIterator<Element> for Children {
+    fn from_iter<I: IntoIterator<Item = Element>>(iter: I) -> Children {
+        Children::from(iter.into_iter().collect::<Vec<Element>>())
+    }
+}
+
+impl IntoIterator for Children {
+    type Item = Element;
+    type IntoIter = std::vec::IntoIter<Element>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_vec().into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Children {
+    type Item = &'a Element;
+    type IntoIter = Iter<'a, Element>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut Children {
+    type Item = &'a mut Element;
+    type IntoIter = IterMut<'a, Element>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
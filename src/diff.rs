@@ -0,0 +1,775 @@
+//! Structural diffs between element trees, produced by `Element::diff`
+//!
+//! Edits are located by XPath-style paths from the root, such as `/config/server[2]`. An index
+//! is only given when the element has siblings with the same name. Paths of removed elements
+//! and of the old side of changes refer to the old tree; paths of inserted elements refer to
+//! the new tree.
+//!
+//! Children are matched in three passes: identical elements in the same order, then identical
+//! elements that moved, then the remaining elements with the same name, which are compared
+//! recursively. Whatever is left was removed or inserted. Identical subtrees are found by
+//! hashing every element once, and ordered matches by a linear-space longest common
+//! subsequence, so long child lists with few changes are cheap to compare.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::{Element, Path};
+
+/// Options controlling what `Element::diff_with` reports
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffOptions {
+    ignore_attribute_order: bool,
+    ignore_whitespace_text: bool,
+    ignored: Vec<Path>,
+}
+
+impl DiffOptions {
+    /// Create options that report every difference
+    pub fn new() -> DiffOptions {
+        DiffOptions::default()
+    }
+
+    /// Don't report attributes that are the same apart from their order
+    pub fn ignore_attribute_order(&mut self, ignore: bool) -> &mut Self {
+        self.ignore_attribute_order = ignore;
+        self
+    }
+
+    /// Treat text and CDATA made up only of whitespace as absent
+    pub fn ignore_whitespace_text(&mut self, ignore: bool) -> &mut Self {
+        self.ignore_whitespace_text = ignore;
+        self
+    }
+
+    /// Ignore the elements `path` matches from the root of either tree, or just the attribute
+    /// if it ends in `@name`
+    pub fn ignore(&mut self, path: Path) -> &mut Self {
+        self.ignored.push(path);
+        self
+    }
+}
+
+/// A single difference between two element trees
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    /// An element only in the new tree
+    ElementInserted { path: String },
+    /// An element only in the old tree
+    ElementRemoved { path: String },
+    /// An element found unchanged in a different place
+    ElementMoved { from: String, to: String },
+    /// An attribute only on the new element
+    AttributeAdded {
+        path: String,
+        name: String,
+        value: String,
+    },
+    /// An attribute whose value changed
+    AttributeChanged {
+        path: String,
+        name: String,
+        old: String,
+        new: String,
+    },
+    /// An attribute only on the old element
+    AttributeRemoved {
+        path: String,
+        name: String,
+        value: String,
+    },
+    /// Attributes that are on both elements, in a different order
+    AttributesReordered {
+        path: String,
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+    /// Text that changed, appeared or disappeared
+    TextChanged {
+        path: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// CDATA that changed, appeared or disappeared
+    CDataChanged {
+        path: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+}
+
+/// The differences between two element trees, in document order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diff {
+    /// The edits that turn the old tree into the new one
+    pub edits: Vec<Edit>,
+}
+
+impl Diff {
+    /// Whether the trees are the same
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    pub(crate) fn new(old: &Element, new: &Element, options: &DiffOptions) -> Diff {
+        let mut differ = Differ::new(options);
+        let (old_tree, new_tree) = (differ.tree(old), differ.tree(new));
+
+        let (old_path, new_path) = (
+            format!("/{}", old.qualified_name()),
+            format!("/{}", new.qualified_name()),
+        );
+        if old.qualified_name() == new.qualified_name() {
+            differ.compare(
+                (&old_tree, Tree::ROOT, &old_path),
+                (&new_tree, Tree::ROOT, &new_path),
+            );
+        } else {
+            differ.edits.push(Edit::ElementRemoved { path: old_path });
+            differ.edits.push(Edit::ElementInserted { path: new_path });
+        }
+
+        Diff {
+            edits: differ.edits,
+        }
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for edit in &self.edits {
+            writeln!(f, "{}", edit)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Edit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Edit::ElementInserted { ref path } => write!(f, "inserted {}", path),
+            Edit::ElementRemoved { ref path } => write!(f, "removed {}", path),
+            Edit::ElementMoved { ref from, ref to } => write!(f, "moved {} to {}", from, to),
+            Edit::AttributeAdded {
+                ref path,
+                ref name,
+                ref value,
+            } => write!(f, "added {}/@{} = {:?}", path, name, value),
+            Edit::AttributeChanged {
+                ref path,
+                ref name,
+                ref old,
+                ref new,
+            } => write!(f, "changed {}/@{} from {:?} to {:?}", path, name, old, new),
+            Edit::AttributeRemoved {
+                ref path,
+                ref name,
+                ref value,
+            } => write!(f, "removed {}/@{} (was {:?})", path, name, value),
+            Edit::AttributesReordered {
+                ref path,
+                ref old,
+                ref new,
+            } => write!(
+                f,
+                "reordered attributes of {} from {} to {}",
+                path,
+                old.join(", "),
+                new.join(", ")
+            ),
+            Edit::TextChanged {
+                ref path,
+                ref old,
+                ref new,
+            } => write!(
+                f,
+                "changed text of {} from {} to {}",
+                path,
+                Quoted(old),
+                Quoted(new)
+            ),
+            Edit::CDataChanged {
+                ref path,
+                ref old,
+                ref new,
+            } => write!(
+                f,
+                "changed CDATA of {} from {} to {}",
+                path,
+                Quoted(old),
+                Quoted(new)
+            ),
+        }
+    }
+}
+
+/// Displays optional text quoted, or as `nothing`
+struct Quoted<'a>(&'a Option<String>);
+
+impl<'a> fmt::Display for Quoted<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            Some(ref text) => write!(f, "{:?}", text),
+            None => f.write_str("nothing"),
+        }
+    }
+}
+
+/// An element tree laid out for diffing
+///
+/// Each element gets a hash of what `Differ::equal` compares, so most unequal subtrees are told
+/// apart without walking them, and what the options ignore is marked by location. Clones share
+/// their children, so the same `Element` can turn up at several locations.
+pub(crate) struct Tree<'a> {
+    /// The elements in breadth-first order, so children come after their parents
+    nodes: Vec<Node<'a>>,
+}
+
+struct Node<'a> {
+    element: &'a Element,
+    /// Indices of the children in `Tree::nodes`, including ignored ones
+    children: Vec<usize>,
+    ignored: bool,
+    ignored_attributes: Vec<&'a str>,
+    hash: u64,
+}
+
+impl<'a> Tree<'a> {
+    /// The root element, at index 0
+    pub(crate) const ROOT: usize = 0;
+
+    pub(crate) fn element(&self, index: usize) -> &'a Element {
+        self.nodes[index].element
+    }
+
+    /// The indices of the children of the element at `index` that aren't ignored
+    pub(crate) fn children(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        self.nodes[index]
+            .children
+            .iter()
+            .copied()
+            .filter(move |&child| !self.nodes[child].ignored)
+    }
+
+    /// The index of the element at `location`, the child indices leading to it from the root
+    fn find(&self, location: &[usize]) -> usize {
+        location.iter().fold(Tree::ROOT, |index, &child| {
+            self.nodes[index].children[child]
+        })
+    }
+}
+
+pub(crate) struct Differ<'a> {
+    options: &'a DiffOptions,
+    edits: Vec<Edit>,
+}
+
+impl<'a> Differ<'a> {
+    pub(crate) fn new(options: &'a DiffOptions) -> Differ<'a> {
+        Differ {
+            options,
+            edits: Vec::new(),
+        }
+    }
+
+    /// Lay out `root` and its descendants for diffing
+    pub(crate) fn tree(&self, root: &'a Element) -> Tree<'a> {
+        let mut nodes = vec![Node {
+            element: root,
+            children: Vec::new(),
+            ignored: false,
+            ignored_attributes: Vec::new(),
+            hash: 0,
+        }];
+        let mut next = 0;
+        while next < nodes.len() {
+            for child in nodes[next].element.children.iter() {
+                let index = nodes.len();
+                nodes.push(Node {
+                    element: child,
+                    children: Vec::new(),
+                    ignored: false,
+                    ignored_attributes: Vec::new(),
+                    hash: 0,
+                });
+                nodes[next].children.push(index);
+            }
+            next += 1;
+        }
+        let mut tree = Tree { nodes };
+
+        for path in &self.options.ignored {
+            for (location, _) in path.locate(root) {
+                let index = tree.find(&location);
+                match path.attribute() {
+                    Some(name) => tree.nodes[index].ignored_attributes.push(name),
+                    None => tree.nodes[index].ignored = true,
+                }
+            }
+        }
+
+        for index in (0..tree.nodes.len()).rev() {
+            let hash = self.hash(&tree, index);
+            tree.nodes[index].hash = hash;
+        }
+        tree
+    }
+
+    /// Hash what `equal` compares of the element at `index`, whose children have been hashed
+    fn hash(&self, tree: &Tree, index: usize) -> u64 {
+        let node = &tree.nodes[index];
+        let mut hasher = DefaultHasher::new();
+        node.element.prefix.hash(&mut hasher);
+        node.element.name.hash(&mut hasher);
+        self.text(&node.element.text).hash(&mut hasher);
+        self.text(&node.element.cdata).hash(&mut hasher);
+        let mut attributes = self.attributes(node);
+        if self.options.ignore_attribute_order {
+            attributes.sort_unstable();
+        }
+        attributes.hash(&mut hasher);
+        for child in tree.children(index) {
+            tree.nodes[child].hash.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    fn text<'t>(&self, text: &'t Option<String>) -> Option<&'t str> {
+        text.as_deref()
+            .filter(|t| !(self.options.ignore_whitespace_text && t.trim().is_empty()))
+    }
+
+    fn attributes<'e>(&self, node: &Node<'e>) -> Vec<(&'e str, &'e str)> {
+        node.element
+            .attributes
+            .iter()
+            .filter(|(name, _)| !node.ignored_attributes.contains(&name.as_str()))
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect()
+    }
+
+    /// The children that aren't ignored, with their paths
+    fn children(&self, tree: &Tree, index: usize, path: &str) -> Vec<(String, usize)> {
+        let paths = child_paths(path, tree.element(index));
+        paths
+            .into_iter()
+            .zip(tree.nodes[index].children.iter().copied())
+            .filter(|&(_, child)| !tree.nodes[child].ignored)
+            .collect()
+    }
+
+    /// Whether the edits between two elements would all be ignored
+    fn equal(&self, old: &Tree, i: usize, new: &Tree, j: usize) -> bool {
+        let mut pending = vec![(i, j)];
+        while let Some((i, j)) = pending.pop() {
+            let (old_node, new_node) = (&old.nodes[i], &new.nodes[j]);
+            if old_node.hash != new_node.hash || !self.shallow_equal(old_node, new_node) {
+                return false;
+            }
+            let (mut old_children, mut new_children) = (old.children(i), new.children(j));
+            loop {
+                match (old_children.next(), new_children.next()) {
+                    (Some(i), Some(j)) => pending.push((i, j)),
+                    (None, None) => break,
+                    _ => return false,
+                }
+            }
+        }
+        true
+    }
+
+    /// Whether two elements are equal, ignoring their children
+    fn shallow_equal(&self, old: &Node, new: &Node) -> bool {
+        let (old_element, new_element) = (old.element, new.element);
+        if old_element.qualified_name() != new_element.qualified_name()
+            || self.text(&old_element.text) != self.text(&new_element.text)
+            || self.text(&old_element.cdata) != self.text(&new_element.cdata)
+        {
+            return false;
+        }
+
+        let (mut old_attributes, mut new_attributes) = (self.attributes(old), self.attributes(new));
+        if self.options.ignore_attribute_order {
+            old_attributes.sort_unstable();
+            new_attributes.sort_unstable();
+        }
+        old_attributes == new_attributes
+    }
+
+    /// Record the edits between two elements with the same name
+    fn compare(&mut self, old: (&Tree, usize, &str), new: (&Tree, usize, &str)) {
+        let (old_tree, i, old_path) = old;
+        let (new_tree, j, _) = new;
+        self.compare_attributes(&old_tree.nodes[i], &new_tree.nodes[j], old_path);
+
+        let (old_element, new_element) = (old_tree.element(i), new_tree.element(j));
+        let (old_text, new_text) = (self.text(&old_element.text), self.text(&new_element.text));
+        if old_text != new_text {
+            self.edits.push(Edit::TextChanged {
+                path: old_path.to_owned(),
+                old: old_text.map(str::to_owned),
+                new: new_text.map(str::to_owned),
+            });
+        }
+        let (old_cdata, new_cdata) = (self.text(&old_element.cdata), self.text(&new_element.cdata));
+        if old_cdata != new_cdata {
+            self.edits.push(Edit::CDataChanged {
+                path: old_path.to_owned(),
+                old: old_cdata.map(str::to_owned),
+                new: new_cdata.map(str::to_owned),
+            });
+        }
+
+        self.compare_children(old, new);
+    }
+
+    fn compare_attributes(&mut self, old: &Node, new: &Node, path: &str) {
+        let (old_attributes, new_attributes) = (self.attributes(old), self.attributes(new));
+
+        for &(name, value) in &old_attributes {
+            match new_attributes.iter().find(|(n, _)| *n == name) {
+                None => self.edits.push(Edit::AttributeRemoved {
+                    path: path.to_owned(),
+                    name: name.to_owned(),
+                    value: value.to_owned(),
+                }),
+                Some(&(_, new_value)) if new_value != value => {
+                    self.edits.push(Edit::AttributeChanged {
+                        path: path.to_owned(),
+                        name: name.to_owned(),
+                        old: value.to_owned(),
+                        new: new_value.to_owned(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        for &(name, value) in &new_attributes {
+            if !old_attributes.iter().any(|(n, _)| *n == name) {
+                self.edits.push(Edit::AttributeAdded {
+                    path: path.to_owned(),
+                    name: name.to_owned(),
+                    value: value.to_owned(),
+                });
+            }
+        }
+
+        if !self.options.ignore_attribute_order {
+            let shared = |attributes: &[(&str, &str)], other: &[(&str, &str)]| -> Vec<String> {
+                attributes
+                    .iter()
+                    .filter(|(name, _)| other.iter().any(|(n, _)| n == name))
+                    .map(|(name, _)| (*name).to_owned())
+                    .collect()
+            };
+            let old_order = shared(&old_attributes, &new_attributes);
+            let new_order = shared(&new_attributes, &old_attributes);
+            if old_order != new_order {
+                self.edits.push(Edit::AttributesReordered {
+                    path: path.to_owned(),
+                    old: old_order,
+                    new: new_order,
+                });
+            }
+        }
+    }
+
+    fn compare_children(&mut self, old: (&Tree, usize, &str), new: (&Tree, usize, &str)) {
+        let (old_tree, i, old_path) = old;
+        let (new_tree, j, new_path) = new;
+        let old_children = self.children(old_tree, i, old_path);
+        let new_children = self.children(new_tree, j, new_path);
+        let (old_matches, new_matches) = self.match_children(
+            old_tree,
+            &old_children.iter().map(|&(_, i)| i).collect::<Vec<_>>(),
+            new_tree,
+            &new_children.iter().map(|&(_, j)| j).collect::<Vec<_>>(),
+        );
+
+        for (i, matched) in old_matches.iter().enumerate() {
+            if matched.is_none() {
+                self.edits.push(Edit::ElementRemoved {
+                    path: old_children[i].0.clone(),
+                });
+            }
+        }
+        for (j, matched) in new_matches.iter().enumerate() {
+            let (ref new_child_path, new_child) = new_children[j];
+            match *matched {
+                None => self.edits.push(Edit::ElementInserted {
+                    path: new_child_path.clone(),
+                }),
+                Some(Match::Moved(i)) => self.edits.push(Edit::ElementMoved {
+                    from: old_children[i].0.clone(),
+                    to: new_child_path.clone(),
+                }),
+                Some(Match::Changed(i)) => {
+                    let (ref old_child_path, old_child) = old_children[i];
+                    self.compare(
+                        (old_tree, old_child, old_child_path),
+                        (new_tree, new_child, new_child_path),
+                    );
+                }
+                Some(Match::Same(_)) => {}
+            }
+        }
+    }
+
+    /// Match two lists of children, given by index in their trees, returning what each one was
+    /// matched with on the other side
+    pub(crate) fn match_children(
+        &self,
+        old_tree: &Tree,
+        old: &[usize],
+        new_tree: &Tree,
+        new: &[usize],
+    ) -> (Vec<Option<Match>>, Vec<Option<Match>>) {
+        let mut old_matches: Vec<Option<Match>> = vec![None; old.len()];
+        let mut new_matches: Vec<Option<Match>> = vec![None; new.len()];
+
+        // Number the children so that equal ones get the same number, comparing each with one
+        // child of every earlier number that has the same hash
+        let mut numbers: HashMap<u64, Vec<(usize, &Tree, usize)>> = HashMap::new();
+        let mut numbered = Vec::with_capacity(old.len() + new.len());
+        let children = old.iter().map(|&i| (old_tree, i));
+        for (tree, index) in children.chain(new.iter().map(|&j| (new_tree, j))) {
+            let count = numbered.len();
+            let same_hash = numbers.entry(tree.nodes[index].hash).or_default();
+            let number = match same_hash
+                .iter()
+                .find(|&&(_, other_tree, other)| self.equal(other_tree, other, tree, index))
+            {
+                Some(&(number, _, _)) => number,
+                None => {
+                    same_hash.push((count, tree, index));
+                    count
+                }
+            };
+            numbered.push(number);
+        }
+        let (old_numbers, new_numbers) = numbered.split_at(old.len());
+
+        let anchors = lcs(old.len(), new.len(), |i, j| {
+            old_numbers[i] == new_numbers[j]
+        });
+        for (i, j) in anchors {
+            old_matches[i] = Some(Match::Same(j));
+            new_matches[j] = Some(Match::Same(i));
+        }
+
+        // Pair the rest of the equal children in order, as moves
+        let mut unmatched: HashMap<usize, VecDeque<usize>> = HashMap::new();
+        for i in (0..old.len()).filter(|&i| old_matches[i].is_none()) {
+            unmatched.entry(old_numbers[i]).or_default().push_back(i);
+        }
+        for j in 0..new.len() {
+            if new_matches[j].is_some() {
+                continue;
+            }
+            if let Some(i) = unmatched
+                .get_mut(&new_numbers[j])
+                .and_then(VecDeque::pop_front)
+            {
+                old_matches[i] = Some(Match::Moved(j));
+                new_matches[j] = Some(Match::Moved(i));
+            }
+        }
+
+        // Match the remaining children by name, keeping their relative order
+        let old_rest: Vec<usize> = (0..old.len())
+            .filter(|&i| old_matches[i].is_none())
+            .collect();
+        let new_rest: Vec<usize> = (0..new.len())
+            .filter(|&j| new_matches[j].is_none())
+            .collect();
+        let pairs = lcs(old_rest.len(), new_rest.len(), |i, j| {
+            old_tree.element(old[old_rest[i]]).qualified_name()
+                == new_tree.element(new[new_rest[j]]).qualified_name()
+        });
+        for (i, j) in pairs {
+            old_matches[old_rest[i]] = Some(Match::Changed(new_rest[j]));
+            new_matches[new_rest[j]] = Some(Match::Changed(old_rest[i]));
+        }
+
+        (old_matches, new_matches)
+    }
+}
+
+/// What a child was matched with, by index on the other side
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Match {
+    /// Identical and in order
+    Same(usize),
+    /// Identical and out of order
+    Moved(usize),
+    /// The same name, but with differences
+    Changed(usize),
+}
+
+/// The path of the child at `index` within `parent`, indexed if it has siblings with the
+/// same name
+pub(crate) fn child_path(path: &str, parent: &Element, index: usize) -> String {
+    let child = &parent.children[index];
+    let name = child.qualified_name();
+    let same_name =
+        |sibling: &&Element| sibling.prefix == child.prefix && sibling.name == child.name;
+    let position = parent.children[..index].iter().filter(same_name).count() + 1;
+    if position == 1 && !parent.children[index + 1..].iter().any(|s| same_name(&s)) {
+        format!("{}/{}", path, name)
+    } else {
+        format!("{}/{}[{}]", path, name, position)
+    }
+}
+
+/// The paths of every child of `parent`, as `child_path` gives them
+pub(crate) fn child_paths(path: &str, parent: &Element) -> Vec<String> {
+    fn key(child: &Element) -> (Option<&str>, &str) {
+        (child.prefix.as_deref(), child.name.as_str())
+    }
+
+    let mut totals: HashMap<(Option<&str>, &str), usize> = HashMap::new();
+    for child in parent.children.iter() {
+        *totals.entry(key(child)).or_default() += 1;
+    }
+    let mut seen: HashMap<(Option<&str>, &str), usize> = HashMap::new();
+    parent
+        .children
+        .iter()
+        .map(|child| {
+            let position = seen.entry(key(child)).or_default();
+            *position += 1;
+            if totals[&key(child)] == 1 {
+                format!("{}/{}", path, child.qualified_name())
+            } else {
+                format!("{}/{}[{}]", path, child.qualified_name(), position)
+            }
+        })
+        .collect()
+}
+
+/// The pairs of indices making up a longest common subsequence of two sequences
+///
+/// This is Myers' O((n + m)d) algorithm in linear space, where d is the number of elements
+/// that aren't in the subsequence: each range is split at the middle of an optimal edit path,
+/// found by searching from both ends at once, after trimming the ends the sequences share.
+fn lcs<F>(n: usize, m: usize, eq: F) -> Vec<(usize, usize)>
+where
+    F: Fn(usize, usize) -> bool,
+{
+    let mut pairs = Vec::new();
+    let mut ranges = vec![(0, n, 0, m)];
+    while let Some((mut a_start, mut a_end, mut b_start, mut b_end)) = ranges.pop() {
+        while a_start < a_end && b_start < b_end && eq(a_start, b_start) {
+            pairs.push((a_start, b_start));
+            a_start += 1;
+            b_start += 1;
+        }
+        while a_start < a_end && b_start < b_end && eq(a_end - 1, b_end - 1) {
+            pairs.push((a_end - 1, b_end - 1));
+            a_end -= 1;
+            b_end -= 1;
+        }
+        if a_start == a_end || b_start == b_end {
+            continue;
+        }
+
+        let (a, b) = (a_end - a_start, b_end - b_start);
+        let eq = |x: usize, y: usize| eq(a_start + x, b_start + y);
+        if let Some((x, y)) = middle(a, b, eq) {
+            ranges.push((a_start, a_start + x, b_start, b_start + y));
+            ranges.push((a_start + x, a_end, b_start + y, b_end));
+        }
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+/// A point on an optimal edit path between sequences of lengths `n` and `m` that splits it in
+/// two, or `None` if they have nothing in common
+///
+/// The sequences must differ in their first and in their last elements.
+fn middle<F>(n: usize, m: usize, eq: F) -> Option<(usize, usize)>
+where
+    F: Fn(usize, usize) -> bool,
+{
+    let (n, m) = (n as isize, m as isize);
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    // forward[k] and backward[k] are the furthest x reached on diagonal k = x - y from the
+    // start and from the end
+    let mut forward = vec![-1; 2 * offset as usize + 1];
+    let mut backward = forward.clone();
+    forward[offset as usize + 1] = 0;
+    backward[offset as usize + 1] = 0;
+    let delta = n - m;
+    // With an odd delta, the paths meet while searching forwards
+    let odd = delta % 2 != 0;
+    let (mut k1_start, mut k1_end, mut k2_start, mut k2_end) = (0, 0, 0, 0);
+
+    for d in 0..max {
+        let mut k1 = -d + k1_start;
+        while k1 <= d - k1_end {
+            let i = (offset + k1) as usize;
+            let mut x = if k1 == -d || (k1 != d && forward[i - 1] < forward[i + 1]) {
+                forward[i + 1]
+            } else {
+                forward[i - 1] + 1
+            };
+            let mut y = x - k1;
+            while x < n && y < m && eq(x as usize, y as usize) {
+                x += 1;
+                y += 1;
+            }
+            forward[i] = x;
+            if x > n {
+                k1_end += 2;
+            } else if y > m {
+                k1_start += 2;
+            } else if odd {
+                let j = offset + delta - k1;
+                if j >= 0
+                    && (j as usize) < backward.len()
+                    && backward[j as usize] != -1
+                    && x >= n - backward[j as usize]
+                {
+                    return Some((x as usize, y as usize));
+                }
+            }
+            k1 += 2;
+        }
+
+        let mut k2 = -d + k2_start;
+        while k2 <= d - k2_end {
+            let i = (offset + k2) as usize;
+            let mut x = if k2 == -d || (k2 != d && backward[i - 1] < backward[i + 1]) {
+                backward[i + 1]
+            } else {
+                backward[i - 1] + 1
+            };
+            let mut y = x - k2;
+            while x < n && y < m && eq((n - x - 1) as usize, (m - y - 1) as usize) {
+                x += 1;
+                y += 1;
+            }
+            backward[i] = x;
+            if x > n {
+                k2_end += 2;
+            } else if y > m {
+                k2_start += 2;
+            } else if !odd {
+                let j = offset + delta - k2;
+                if j >= 0 && (j as usize) < forward.len() && forward[j as usize] != -1 {
+                    let forward_x = forward[j as usize];
+                    let forward_y = forward_x - (j - offset);
+                    if forward_x >= n - x {
+                        return Some((forward_x as usize, forward_y as usize));
+                    }
+                }
+            }
+            k2 += 2;
+        }
+    }
+    None
+}
@@ -1,16 +1,20 @@
 use std::fmt;
 use std::io::{Read, Write};
 
-use indexmap::IndexMap;
-
-use crate::{Element, ElementBuilder, TreexmlError, XmlVersion};
+use crate::writer::FmtWriter;
+use crate::{
+    Canonicalization, Element, ElementBuilder, InvalidChars, TreeWriter, TreexmlError,
+    WriteOptions, XmlVersion,
+};
 
 /// An XML document
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Document {
     /// Version of the XML document
     pub version: XmlVersion,
-    /// Encoding of the XML document
+    /// Encoding of the XML document, as declared by its source
+    ///
+    /// Documents are always written in UTF-8, and declared as such.
     pub encoding: String,
     /// Root tag of the XML document
     pub root: Option<Element>,
@@ -48,6 +52,7 @@ impl Document {
     ///
     /// Passes any errors that the `xml-rs` library returns up the stack
     pub fn parse<R: Read>(r: R) -> Result<Document, TreexmlError> {
+        use xml::namespace::Namespace;
         use xml::reader::{EventReader, XmlEvent};
 
         let mut reader = EventReader::new(r);
@@ -63,26 +68,18 @@ impl Document {
                     doc.encoding = encoding;
                 }
                 XmlEvent::StartElement {
-                    name, attributes, ..
+                    name,
+                    attributes,
+                    namespace,
                 } => {
                     // Start of the root element
-
-                    let mut attr_map = IndexMap::new();
-                    for attr in attributes {
-                        let attr_name = match attr.name.prefix {
-                            Some(prefix) => format!("{}:{}", prefix, attr.name.local_name),
-                            None => attr.name.local_name,
-                        };
-                        attr_map.insert(attr_name, attr.value);
-                    }
-
-                    let mut root = Element {
-                        prefix: name.prefix,
-                        name: name.local_name,
-                        attributes: attr_map,
-                        ..Element::default()
-                    };
-                    root.parse(&mut reader)?;
+                    let mut root = Element::from_start_event(
+                        name,
+                        attributes,
+                        &namespace,
+                        &Namespace::empty(),
+                    );
+                    root.parse(&mut reader, &namespace)?;
                     doc.root = Some(root);
                 }
                 XmlEvent::EndDocument => break,
@@ -93,6 +90,46 @@ impl Document {
         Ok(doc)
     }
 
+    /// Check the names and characters in the document against the productions of its version
+    pub fn validate(&self) -> Result<(), TreexmlError> {
+        match self.root {
+            Some(ref root) => root.validate(self.version),
+            None => Ok(()),
+        }
+    }
+
+    /// Check the document like `validate`, handling characters its version doesn't allow as
+    /// `chars` says to
+    pub fn validate_with(&mut self, chars: InvalidChars) -> Result<(), TreexmlError> {
+        match self.root {
+            Some(ref mut root) => root.validate_with(self.version, chars),
+            None => Ok(()),
+        }
+    }
+
+    /// The canonical form of the document under `method`, which is that of its root
+    ///
+    /// Fails where `Element::canonicalize` does. A parsed document has lost its whitespace-only
+    /// text, so this only gives the canonical form of documents built in code; use a
+    /// `Canonicalizer` on the source document to keep its comments, processing instructions
+    /// and whitespace.
+    pub fn canonicalize(&self, method: Canonicalization) -> Result<String, TreexmlError> {
+        match self.root {
+            Some(ref root) => root.canonicalize(method),
+            None => Ok(String::new()),
+        }
+    }
+
+    /// Write the canonical form of the document to `w`
+    pub fn write_canonical<W: Write>(
+        &self,
+        w: &mut W,
+        method: Canonicalization,
+    ) -> Result<(), TreexmlError> {
+        w.write_all(self.canonicalize(method)?.as_bytes())?;
+        Ok(())
+    }
+
     pub fn write<W: Write>(&self, mut w: &mut W) -> Result<(), TreexmlError> {
         self.write_with(&mut w, true, "  ", true)
     }
@@ -105,35 +142,60 @@ impl Document {
         indent_str: &'static str,
         indent: bool,
     ) -> Result<(), TreexmlError> {
-        use xml::writer::{EmitterConfig, XmlEvent};
-
-        let mut writer = EmitterConfig::new()
-            .perform_indent(indent)
-            .write_document_declaration(document_decl)
-            .indent_string(indent_str)
-            .create_writer(w);
-
-        if document_decl {
-            writer.write(XmlEvent::StartDocument {
-                version: self.version.into(),
-                encoding: Some(&self.encoding),
-                standalone: None,
-            })?;
-        }
+        self.write_with_options(
+            w,
+            WriteOptions::new()
+                .document_decl(document_decl)
+                .indent_string(indent_str)
+                .indent(indent),
+        )
+    }
+
+    /// Write the document to a string, formatted according to `options`
+    pub fn to_xml_string(&self, options: &WriteOptions) -> Result<String, TreexmlError> {
+        let mut s = String::new();
+        self.write_to_fmt(&mut s, options)?;
+        Ok(s)
+    }
 
+    /// Write the document to `f`, formatted according to `options`
+    pub fn write_to_fmt<F: fmt::Write + ?Sized>(
+        &self,
+        f: &mut F,
+        options: &WriteOptions,
+    ) -> Result<(), TreexmlError> {
+        self.write_with_options(&mut FmtWriter(f), options)
+    }
+
+    /// Writes a document to `w`, formatted according to `options`
+    pub fn write_with_options<W: Write>(
+        &self,
+        w: &mut W,
+        options: &WriteOptions,
+    ) -> Result<(), TreexmlError> {
+        let mut writer = TreeWriter::new(w, options);
+        writer.start_document(self.version, "UTF-8")?;
         if let Some(ref e) = self.root {
-            e.write(&mut writer)?;
+            writer.write_element(e)?;
         }
-
+        writer.finish()?;
         Ok(())
     }
 }
 
+/// Formats the document like `to_xml_string`, but writes invalid names as they are and drops
+/// characters its version doesn't allow rather than failing
+///
+/// The output may not be well-formed; use `to_xml_string` to have problems reported. Only
+/// fails if the formatter does.
 impl fmt::Display for Document {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut v = Vec::<u8>::new();
-        self.write(&mut v).unwrap();
-        let s = String::from_utf8(v).unwrap();
-        f.write_str(&s[..])
+        self.write_to_fmt(
+            f,
+            WriteOptions::new()
+                .check_names(false)
+                .strip_invalid_chars(true),
+        )
+        .map_err(|_| fmt::Error)
     }
 }
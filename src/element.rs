@@ -1,4 +1,3 @@
-use std::borrow::Cow;
 use std::fmt;
 use std::io::{Read, Write};
 use std::iter::Filter;
@@ -8,7 +7,19 @@ use std::string::ToString;
 
 use indexmap::IndexMap;
 
-use crate::{Document, TreexmlError};
+use crate::canonical::{self, Canonicalization};
+use crate::diff::{Diff, DiffOptions};
+use crate::path::Path;
+use crate::select::Selector;
+use crate::semantic::EqOptions;
+use crate::text::TextOptions;
+use crate::traverse::{BreadthFirst, Descendants, DescendantsMut, Traverse};
+use crate::validate::{self, InvalidChars};
+use crate::visit::{self, Transformer, Visitor};
+use crate::writer::FmtWriter;
+use crate::{
+    Children, TreeWriter, TreexmlError, WriteOptions, XPath, XPathContext, XPathNode, XmlVersion,
+};
 
 /// An XML element
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -19,8 +30,15 @@ pub struct Element {
     pub name: String,
     /// Tag attributes
     pub attributes: IndexMap<String, String>,
-    /// A vector of child elements
-    pub children: Vec<Element>,
+    /// Namespace bindings declared on the element, as recorded by the parser: prefix to URI,
+    /// with the default namespace under the empty prefix, and an empty URI where the default
+    /// namespace is unset
+    ///
+    /// Bindings inherited from ancestors aren't repeated here. `xmlns` attributes on elements
+    /// built in code declare namespaces too.
+    pub namespaces: IndexMap<String, String>,
+    /// Child elements, shared between clones until modified
+    pub children: Children,
     /// Contents of the element
     pub text: Option<String>,
     /// CDATA contents of the element
@@ -33,7 +51,8 @@ impl Default for Element {
             prefix: None,
             name: "tag".to_owned(),
             attributes: IndexMap::new(),
-            children: Vec::new(),
+            namespaces: IndexMap::new(),
+            children: Children::new(),
             text: None,
             cdata: None,
         }
@@ -52,10 +71,98 @@ impl Element {
         }
     }
 
+    /// Create a new `Element` with the tag name `name`, checking that it's a valid XML name
+    /// without a prefix
+    pub fn try_new<S>(name: S) -> Result<Element, TreexmlError>
+    where
+        S: ToString,
+    {
+        let name = name.to_string();
+        if validate::is_ncname(&name) {
+            Ok(Element::new(name))
+        } else {
+            Err(TreexmlError::InvalidName { name })
+        }
+    }
+
+    /// Create an element from the contents of a `StartElement` event, whose namespace bindings
+    /// are `namespace`, inside an element whose bindings are `parent`
+    pub(crate) fn from_start_event(
+        name: xml::name::OwnedName,
+        attributes: Vec<xml::attribute::OwnedAttribute>,
+        namespace: &xml::namespace::Namespace,
+        parent: &xml::namespace::Namespace,
+    ) -> Element {
+        use xml::namespace::{NS_XMLNS_PREFIX, NS_XML_PREFIX};
+
+        let mut attr_map = IndexMap::new();
+        for attr in attributes {
+            let attr_name = match attr.name.prefix {
+                Some(prefix) => format!("{}:{}", prefix, attr.name.local_name),
+                None => attr.name.local_name,
+            };
+            attr_map.insert(attr_name, attr.value);
+        }
+
+        // Keep the bindings the element declares, skipping the implicit `xml` and `xmlns` ones
+        // and the unset default namespace
+        let namespaces = namespace
+            .0
+            .iter()
+            .filter(|&(prefix, uri)| {
+                let inherited = parent.get(prefix).unwrap_or("");
+                prefix != NS_XML_PREFIX && prefix != NS_XMLNS_PREFIX && uri != inherited
+            })
+            .map(|(prefix, uri)| (prefix.clone(), uri.clone()))
+            .collect();
+
+        Element {
+            prefix: name.prefix,
+            name: name.local_name,
+            attributes: attr_map,
+            namespaces,
+            ..Element::default()
+        }
+    }
+
+    /// The namespace bindings the current `Element` declares, through `xmlns` attributes and
+    /// then `namespaces`, with the default namespace under `""`
+    pub(crate) fn declared_namespaces(&self) -> impl Iterator<Item = (&str, &str)> {
+        let attributes = self.attributes.iter().filter_map(|(name, uri)| {
+            let prefix = if name == "xmlns" {
+                ""
+            } else {
+                name.strip_prefix("xmlns:")?
+            };
+            Some((prefix, uri.as_str()))
+        });
+        let namespaces = self
+            .namespaces
+            .iter()
+            .map(|(prefix, uri)| (prefix.as_str(), uri.as_str()));
+        attributes.chain(namespaces)
+    }
+
+    /// The namespace URI the current `Element` binds `prefix` to, through `namespaces` or an
+    /// `xmlns` attribute, with the default namespace under `""`
+    ///
+    /// An empty URI means the element unsets the default namespace.
+    pub(crate) fn declared_namespace(&self, prefix: &str) -> Option<&str> {
+        let attribute = if prefix.is_empty() {
+            self.attributes.get("xmlns")
+        } else {
+            self.attributes.get(&format!("xmlns:{}", prefix))
+        };
+        attribute
+            .or_else(|| self.namespaces.get(prefix))
+            .map(String::as_str)
+    }
+
     /// Parse the contents of an element
     pub(crate) fn parse<R: Read>(
         &mut self,
-        mut reader: &mut xml::reader::EventReader<R>,
+        reader: &mut xml::reader::EventReader<R>,
+        scope: &xml::namespace::Namespace,
     ) -> Result<(), TreexmlError> {
         use xml::reader::XmlEvent;
 
@@ -63,24 +170,12 @@ impl Element {
             let ev = reader.next()?;
             match ev {
                 XmlEvent::StartElement {
-                    name, attributes, ..
+                    name,
+                    attributes,
+                    namespace,
                 } => {
-                    let mut attr_map = IndexMap::new();
-                    for attr in attributes {
-                        let attr_name = match attr.name.prefix {
-                            Some(prefix) => format!("{}:{}", prefix, attr.name.local_name),
-                            None => attr.name.local_name,
-                        };
-                        attr_map.insert(attr_name, attr.value);
-                    }
-
-                    let mut child = Element {
-                        prefix: name.prefix,
-                        name: name.local_name,
-                        attributes: attr_map,
-                        ..Element::default()
-                    };
-                    child.parse(&mut reader)?;
+                    let mut child = Element::from_start_event(name, attributes, &namespace, scope);
+                    child.parse(reader, &namespace)?;
                     self.children.push(child);
                 }
                 XmlEvent::EndElement { name } => {
@@ -115,45 +210,45 @@ impl Element {
     }
 
     /// Write an element and its contents to `writer`
-    pub(crate) fn write<W: Write>(
-        &self,
-        writer: &mut xml::writer::EventWriter<W>,
-    ) -> Result<(), TreexmlError> {
-        use xml::attribute::Attribute;
-        use xml::name::Name;
-        use xml::namespace::Namespace;
-        use xml::writer::XmlEvent;
-
-        let name = Name::local(&self.name);
-        let mut attributes = Vec::with_capacity(self.attributes.len());
-        for (k, v) in &self.attributes {
-            attributes.push(Attribute {
-                name: Name::local(k),
-                value: v,
-            });
-        }
-
-        let namespace = Namespace::empty();
-
-        writer.write(XmlEvent::StartElement {
-            name,
-            attributes: Cow::Owned(attributes),
-            namespace: Cow::Owned(namespace),
-        })?;
-
-        if let Some(ref text) = self.text {
-            writer.write(XmlEvent::Characters(&text[..]))?;
-        }
-        if let Some(ref cdata) = self.cdata {
-            writer.write(XmlEvent::CData(&cdata[..]))?;
+    pub(crate) fn write<W: Write>(&self, writer: &mut TreeWriter<W>) -> Result<(), TreexmlError> {
+        enum Step<'e> {
+            Start(&'e Element),
+            End,
         }
 
-        for e in &self.children {
-            e.write(writer)?;
+        let mut pending = vec![Step::Start(self)];
+        while let Some(step) = pending.pop() {
+            match step {
+                Step::Start(element) => {
+                    writer.write_start(element)?;
+                    pending.push(Step::End);
+                    let children = writer.child_order(element);
+                    pending.extend(children.into_iter().rev().map(Step::Start));
+                }
+                Step::End => writer.end_element()?,
+            }
         }
+        Ok(())
+    }
 
-        writer.write(XmlEvent::EndElement { name: Some(name) })?;
+    /// Write the current `Element` and its descendants to a string, formatted according to
+    /// `options`
+    pub fn to_xml_string(&self, options: &WriteOptions) -> Result<String, TreexmlError> {
+        let mut s = String::new();
+        self.write_to_fmt(&mut s, options)?;
+        Ok(s)
+    }
 
+    /// Write the current `Element` and its descendants to `f`, formatted according to
+    /// `options`
+    pub fn write_to_fmt<F: fmt::Write + ?Sized>(
+        &self,
+        f: &mut F,
+        options: &WriteOptions,
+    ) -> Result<(), TreexmlError> {
+        let mut writer = TreeWriter::new(FmtWriter(f), options);
+        writer.write_element(self)?;
+        writer.finish()?;
         Ok(())
     }
 
@@ -173,42 +268,108 @@ impl Element {
         self.children.iter_mut().find(predicate)
     }
 
-    /// Traverse element using an xpath-like string: root/child/a
+    /// Find the first element matched by `path`, relative to this one
+    ///
+    /// Steps are separated by `/`, or by `//` to search all descendants. Each step is a tag
+    /// name, `prefix:name` or `*`, optionally followed by predicates such as `[2]`, `[@name]`
+    /// or `[@name='db']`. A final `@name` step only matches elements with that attribute.
+    /// Use a compiled `Path` to repeat a lookup without parsing it each time.
     pub fn find(&self, path: &str) -> Result<&Element, TreexmlError> {
-        Self::find_path(&path.split('/').collect::<Vec<&str>>(), path, self)
+        Path::compile(path)?.find(self)
     }
 
+    /// Find the first element matched by `path`; returns a mutable borrow
+    pub fn find_mut(&mut self, path: &str) -> Result<&mut Element, TreexmlError> {
+        Path::compile(path)?.find_mut(self)
+    }
+
+    /// Find the first element matched by `path`, creating it and any missing ancestors if
+    /// there is none
+    ///
+    /// Created steps must name a single element: they can't be `*` or use `//`, and may only
+    /// use `[@name='value']` predicates, which set the attribute on the new element, or an
+    /// `[n]` predicate naming the element that would be appended. A final `@name` step is
+    /// ignored.
+    pub fn ensure_path(&mut self, path: &str) -> Result<&mut Element, TreexmlError> {
+        Path::compile(path)?.ensure(self)
+    }
+
+    /// Set the text of the element at `path`, or the attribute if the path ends in `@name`,
+    /// creating the element as `ensure_path` would
+    pub fn set_value<V: ToString>(&mut self, path: &str, value: V) -> Result<(), TreexmlError> {
+        Path::compile(path)?.set_value(self, value)
+    }
+
+    /// Find the first element matched by `path`, and parse its text as a `T`, or its attribute
+    /// if the path ends in `@name`
     pub fn find_value<T: FromStr>(&self, path: &str) -> Result<Option<T>, TreexmlError> {
-        let el = self.find(path)?;
-        if let Some(text) = el.text.as_ref() {
-            match T::from_str(text) {
-                Err(_) => Err(TreexmlError::ValueFromStr {
-                    t: text.to_string(),
-                }),
-                Ok(value) => Ok(Some(value)),
-            }
-        } else {
-            Ok(None)
-        }
+        Path::compile(path)?.find_value(self)
     }
 
-    fn find_path<'a>(
-        path: &[&str],
-        original: &str,
-        tree: &'a Element,
-    ) -> Result<&'a Element, TreexmlError> {
-        if path.is_empty() {
-            return Ok(tree);
-        }
+    /// Find every element matched by `path`, in document order
+    pub fn find_all(&self, path: &str) -> Result<impl Iterator<Item = &Element>, TreexmlError> {
+        Ok(Path::compile(path)?.find_all(self))
+    }
+
+    /// Find every element matched by `path`; returns mutable borrows
+    ///
+    /// Matches nested inside another match are skipped, since they're reachable through it.
+    pub fn find_all_mut(
+        &mut self,
+        path: &str,
+    ) -> Result<impl Iterator<Item = &mut Element>, TreexmlError> {
+        Ok(Path::compile(path)?.find_all_mut(self))
+    }
+
+    /// Find every element matched by `path`, and parse the text of those that have any, or
+    /// their attribute if the path ends in `@name`
+    pub fn find_values<T: FromStr>(&self, path: &str) -> Result<Vec<T>, TreexmlError> {
+        Path::compile(path)?.find_values(self)
+    }
 
-        match tree.find_child(|t| t.name == path[0]) {
-            Some(element) => Self::find_path(&path[1..], original, element),
-            None => Err(TreexmlError::ElementNotFound { t: original.into() }),
+    /// Count the elements matched by `path`
+    pub fn count(&self, path: &str) -> Result<usize, TreexmlError> {
+        Ok(Path::compile(path)?.count(self))
+    }
+
+    /// Parse the attribute `name` as a `T`, if it's present
+    ///
+    /// Errors give the element's path as `/name`, since it doesn't know its ancestors.
+    pub fn attr<T: FromStr>(&self, name: &str) -> Result<Option<T>, TreexmlError> {
+        self.attributes
+            .get(name)
+            .map(|value| parse_attribute(format!("/{}", self.qualified_name()), name, value))
+            .transpose()
+    }
+
+    /// Parse the attribute `name` as a `T`
+    ///
+    /// # Failures
+    ///
+    /// Returns `TreexmlError::AttributeNotFound` if the attribute is missing
+    pub fn attr_required<T: FromStr>(&self, name: &str) -> Result<T, TreexmlError> {
+        self.attr(name)?
+            .ok_or_else(|| TreexmlError::AttributeNotFound {
+                path: format!("/{}", self.qualified_name()),
+                attr: name.to_owned(),
+            })
+    }
+
+    /// The tag name, including its prefix if it has one
+    pub(crate) fn qualified_name(&self) -> String {
+        match self.prefix {
+            Some(ref prefix) => format!("{}:{}", prefix, self.name),
+            None => self.name.clone(),
         }
     }
 
+    /// Select nodes using an XPath 1.0 expression, with this `Element` as the context node
+    pub fn xpath(&self, expr: &str) -> Result<Vec<XPathNode<'_>>, TreexmlError> {
+        XPath::compile(expr)?.select(self, &XPathContext::new())
+    }
+
     /// Filters the children of the current `Element`, given a predicate
-    pub fn filter_children<P>(&self, predicate: P) -> Filter<Iter<Element>, P>
+    pub fn filter_children<P>(&self, predicate: P) -> Filter<Iter<'_, Element>, P>
     where
         P: for<'r> Fn(&'r &Element) -> bool,
     {
@@ -216,23 +377,227 @@ impl Element {
     }
 
     /// Filters the children of the current `Element`, given a predicate; returns a mutable iterator
-    pub fn filter_children_mut<P>(&mut self, predicate: P) -> Filter<IterMut<Element>, P>
+    pub fn filter_children_mut<P>(&mut self, predicate: P) -> Filter<IterMut<'_, Element>, P>
     where
         P: for<'r> FnMut(&'r &mut Element) -> bool,
     {
         self.children.iter_mut().filter(predicate)
     }
+
+    /// The text and CDATA of the current `Element` and its descendants, joined in document order
+    ///
+    /// The text of an element is taken to come before its children, since the tree doesn't
+    /// record where between them it appeared.
+    pub fn text_content(&self) -> String {
+        TextOptions::new().text_content(self)
+    }
+
+    /// The text content of the current `Element`, joined according to `options`
+    pub fn text_content_with(&self, options: &TextOptions) -> String {
+        options.text_content(self)
+    }
+
+    /// Check the names and characters of the current `Element` and its descendants against
+    /// the productions of XML `version`
+    ///
+    /// Every problem found is reported in the `InvalidXml` error, along with its path.
+    pub fn validate(&self, version: XmlVersion) -> Result<(), TreexmlError> {
+        let problems = validate::problems(self, version, InvalidChars::Report);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(TreexmlError::InvalidXml { problems })
+        }
+    }
+
+    /// Check the current `Element` and its descendants like `validate`, handling characters
+    /// `version` doesn't allow as `chars` says to
+    pub fn validate_with(
+        &mut self,
+        version: XmlVersion,
+        chars: InvalidChars,
+    ) -> Result<(), TreexmlError> {
+        if chars == InvalidChars::Strip {
+            validate::strip(self, version);
+        }
+        let problems = validate::problems(self, version, chars);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(TreexmlError::InvalidXml { problems })
+        }
+    }
+
+    /// The canonical form of the current `Element` and its descendants under `method`, as the
+    /// root of its own document
+    ///
+    /// This is only meant for trees built in code. Parsing drops whitespace-only text, such as
+    /// the indentation of pretty-printed documents, and it can't be told apart from text that
+    /// was never there, so a parsed tree gives output that differs from the canonical form of
+    /// its source without failing. To digest parsed XML, such as a signed SAML assertion, use
+    /// `Canonicalizer::subtree` on the source document.
+    ///
+    /// # Failures
+    ///
+    /// A tree has no comments and doesn't keep the order of text and children, so methods with
+    /// comments, elements with mixed content and unbound prefixes are errors; a
+    /// `Canonicalizer` handles those from the source document.
+    pub fn canonicalize(&self, method: Canonicalization) -> Result<String, TreexmlError> {
+        canonical::canonicalize(self, method, &[])
+    }
+
+    /// Write the canonical form of the current `Element` to `w`
+    pub fn write_canonical<W: Write>(
+        &self,
+        w: &mut W,
+        method: Canonicalization,
+    ) -> Result<(), TreexmlError> {
+        w.write_all(self.canonicalize(method)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// The Exclusive XML Canonicalization form of the current `Element` and its descendants
+    ///
+    /// Only namespace declarations used by the name of an element or its attributes are
+    /// written, along with those for `prefixes`, the `InclusiveNamespaces` prefix list, where
+    /// `#default` stands for the default namespace.
+    ///
+    /// Like `canonicalize`, this is only meant for trees built in code, and fails in the same
+    /// cases. Parsed subtrees have lost their whitespace-only text; use `Canonicalizer::subtree`
+    /// with `Canonicalization::ExcC14n` on the source document for those.
+    pub fn canonicalize_exclusive(&self, prefixes: &[&str]) -> Result<String, TreexmlError> {
+        canonical::canonicalize(self, Canonicalization::ExcC14n, prefixes)
+    }
+
+    /// Write the Exclusive XML Canonicalization form of the current `Element` to `w`
+    pub fn write_canonical_exclusive<W: Write>(
+        &self,
+        w: &mut W,
+        prefixes: &[&str],
+    ) -> Result<(), TreexmlError> {
+        w.write_all(self.canonicalize_exclusive(prefixes)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Iterate over the descendants of the current `Element`, depth-first in document order
+    pub fn descendants(&self) -> Descendants<'_> {
+        Descendants::new(self)
+    }
+
+    /// Iterate over the descendants of the current `Element`, depth-first in document order;
+    /// yields mutable borrows of everything but their children
+    pub fn descendants_mut(&mut self) -> DescendantsMut<'_> {
+        DescendantsMut::new(self)
+    }
+
+    /// Iterate over the descendants of the current `Element`, breadth-first
+    pub fn breadth_first(&self) -> BreadthFirst<'_> {
+        BreadthFirst::new(self)
+    }
+
+    /// Walk the current `Element` and its descendants, yielding an event on entering and on
+    /// leaving each one
+    pub fn traverse(&self) -> Traverse<'_> {
+        Traverse::new(self)
+    }
+
+    /// Compare the current `Element` with `other`, listing the edits that turn it into `other`
+    pub fn diff(&self, other: &Element) -> Diff {
+        Diff::new(self, other, &DiffOptions::new())
+    }
+
+    /// Compare the current `Element` with `other`, ignoring what `options` says to
+    pub fn diff_with(&self, other: &Element, options: &DiffOptions) -> Diff {
+        Diff::new(self, other, options)
+    }
+
+    /// Compare the current `Element` with `other`, ignoring the differences `options` says
+    /// are insignificant
+    ///
+    /// Attribute order is always ignored, as it is by `==`. Namespace bindings are only
+    /// compared through the names that use them.
+    pub fn semantically_eq(&self, other: &Element, options: &EqOptions) -> bool {
+        options.eq(self, other)
+    }
+
+    /// Walk the current `Element` and its descendants with a `Visitor`, in document order
+    pub fn visit<'a, V: Visitor<'a>>(&'a self, visitor: &mut V) {
+        visit::visit(self, visitor)
+    }
+
+    /// Rewrite the contents of the current `Element` with a `Transformer`: its text and CDATA,
+    /// and its descendants in document order
+    pub fn transform<T: Transformer>(&mut self, transformer: &mut T) {
+        visit::transform(self, transformer)
+    }
+
+    /// Find the descendants matching a CSS selector, in document order
+    ///
+    /// Type, `#id`, `.class` and attribute selectors are supported, as are all four
+    /// combinators, `:first-child`, `:last-child`, `:only-child`, `:nth-child()` and
+    /// `:nth-last-child()`. Type selectors may use `*|name` and `|name` to match elements in
+    /// any namespace or in none; `ns|name` needs `select_with` to declare `ns`.
+    pub fn select(&self, selector: &str) -> Result<Vec<&Element>, TreexmlError> {
+        self.select_with(selector, &[])
+    }
+
+    /// Find the first descendant matching a CSS selector
+    pub fn select_first(&self, selector: &str) -> Result<Option<&Element>, TreexmlError> {
+        self.select_first_with(selector, &[])
+    }
+
+    /// Find the descendants matching a CSS selector like `select`, with `namespaces` mapping
+    /// the prefixes it uses to namespace URIs, and `""` to the default namespace
+    ///
+    /// Elements match `ns|name` when their own prefix is bound to the same URI, whatever that
+    /// prefix is.
+    pub fn select_with(
+        &self,
+        selector: &str,
+        namespaces: &[(&str, &str)],
+    ) -> Result<Vec<&Element>, TreexmlError> {
+        Ok(Selector::parse(selector, namespaces)?
+            .select(self)
+            .collect())
+    }
+
+    /// Find the first descendant matching a CSS selector like `select_with`
+    pub fn select_first_with(
+        &self,
+        selector: &str,
+        namespaces: &[(&str, &str)],
+    ) -> Result<Option<&Element>, TreexmlError> {
+        Ok(Selector::parse(selector, namespaces)?.select(self).next())
+    }
 }
 
+pub(crate) fn parse_attribute<T: FromStr>(
+    path: String,
+    attr: &str,
+    value: &str,
+) -> Result<T, TreexmlError> {
+    T::from_str(value).map_err(|_| TreexmlError::AttributeFromStr {
+        path,
+        attr: attr.to_owned(),
+        value: value.to_owned(),
+        target: std::any::type_name::<T>(),
+    })
+}
+
+/// Formats the element like `to_xml_string` without a document declaration, but writes
+/// invalid names as they are and drops characters XML doesn't allow rather than failing
+///
+/// The output may not be well-formed; use `to_xml_string` to have problems reported. Only
+/// fails if the formatter does.
 impl fmt::Display for Element {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let doc = Document {
-            root: Some(self.clone()),
-            ..Document::default()
-        };
-        let mut v = Vec::<u8>::new();
-        doc.write_with(&mut v, false, "  ", true).unwrap();
-        let s = String::from_utf8(v).unwrap();
-        f.write_str(&s[..])
+        self.write_to_fmt(
+            f,
+            WriteOptions::new()
+                .document_decl(false)
+                .check_names(false)
+                .strip_invalid_chars(true),
+        )
+        .map_err(|_| fmt::Error)
     }
 }
@@ -1,17 +1,78 @@
 use thiserror::Error;
-use xml;
+
+use crate::Problem;
 
 #[derive(Debug, Error)]
 pub enum TreexmlError {
     #[error("Element not found: '{t}'")]
     ElementNotFound { t: String },
-    #[error("Value could not be parsed: '{t}'")]
-    ValueFromStr { t: String },
+    #[error("Text of '{path}' could not be parsed as {target}: '{t}'")]
+    ValueFromStr {
+        path: String,
+        t: String,
+        target: &'static str,
+    },
+    #[error("Attribute not found: '{attr}' on '{path}'")]
+    AttributeNotFound { path: String, attr: String },
+    #[error("Attribute '{attr}' on '{path}' could not be parsed as {target}: '{value}'")]
+    AttributeFromStr {
+        path: String,
+        attr: String,
+        value: String,
+        target: &'static str,
+    },
+    #[error("Path syntax error at offset {pos} in '{path}': {msg}")]
+    PathSyntax {
+        path: String,
+        pos: usize,
+        msg: String,
+    },
+    #[error("Path can't be created: '{path}': {msg}")]
+    PathNotCreatable { path: String, msg: String },
+    #[error("Selector syntax error at offset {pos} in '{selector}': {msg}")]
+    SelectorSyntax {
+        selector: String,
+        pos: usize,
+        msg: String,
+    },
+    #[error("XPath syntax error at offset {pos} in '{expr}': {msg}")]
+    XPathSyntax {
+        expr: String,
+        pos: usize,
+        msg: String,
+    },
+    #[error("XPath evaluation error in '{expr}': {msg}")]
+    XPathEvaluation { expr: String, msg: String },
+    #[error("Invalid patch: {msg}")]
+    InvalidPatch { msg: String },
+    #[error("Patch operation on '{sel}' failed: {msg}")]
+    PatchFailed { sel: String, msg: String },
+    #[error("Merge conflict at '{path}': {msg}")]
+    MergeConflict { path: String, msg: String },
+    #[error("Can't canonicalize: {msg}")]
+    Canonicalization { msg: String },
+    #[error("Invalid XML name: '{name}'")]
+    InvalidName { name: String },
+    #[error("Name can't be written as ASCII: '{name}'")]
+    NonAsciiName { name: String },
+    #[error("Invalid XML: {}", .problems.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+    InvalidXml { problems: Vec<Problem> },
+    #[error("No open element to close")]
+    NoOpenElement,
+    #[error("Document already has a root element")]
+    MultipleRoots,
+    #[error("Unsupported encoding: '{encoding}', output is always UTF-8")]
+    UnsupportedEncoding { encoding: String },
     #[error("Parse error: '{source}'")]
     ParseError {
         #[from]
         source: xml::reader::Error,
     },
+    #[error("IO error: '{source}'")]
+    IoError {
+        #[from]
+        source: std::io::Error,
+    },
     #[error("Write error: '{source}'")]
     WriteError {
         #[from]
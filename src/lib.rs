@@ -7,8 +7,7 @@
 //! ```
 //! use treexml::Document;
 //!
-//! let doc_raw = r#"
-//! <?xml version="1.1" encoding="UTF-8"?>
+//! let doc_raw = r#"<?xml version="1.1" encoding="UTF-8"?>
 //! <table>
 //!     <fruit type="apple">worm</fruit>
 //!     <vegetable />
@@ -43,13 +42,45 @@
 //!
 
 mod builder;
+mod canonical;
+mod children;
+mod diff;
 mod document;
 mod element;
 mod errors;
+mod merge;
+mod patch;
+mod path;
+mod scope;
+mod select;
+mod semantic;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod text;
+mod traverse;
+mod validate;
 mod version;
+mod visit;
+mod writer;
+mod xpath;
 
 pub use builder::*;
+pub use canonical::{Canonicalization, Canonicalizer};
+pub use children::Children;
+pub use diff::{Diff, DiffOptions, Edit};
 pub use document::Document;
 pub use element::Element;
 pub use errors::TreexmlError;
+pub use merge::{ChildMode, Conflict, MatchBy, MergeRules, Merged};
+pub use patch::Patch;
+pub use path::Path;
+pub use semantic::EqOptions;
+pub use text::TextOptions;
+pub use traverse::{
+    BreadthFirst, Descendants, DescendantsMut, ElementMut, Traverse, TraverseEvent,
+};
+pub use validate::{InvalidChars, Problem, ProblemKind};
 pub use version::XmlVersion;
+pub use visit::{Transform, Transformer, VisitControl, Visitor};
+pub use writer::{AttributeWrap, EscapeOptions, SortKey, TreeWriter, WriteOptions};
+pub use xpath::{XPath, XPathContext, XPathNode, XPathValue};
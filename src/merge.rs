@@ -0,0 +1,440 @@
+//! Merging of layered element trees, such as configuration files
+//!
+//! Layers are merged in order, each one on top of the result so far. The roots are always
+//! merged with each other; whether their descendants are depends on the rules in effect for
+//! each element. Rules are given for the elements a `Path` matches from the root of either
+//! tree, with later rules taking precedence, and fall back to the defaults.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::diff::child_path;
+use crate::{Element, Path, TreexmlError};
+
+/// How children of a later layer are matched with those of the earlier ones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MatchBy {
+    /// Match the first child with the same name
+    Name,
+    /// Match the child with the same name and the same value for the attribute
+    Attribute(String),
+    /// Match the child with the same name at the same position among its namesakes
+    Position,
+}
+
+/// What happens to the children of matched elements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildMode {
+    /// Merge matched children recursively, and append unmatched ones
+    Merge,
+    /// Replace the children with those of the later layer, even if it has none
+    Replace,
+    /// Append the children of the later layer
+    Append,
+}
+
+/// How attributes, text and CDATA set in more than one layer resolve
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conflict {
+    /// Take the value from the later layer
+    Override,
+    /// Keep the value from the earlier layer
+    Keep,
+    /// Fail with `TreexmlError::MergeConflict` if the values differ
+    Error,
+}
+
+/// Rules deciding how `MergeRules::merge` combines layers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeRules {
+    match_by: MatchBy,
+    children: ChildMode,
+    conflicts: Conflict,
+    rules: Vec<(Path, Setting)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Setting {
+    MatchBy(MatchBy),
+    Children(ChildMode),
+    Conflicts(Conflict),
+}
+
+impl Default for MergeRules {
+    fn default() -> Self {
+        MergeRules {
+            match_by: MatchBy::Name,
+            children: ChildMode::Merge,
+            conflicts: Conflict::Override,
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// The result of a merge, recording which layer each part of it came from
+///
+/// Layers are numbered from 0, the base.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merged {
+    root: Element,
+    origins: HashMap<Vec<usize>, Origins>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Origins {
+    element: usize,
+    attributes: HashMap<String, usize>,
+    text: Option<usize>,
+    cdata: Option<usize>,
+}
+
+impl MergeRules {
+    /// Create rules that match children by name, merge them recursively, and let later layers
+    /// override earlier ones
+    pub fn new() -> MergeRules {
+        MergeRules::default()
+    }
+
+    /// Set how children are matched by default
+    pub fn default_match_by(&mut self, match_by: MatchBy) -> &mut Self {
+        self.match_by = match_by;
+        self
+    }
+
+    /// Set what happens to children by default
+    pub fn default_children(&mut self, mode: ChildMode) -> &mut Self {
+        self.children = mode;
+        self
+    }
+
+    /// Set how conflicting values resolve by default
+    pub fn default_conflicts(&mut self, conflicts: Conflict) -> &mut Self {
+        self.conflicts = conflicts;
+        self
+    }
+
+    /// Set how the children of the elements `path` matches are matched
+    pub fn match_by(&mut self, path: Path, match_by: MatchBy) -> &mut Self {
+        self.rules.push((path, Setting::MatchBy(match_by)));
+        self
+    }
+
+    /// Set what happens to the children of the elements `path` matches
+    pub fn children(&mut self, path: Path, mode: ChildMode) -> &mut Self {
+        self.rules.push((path, Setting::Children(mode)));
+        self
+    }
+
+    /// Set how conflicting values on the elements `path` matches resolve
+    pub fn conflicts(&mut self, path: Path, conflicts: Conflict) -> &mut Self {
+        self.rules.push((path, Setting::Conflicts(conflicts)));
+        self
+    }
+
+    /// Merge `layers` on top of `base`, in order
+    ///
+    /// # Failures
+    ///
+    /// Returns `TreexmlError::MergeConflict` if values conflict where `Conflict::Error` is in
+    /// effect
+    pub fn merge(&self, base: &Element, layers: &[&Element]) -> Result<Merged, TreexmlError> {
+        let mut merged = Merged {
+            root: base.clone(),
+            origins: HashMap::new(),
+        };
+        record(&mut merged.origins, Vec::new(), base, 0);
+
+        for (n, layer) in layers.iter().enumerate() {
+            let mut merger = Merger {
+                rules: self,
+                base_matches: self.matches(&merged.root),
+                layer_matches: self.matches(layer),
+                layer: n + 1,
+                origins: &mut merged.origins,
+            };
+            let path = format!("/{}", merged.root.qualified_name());
+            merger.merge(&mut merged.root, Vec::new(), layer, Vec::new(), &path)?;
+        }
+
+        Ok(merged)
+    }
+
+    /// The locations of the elements each rule matches in `root`
+    fn matches(&self, root: &Element) -> Vec<HashSet<Vec<usize>>> {
+        self.rules
+            .iter()
+            .map(|(path, _)| {
+                path.locate(root)
+                    .into_iter()
+                    .map(|(location, _)| location)
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl Merged {
+    /// The merged tree
+    pub fn root(&self) -> &Element {
+        &self.root
+    }
+
+    /// Take the merged tree, discarding where it came from
+    pub fn into_root(self) -> Element {
+        self.root
+    }
+
+    /// The layer that added the first element `path` matches, or the layer that set its
+    /// attribute if the path ends in `@name`
+    pub fn origin(&self, path: &str) -> Result<Option<usize>, TreexmlError> {
+        let path = Path::compile(path)?;
+        Ok(self
+            .origins_of(&path)
+            .and_then(|origins| match path.attribute() {
+                Some(name) => origins.attributes.get(name).copied(),
+                None => Some(origins.element),
+            }))
+    }
+
+    /// The layer that set the text of the first element `path` matches
+    pub fn text_origin(&self, path: &str) -> Result<Option<usize>, TreexmlError> {
+        let path = Path::compile(path)?;
+        Ok(self.origins_of(&path).and_then(|origins| origins.text))
+    }
+
+    /// The layer that set the CDATA of the first element `path` matches
+    pub fn cdata_origin(&self, path: &str) -> Result<Option<usize>, TreexmlError> {
+        let path = Path::compile(path)?;
+        Ok(self.origins_of(&path).and_then(|origins| origins.cdata))
+    }
+
+    fn origins_of(&self, path: &Path) -> Option<&Origins> {
+        let (location, _) = path.locate(&self.root).into_iter().next()?;
+        self.origins.get(&location)
+    }
+}
+
+/// Record `layer` as the origin of everything in `element`, found at `location`
+fn record(
+    origins: &mut HashMap<Vec<usize>, Origins>,
+    location: Vec<usize>,
+    element: &Element,
+    layer: usize,
+) {
+    let mut stack = vec![(location, element)];
+    while let Some((location, el)) = stack.pop() {
+        for (index, child) in el.children.iter().enumerate() {
+            let mut child_location = location.clone();
+            child_location.push(index);
+            stack.push((child_location, child));
+        }
+        origins.insert(
+            location,
+            Origins {
+                element: layer,
+                attributes: el.attributes.keys().map(|k| (k.clone(), layer)).collect(),
+                text: el.text.as_ref().map(|_| layer),
+                cdata: el.cdata.as_ref().map(|_| layer),
+            },
+        );
+    }
+}
+
+struct Merger<'a> {
+    rules: &'a MergeRules,
+    base_matches: Vec<HashSet<Vec<usize>>>,
+    layer_matches: Vec<HashSet<Vec<usize>>>,
+    layer: usize,
+    origins: &'a mut HashMap<Vec<usize>, Origins>,
+}
+
+impl<'a> Merger<'a> {
+    /// The setting in effect for an element, by its location in each tree
+    fn setting<T, F>(&self, base: &[usize], layer: &[usize], pick: F, default: T) -> T
+    where
+        F: Fn(&Setting) -> Option<T>,
+    {
+        self.rules
+            .rules
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|&(i, _)| {
+                self.base_matches[i].contains(base) || self.layer_matches[i].contains(layer)
+            })
+            .find_map(|(_, (_, setting))| pick(setting))
+            .unwrap_or(default)
+    }
+
+    /// Resolve a conflict between two values, returning whether to take the new one
+    fn resolve(
+        conflicts: Conflict,
+        old: &str,
+        new: &str,
+        path: &str,
+        what: &str,
+    ) -> Result<bool, TreexmlError> {
+        match conflicts {
+            Conflict::Override => Ok(true),
+            Conflict::Keep => Ok(false),
+            Conflict::Error if old == new => Ok(true),
+            Conflict::Error => Err(TreexmlError::MergeConflict {
+                path: path.to_owned(),
+                msg: format!(
+                    "{} is {:?} in one layer and {:?} in another",
+                    what, old, new
+                ),
+            }),
+        }
+    }
+
+    fn merge(
+        &mut self,
+        base: &mut Element,
+        base_location: Vec<usize>,
+        layer: &Element,
+        layer_location: Vec<usize>,
+        path: &str,
+    ) -> Result<(), TreexmlError> {
+        let conflicts = self.setting(
+            &base_location,
+            &layer_location,
+            |s| match *s {
+                Setting::Conflicts(c) => Some(c),
+                _ => None,
+            },
+            self.rules.conflicts,
+        );
+        let origins = self.origins.get_mut(&base_location).unwrap();
+
+        for (name, value) in &layer.attributes {
+            let take = match base.attributes.get(name) {
+                Some(old) => Merger::resolve(
+                    conflicts,
+                    old,
+                    value,
+                    path,
+                    &format!("attribute '{}'", name),
+                )?,
+                None => true,
+            };
+            if take {
+                base.attributes.insert(name.clone(), value.clone());
+                origins.attributes.insert(name.clone(), self.layer);
+            }
+        }
+
+        for (field, new, origin, what) in [
+            (&mut base.text, &layer.text, &mut origins.text, "text"),
+            (&mut base.cdata, &layer.cdata, &mut origins.cdata, "CDATA"),
+        ] {
+            if let Some(new) = new {
+                let take = match field {
+                    Some(old) => Merger::resolve(conflicts, old, new, path, what)?,
+                    None => true,
+                };
+                if take {
+                    *field = Some(new.clone());
+                    *origin = Some(self.layer);
+                }
+            }
+        }
+
+        let mode = self.setting(
+            &base_location,
+            &layer_location,
+            |s| match *s {
+                Setting::Children(mode) => Some(mode),
+                _ => None,
+            },
+            self.rules.children,
+        );
+        match mode {
+            ChildMode::Replace => {
+                self.origins.retain(|location, _| {
+                    location.len() <= base_location.len() || !location.starts_with(&base_location)
+                });
+                base.children = layer.children.clone();
+                self.record_children(base, &base_location, 0);
+            }
+            ChildMode::Append => {
+                let start = base.children.len();
+                base.children.extend(layer.children.iter().cloned());
+                self.record_children(base, &base_location, start);
+            }
+            ChildMode::Merge => {
+                let match_by = self.setting(
+                    &base_location,
+                    &layer_location,
+                    |s| match *s {
+                        Setting::MatchBy(ref by) => Some(by.clone()),
+                        _ => None,
+                    },
+                    self.rules.match_by.clone(),
+                );
+                self.merge_children(base, base_location, layer, layer_location, path, &match_by)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn merge_children(
+        &mut self,
+        base: &mut Element,
+        base_location: Vec<usize>,
+        layer: &Element,
+        layer_location: Vec<usize>,
+        path: &str,
+        match_by: &MatchBy,
+    ) -> Result<(), TreexmlError> {
+        let existing = base.children.len();
+        for (j, child) in layer.children.iter().enumerate() {
+            let same_name = |el: &Element| el.prefix == child.prefix && el.name == child.name;
+            let mut candidates = (0..existing).filter(|&i| same_name(&base.children[i]));
+            let target = match *match_by {
+                MatchBy::Name => candidates.next(),
+                MatchBy::Attribute(ref key) => child.attributes.get(key).and_then(|value| {
+                    candidates.find(|&i| base.children[i].attributes.get(key) == Some(value))
+                }),
+                MatchBy::Position => {
+                    let position = layer.children[..j]
+                        .iter()
+                        .filter(|el| same_name(el))
+                        .count();
+                    candidates.nth(position)
+                }
+            };
+
+            match target {
+                Some(i) => {
+                    let child_path = child_path(path, base, i);
+                    let mut base_child = base_location.clone();
+                    base_child.push(i);
+                    let mut layer_child = layer_location.clone();
+                    layer_child.push(j);
+                    self.merge(
+                        &mut base.children[i],
+                        base_child,
+                        child,
+                        layer_child,
+                        &child_path,
+                    )?;
+                }
+                None => {
+                    base.children.push(child.clone());
+                    self.record_children(base, &base_location, base.children.len() - 1);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Record the current layer as the origin of the children of `element` from `start` on
+    fn record_children(&mut self, element: &Element, location: &[usize], start: usize) {
+        for (index, child) in element.children.iter().enumerate().skip(start) {
+            let mut child_location = location.to_vec();
+            child_location.push(index);
+            record(self.origins, child_location, child, self.layer);
+        }
+    }
+}
@@ -0,0 +1,635 @@
+//! XML patches, as described by RFC 5261
+//!
+//! A patch is a `<diff>` element whose children are `<add>`, `<replace>` and `<remove>`
+//! operations, applied in order. Each has a `sel` attribute: an XPath expression, evaluated
+//! against the document, that must select exactly one element, attribute or text node.
+//! Prefixes in selectors are resolved using the namespace bindings in scope for the
+//! operation.
+//!
+//! Since the tree keeps an element's text apart from its children, text added with
+//! `pos="before"` or `pos="after"` can't be placed and is rejected, and the `ws` attribute of
+//! `<remove>` has no effect. Comments and processing instructions aren't supported.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Read;
+
+use crate::diff::{Differ, Match, Tree};
+use crate::scope::Scope;
+use crate::xpath::Location;
+use crate::{DiffOptions, Document, Element, TreexmlError, XPath, XPathContext};
+
+/// A parsed XML patch
+#[derive(Debug, Clone)]
+pub struct Patch {
+    diff: Element,
+    operations: Vec<Operation>,
+}
+
+#[derive(Debug, Clone)]
+struct Operation {
+    kind: Kind,
+    sel: XPath,
+    context: XPathContext,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Kind {
+    Add(Position, AddType),
+    Replace,
+    Remove,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Position {
+    Append,
+    Prepend,
+    Before,
+    After,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AddType {
+    Nodes,
+    Attribute(String),
+    Namespace(String),
+}
+
+/// The node an operation's selector matched, located by child indices from the root
+enum Target {
+    Root,
+    Element(Vec<usize>),
+    Attribute(Vec<usize>, String),
+    Text(Vec<usize>),
+    CData(Vec<usize>),
+}
+
+impl Patch {
+    /// Parse a patch document
+    ///
+    /// # Failures
+    ///
+    /// Returns `TreexmlError::InvalidPatch` if the document isn't a valid patch
+    pub fn parse<R: Read>(r: R) -> Result<Patch, TreexmlError> {
+        match Document::parse(r)?.root {
+            Some(diff) => Patch::from_element(diff),
+            None => Err(invalid("the document has no root element")),
+        }
+    }
+
+    /// Create a patch from a `<diff>` element
+    ///
+    /// # Failures
+    ///
+    /// Returns `TreexmlError::InvalidPatch` if the element isn't a valid patch, or
+    /// `TreexmlError::XPathSyntax` if one of its selectors isn't valid XPath
+    pub fn from_element(diff: Element) -> Result<Patch, TreexmlError> {
+        let scope = Scope::root(&diff);
+        let operations = diff
+            .children
+            .iter()
+            .map(|operation| Operation::parse(&Scope::child(&scope, operation)))
+            .collect::<Result<_, _>>()?;
+        Ok(Patch { diff, operations })
+    }
+
+    /// The `<diff>` element the patch was created from
+    pub fn as_element(&self) -> &Element {
+        &self.diff
+    }
+
+    /// Apply the patch to the root of `document`
+    ///
+    /// The document is only changed if every operation succeeds.
+    ///
+    /// # Failures
+    ///
+    /// Returns `TreexmlError::PatchFailed` if an operation's selector doesn't match exactly one
+    /// node, or the operation can't be applied to it
+    pub fn apply(&self, document: &mut Document) -> Result<(), TreexmlError> {
+        match document.root {
+            Some(ref mut root) => self.apply_to(root),
+            None => Err(TreexmlError::PatchFailed {
+                sel: "/".to_owned(),
+                msg: "the document has no root element".to_owned(),
+            }),
+        }
+    }
+
+    /// Apply the patch to `element`, treating it as the root of a document
+    ///
+    /// The element is only changed if every operation succeeds.
+    pub fn apply_to(&self, element: &mut Element) -> Result<(), TreexmlError> {
+        let mut patched = element.clone();
+        for (operation, content) in self.operations.iter().zip(self.diff.children.iter()) {
+            operation.apply(content, &mut patched)?;
+        }
+        *element = patched;
+        Ok(())
+    }
+
+    /// Create a patch that turns `old` into `new`
+    pub fn between(old: &Element, new: &Element) -> Patch {
+        let options = DiffOptions::new();
+        let differ = Differ::new(&options);
+        let mut generator = Generator {
+            old: differ.tree(old),
+            new: differ.tree(new),
+            differ,
+            used_prefixes: old
+                .traverse()
+                .filter_map(|event| event.element().prefix.clone())
+                .collect(),
+            prefixes: HashMap::new(),
+            operations: Vec::new(),
+        };
+
+        let defaults = (default_namespace(old, None), default_namespace(new, None));
+        if old.qualified_name() == new.qualified_name() {
+            let path = format!("/{}", generator.step(old, defaults.0, None));
+            generator.generate(Tree::ROOT, Tree::ROOT, &path, defaults);
+        } else {
+            let path = format!("/{}", generator.step(old, defaults.0, None));
+            generator.push("replace", &path, |op| op.children.push(new.clone()));
+        }
+
+        let mut diff = Element::new("diff");
+        let mut bindings: Vec<(&String, &String)> = generator
+            .prefixes
+            .iter()
+            .map(|(uri, prefix)| (prefix, uri))
+            .collect();
+        bindings.sort();
+        for (prefix, uri) in bindings {
+            diff.attributes
+                .insert(format!("xmlns:{}", prefix), uri.clone());
+            diff.namespaces.insert(prefix.clone(), uri.clone());
+        }
+        diff.children.extend(generator.operations);
+
+        Patch::from_element(diff).expect("generated patches are valid")
+    }
+}
+
+impl fmt::Display for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.diff.fmt(f)
+    }
+}
+
+fn invalid(msg: &str) -> TreexmlError {
+    TreexmlError::InvalidPatch {
+        msg: msg.to_owned(),
+    }
+}
+
+impl Operation {
+    fn parse(scope: &Scope) -> Result<Operation, TreexmlError> {
+        let element = scope.element;
+        let sel = element
+            .attributes
+            .get("sel")
+            .ok_or_else(|| invalid(&format!("<{}> has no 'sel' attribute", element.name)))?;
+
+        let kind = match element.name.as_str() {
+            "add" => {
+                let position = match element.attributes.get("pos").map(String::as_str) {
+                    None => Position::Append,
+                    Some("prepend") => Position::Prepend,
+                    Some("before") => Position::Before,
+                    Some("after") => Position::After,
+                    Some(pos) => return Err(invalid(&format!("unknown position '{}'", pos))),
+                };
+                let add_type = match element.attributes.get("type") {
+                    None => AddType::Nodes,
+                    Some(t) if t.starts_with('@') && t.len() > 1 => {
+                        AddType::Attribute(t[1..].to_owned())
+                    }
+                    Some(t) if t.starts_with("namespace::") && t.len() > 11 => {
+                        AddType::Namespace(t[11..].to_owned())
+                    }
+                    Some(t) => return Err(invalid(&format!("unknown type '{}'", t))),
+                };
+                if add_type != AddType::Nodes && position != Position::Append {
+                    return Err(invalid("'pos' can only be used when adding nodes"));
+                }
+                Kind::Add(position, add_type)
+            }
+            "replace" => Kind::Replace,
+            "remove" => {
+                match element.attributes.get("ws").map(String::as_str) {
+                    None | Some("before") | Some("after") | Some("both") => {}
+                    Some(ws) => return Err(invalid(&format!("unknown ws value '{}'", ws))),
+                }
+                Kind::Remove
+            }
+            name => return Err(invalid(&format!("unknown operation <{}>", name))),
+        };
+
+        // Bind every prefix in scope, where the innermost declaration wins
+        let mut bindings = HashMap::new();
+        let mut current = Some(scope);
+        while let Some(scope) = current {
+            for (prefix, uri) in scope.element.declared_namespaces() {
+                bindings.entry(prefix).or_insert(uri);
+            }
+            current = scope.parent.as_deref();
+        }
+        let mut context = XPathContext::new();
+        for (prefix, uri) in bindings {
+            if !prefix.is_empty() && !uri.is_empty() {
+                context.namespace(prefix, uri);
+            }
+        }
+
+        Ok(Operation {
+            kind,
+            sel: XPath::compile(sel)?,
+            context,
+        })
+    }
+
+    fn fail(&self, msg: &str) -> TreexmlError {
+        TreexmlError::PatchFailed {
+            sel: self.sel.as_str().to_owned(),
+            msg: msg.to_owned(),
+        }
+    }
+
+    fn target(&self, root: &Element) -> Result<Target, TreexmlError> {
+        let mut locations = self.sel.locate(root, &self.context)?;
+        let location = match locations.len() {
+            1 => locations.pop().unwrap(),
+            0 => return Err(self.fail("no node matched")),
+            n => return Err(self.fail(&format!("{} nodes matched", n))),
+        };
+
+        Ok(match location {
+            Location::Root => Target::Root,
+            Location::Element(location) => Target::Element(location),
+            Location::Attribute(location, index) => {
+                let (name, _) = resolve(root, &location)
+                    .attributes
+                    .get_index(index)
+                    .unwrap();
+                Target::Attribute(location, name.clone())
+            }
+            Location::Text(location, false) => Target::Text(location),
+            Location::Text(location, true) => Target::CData(location),
+        })
+    }
+
+    fn apply(&self, content: &Element, root: &mut Element) -> Result<(), TreexmlError> {
+        let target = self.target(root)?;
+        let text = || {
+            let mut text = content.text.clone().unwrap_or_default();
+            text.extend(content.cdata.as_deref());
+            text
+        };
+
+        match (&self.kind, target) {
+            (Kind::Add(position, AddType::Nodes), Target::Element(location)) => {
+                self.add_nodes(*position, content, root, location)
+            }
+            (Kind::Add(_, AddType::Attribute(name)), Target::Element(location)) => {
+                let el = resolve_mut(root, &location);
+                if el.attributes.contains_key(name) {
+                    return Err(self.fail(&format!("attribute '{}' already exists", name)));
+                }
+                el.attributes.insert(name.clone(), text());
+                Ok(())
+            }
+            (Kind::Add(_, AddType::Namespace(prefix)), Target::Element(location)) => {
+                let el = resolve_mut(root, &location);
+                if el.declared_namespace(prefix).is_some() {
+                    return Err(self.fail(&format!("namespace '{}' already exists", prefix)));
+                }
+                el.namespaces.insert(prefix.clone(), text());
+                Ok(())
+            }
+            (Kind::Add(..), _) => Err(self.fail("nodes can only be added to an element")),
+
+            (Kind::Replace, Target::Element(location)) => {
+                let mut elements = content.children.iter();
+                match (elements.next(), elements.next()) {
+                    (Some(replacement), None) if is_blank(&content.text) => {
+                        *resolve_mut(root, &location) = replacement.clone();
+                        Ok(())
+                    }
+                    _ => Err(self.fail("an element can only be replaced by a single element")),
+                }
+            }
+            (Kind::Replace, Target::Attribute(location, name)) => {
+                resolve_mut(root, &location).attributes[&name] = text();
+                Ok(())
+            }
+            (Kind::Replace, Target::Text(location)) => {
+                resolve_mut(root, &location).text = Some(text());
+                Ok(())
+            }
+            (Kind::Replace, Target::CData(location)) => {
+                resolve_mut(root, &location).cdata = Some(text());
+                Ok(())
+            }
+
+            (Kind::Remove, Target::Element(mut location)) => match location.pop() {
+                Some(index) => {
+                    resolve_mut(root, &location).children.remove(index);
+                    Ok(())
+                }
+                None => Err(self.fail("the root element can't be removed")),
+            },
+            (Kind::Remove, Target::Attribute(location, name)) => {
+                resolve_mut(root, &location).attributes.shift_remove(&name);
+                Ok(())
+            }
+            (Kind::Remove, Target::Text(location)) => {
+                resolve_mut(root, &location).text = None;
+                Ok(())
+            }
+            (Kind::Remove, Target::CData(location)) => {
+                resolve_mut(root, &location).cdata = None;
+                Ok(())
+            }
+
+            (_, Target::Root) => Err(self.fail("the root node can't be changed")),
+        }
+    }
+
+    fn add_nodes(
+        &self,
+        position: Position,
+        content: &Element,
+        root: &mut Element,
+        mut location: Vec<usize>,
+    ) -> Result<(), TreexmlError> {
+        let nodes = content.children.iter().cloned();
+        match position {
+            Position::Append | Position::Prepend => {
+                let el = resolve_mut(root, &location);
+                let prepend = position == Position::Prepend;
+                for (field, added) in [
+                    (&mut el.text, &content.text),
+                    (&mut el.cdata, &content.cdata),
+                ] {
+                    if let Some(added) = added {
+                        let existing = field.take().unwrap_or_default();
+                        *field = Some(if prepend {
+                            added.clone() + &existing
+                        } else {
+                            existing + added
+                        });
+                    }
+                }
+                if prepend {
+                    el.children.splice(0..0, nodes);
+                } else {
+                    el.children.extend(nodes);
+                }
+                Ok(())
+            }
+            Position::Before | Position::After => {
+                if !is_blank(&content.text) || !is_blank(&content.cdata) {
+                    return Err(self.fail("text can only be added inside an element"));
+                }
+                let index = match location.pop() {
+                    Some(index) if position == Position::Before => index,
+                    Some(index) => index + 1,
+                    None => return Err(self.fail("the root element can't have siblings")),
+                };
+                resolve_mut(root, &location)
+                    .children
+                    .splice(index..index, nodes);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn is_blank(text: &Option<String>) -> bool {
+    text.as_deref().map_or(true, |t| t.trim().is_empty())
+}
+
+/// The default namespace in scope for `element`, inside an element where it's `inherited`
+fn default_namespace<'a>(element: &'a Element, inherited: Option<&'a str>) -> Option<&'a str> {
+    match element.declared_namespace("") {
+        Some(uri) => Some(uri).filter(|uri| !uri.is_empty()),
+        None => inherited,
+    }
+}
+
+fn resolve<'a>(mut element: &'a Element, location: &[usize]) -> &'a Element {
+    for &index in location {
+        element = &element.children[index];
+    }
+    element
+}
+
+fn resolve_mut<'a>(mut element: &'a mut Element, location: &[usize]) -> &'a mut Element {
+    for &index in location {
+        element = &mut element.children[index];
+    }
+    element
+}
+
+/// Builds the operations of a patch between two trees
+struct Generator<'a> {
+    differ: Differ<'a>,
+    old: Tree<'a>,
+    new: Tree<'a>,
+    /// Prefixes used in the old tree, which can't be bound to default namespaces
+    used_prefixes: HashSet<String>,
+    /// Prefixes bound to default namespaces, by URI
+    prefixes: HashMap<String, String>,
+    operations: Vec<Element>,
+}
+
+impl<'a> Generator<'a> {
+    fn push<F>(&mut self, name: &str, sel: &str, build: F)
+    where
+        F: FnOnce(&mut Element),
+    {
+        let mut operation = Element::new(name);
+        operation
+            .attributes
+            .insert("sel".to_owned(), sel.to_owned());
+        build(&mut operation);
+        self.operations.push(operation);
+    }
+
+    /// The selector step for `element`, in the default namespace `default`, with its position
+    /// among same-named siblings if it has a parent
+    fn step(
+        &mut self,
+        element: &Element,
+        default: Option<&str>,
+        position: Option<usize>,
+    ) -> String {
+        let name = match (&element.prefix, default) {
+            (None, Some(uri)) => {
+                if !self.prefixes.contains_key(uri) {
+                    let prefix = (1..)
+                        .map(|n| format!("d{}", n))
+                        .find(|p| {
+                            !self.used_prefixes.contains(p)
+                                && !self.prefixes.values().any(|v| v == p)
+                        })
+                        .unwrap();
+                    self.prefixes.insert(uri.to_owned(), prefix);
+                }
+                format!("{}:{}", self.prefixes[uri], element.name)
+            }
+            _ => element.qualified_name(),
+        };
+        match position {
+            Some(position) => format!("{}[{}]", name, position),
+            None => name,
+        }
+    }
+
+    /// The path of the child at `index` of `parent`, in the default namespace `default`
+    fn child_path(
+        &mut self,
+        path: &str,
+        parent: &Element,
+        default: Option<&str>,
+        index: usize,
+    ) -> String {
+        let child = &parent.children[index];
+        let position = parent.children[..index]
+            .iter()
+            .filter(|sibling| sibling.prefix == child.prefix && sibling.name == child.name)
+            .count()
+            + 1;
+        let step = self.step(child, default_namespace(child, default), Some(position));
+        format!("{}/{}", path, step)
+    }
+
+    /// Generate the operations turning the element at `old_index` in the old tree into the one
+    /// at `new_index` in the new tree, which have the same name, given the default namespaces
+    /// in scope for each
+    fn generate(
+        &mut self,
+        old_index: usize,
+        new_index: usize,
+        path: &str,
+        defaults: (Option<&str>, Option<&str>),
+    ) {
+        let (old, new) = (self.old.element(old_index), self.new.element(new_index));
+        for (name, value) in &old.attributes {
+            match new.attributes.get(name) {
+                None => self.push("remove", &format!("{}/@{}", path, name), |_| {}),
+                Some(new_value) if new_value != value => {
+                    self.push("replace", &format!("{}/@{}", path, name), |op| {
+                        op.text = Some(new_value.clone())
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        for (name, value) in &new.attributes {
+            if !old.attributes.contains_key(name) {
+                self.push("add", path, |op| {
+                    op.attributes
+                        .insert("type".to_owned(), format!("@{}", name));
+                    op.text = Some(value.clone());
+                });
+            }
+        }
+
+        // CDATA comes after text, so change it first to keep the text's position valid
+        let cdata_position = if old.text.is_some() { 2 } else { 1 };
+        self.generate_text(&old.cdata, &new.cdata, path, cdata_position, true);
+        self.generate_text(&old.text, &new.text, path, 1, false);
+
+        let old_children: Vec<usize> = self.old.children(old_index).collect();
+        let new_children: Vec<usize> = self.new.children(new_index).collect();
+        let (old_matches, _) =
+            self.differ
+                .match_children(&self.old, &old_children, &self.new, &new_children);
+
+        // Keep the children matched in order; the rest are removed and added again
+        let mut kept = vec![None; new_children.len()];
+        let mut last = None;
+        for (i, matched) in old_matches.iter().enumerate() {
+            if let Some(Match::Same(j)) | Some(Match::Changed(j)) = *matched {
+                if last.map_or(true, |last| j > last) {
+                    kept[j] = Some(i);
+                    last = Some(j);
+                }
+            }
+        }
+
+        for (j, kept) in kept.iter().enumerate() {
+            if let Some(i) = *kept {
+                if let Some(Match::Changed(_)) = old_matches[i] {
+                    let child_path = self.child_path(path, old, defaults.0, i);
+                    let child_defaults = (
+                        default_namespace(self.old.element(old_children[i]), defaults.0),
+                        default_namespace(self.new.element(new_children[j]), defaults.1),
+                    );
+                    self.generate(
+                        old_children[i],
+                        new_children[j],
+                        &child_path,
+                        child_defaults,
+                    );
+                }
+            }
+        }
+        for i in (0..old_children.len()).rev() {
+            if !kept.contains(&Some(i)) {
+                let child_path = self.child_path(path, old, defaults.0, i);
+                self.push("remove", &child_path, |_| {});
+            }
+        }
+        for (j, kept) in kept.iter().enumerate() {
+            if kept.is_some() {
+                continue;
+            }
+            let child = new.children[j].clone();
+            match j {
+                0 => self.push("add", path, |op| {
+                    op.attributes.insert("pos".to_owned(), "prepend".to_owned());
+                    op.children.push(child);
+                }),
+                _ => {
+                    let sibling_path = self.child_path(path, new, defaults.1, j - 1);
+                    self.push("add", &sibling_path, |op| {
+                        op.attributes.insert("pos".to_owned(), "after".to_owned());
+                        op.children.push(child);
+                    })
+                }
+            }
+        }
+    }
+
+    fn generate_text(
+        &mut self,
+        old: &Option<String>,
+        new: &Option<String>,
+        path: &str,
+        position: usize,
+        cdata: bool,
+    ) {
+        let sel = format!("{}/text()[{}]", path, position);
+        let set = |op: &mut Element, value: &String| {
+            if cdata {
+                op.cdata = Some(value.clone());
+            } else {
+                op.text = Some(value.clone());
+            }
+        };
+        match (old, new) {
+            (Some(old), Some(new)) if old != new => self.push("replace", &sel, |op| set(op, new)),
+            (Some(_), None) => self.push("remove", &sel, |_| {}),
+            (None, Some(new)) => self.push("add", path, |op| {
+                if !cdata {
+                    op.attributes.insert("pos".to_owned(), "prepend".to_owned());
+                }
+                set(op, new);
+            }),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,601 @@
+//! Compiled paths, as accepted by `Element::find` and friends
+//!
+//! A path is a list of `/`-separated steps, each selecting among the children of the
+//! elements matched so far. A step preceded by `//` searches all descendants instead.
+//! Steps are a tag name (`server`, matching any prefix), a prefixed name (`xsl:template`),
+//! or `*`, optionally followed by predicates:
+//!
+//! * `[2]`: the second element matched by the step so far, counting from 1
+//! * `[@name]`: elements that have the attribute `name`
+//! * `[@name='db']`: elements whose `name` attribute is `db`
+//!
+//! A path may end in an attribute step, `@name`, in which case it only matches elements that
+//! have that attribute, and values are read from the attribute rather than the text.
+//!
+//! The `Element` methods parse their path on every call; compile a `Path` once to apply the
+//! same lookup to many elements.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::diff::child_path;
+use crate::element::parse_attribute;
+use crate::{Element, TreexmlError};
+
+/// A compiled path, which can be applied to any number of elements
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path {
+    source: String,
+    steps: Vec<Step>,
+    attribute: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    /// Whether the step searches all descendants rather than just children
+    descendant: bool,
+    test: NameTest,
+    predicates: Vec<Predicate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum NameTest {
+    Any,
+    Local(String),
+    Prefixed(String, String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Predicate {
+    Index(usize),
+    HasAttribute(String),
+    AttributeEquals(String, String),
+}
+
+fn is_name_char(c: char) -> bool {
+    !c.is_whitespace() && !"/[]@='\"*".contains(c)
+}
+
+struct Parser<'s> {
+    source: &'s str,
+    pos: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn error(&self, msg: &str) -> TreexmlError {
+        TreexmlError::PathSyntax {
+            path: self.source.to_owned(),
+            pos: self.pos,
+            msg: msg.to_owned(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if self.source[self.pos..].starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), TreexmlError> {
+        if self.eat(s) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", s)))
+        }
+    }
+
+    fn name(&mut self) -> Result<&'s str, TreexmlError> {
+        let rest = &self.source[self.pos..];
+        let len = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expected a name"));
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    fn parse(&mut self) -> Result<Path, TreexmlError> {
+        let mut steps = Vec::new();
+        let mut descendant = self.eat("//");
+        loop {
+            if self.eat("@") {
+                if descendant {
+                    steps.push(Step {
+                        descendant,
+                        test: NameTest::Any,
+                        predicates: Vec::new(),
+                    });
+                }
+                let attribute = Some(self.name()?.to_owned());
+                if self.peek().is_some() {
+                    return Err(self.error("attribute steps must come last"));
+                }
+                return Ok(Path {
+                    source: self.source.to_owned(),
+                    steps,
+                    attribute,
+                });
+            }
+
+            steps.push(self.step(descendant)?);
+            if self.peek().is_none() {
+                return Ok(Path {
+                    source: self.source.to_owned(),
+                    steps,
+                    attribute: None,
+                });
+            }
+            descendant = self.eat("//");
+            if !descendant {
+                self.expect("/")?;
+            }
+        }
+    }
+
+    fn step(&mut self, descendant: bool) -> Result<Step, TreexmlError> {
+        let test = if self.eat("*") {
+            NameTest::Any
+        } else {
+            let name = self.name()?;
+            match name.find(':') {
+                Some(colon) => {
+                    NameTest::Prefixed(name[..colon].to_owned(), name[colon + 1..].to_owned())
+                }
+                None => NameTest::Local(name.to_owned()),
+            }
+        };
+
+        let mut predicates = Vec::new();
+        while self.eat("[") {
+            predicates.push(self.predicate()?);
+            self.expect("]")?;
+        }
+
+        Ok(Step {
+            descendant,
+            test,
+            predicates,
+        })
+    }
+
+    fn predicate(&mut self) -> Result<Predicate, TreexmlError> {
+        if self.eat("@") {
+            let name = self.name()?.to_owned();
+            if !self.eat("=") {
+                return Ok(Predicate::HasAttribute(name));
+            }
+            let quote = match self.peek() {
+                Some(quote @ '\'') | Some(quote @ '"') => quote,
+                _ => return Err(self.error("expected a quoted value")),
+            };
+            self.pos += 1;
+            let rest = &self.source[self.pos..];
+            let len = rest
+                .find(quote)
+                .ok_or_else(|| self.error("unterminated value"))?;
+            self.pos += len + 1;
+            return Ok(Predicate::AttributeEquals(name, rest[..len].to_owned()));
+        }
+
+        let rest = &self.source[self.pos..];
+        let len = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        match rest[..len].parse::<usize>() {
+            Ok(index) if index > 0 => {
+                self.pos += len;
+                Ok(Predicate::Index(index))
+            }
+            _ => Err(self.error("expected an index from 1, or an attribute test")),
+        }
+    }
+}
+
+impl Path {
+    /// Compile a path
+    ///
+    /// # Failures
+    ///
+    /// Returns `TreexmlError::PathSyntax` with the offset at which parsing failed
+    pub fn compile(path: &str) -> Result<Path, TreexmlError> {
+        Parser {
+            source: path,
+            pos: 0,
+        }
+        .parse()
+    }
+
+    /// The source the path was compiled from
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// The attribute named by a trailing `@name` step
+    pub fn attribute(&self) -> Option<&str> {
+        self.attribute.as_deref()
+    }
+
+    /// Find the first element matched by the path, relative to `element`
+    pub fn find<'a>(&self, element: &'a Element) -> Result<&'a Element, TreexmlError> {
+        self.first(element).ok_or_else(|| self.not_found())
+    }
+
+    /// Find the first element matched by the path; returns a mutable borrow
+    pub fn find_mut<'a>(&self, element: &'a mut Element) -> Result<&'a mut Element, TreexmlError> {
+        let (location, _) = self
+            .first_from(&self.steps, true, Vec::new(), element)
+            .ok_or_else(|| self.not_found())?;
+        Ok(resolve_mut(element, &location))
+    }
+
+    /// Find every element matched by the path, in document order
+    pub fn find_all<'a>(&self, element: &'a Element) -> impl Iterator<Item = &'a Element> {
+        self.locate(element).into_iter().map(|(_, el)| el)
+    }
+
+    /// Find every element matched by the path; returns mutable borrows
+    ///
+    /// Matches nested inside another match are skipped, since they're reachable through it.
+    pub fn find_all_mut<'a>(
+        &self,
+        element: &'a mut Element,
+    ) -> impl Iterator<Item = &'a mut Element> {
+        let mut locations: Vec<Vec<usize>> = Vec::new();
+        for (location, _) in self.locate(element) {
+            if !locations.iter().any(|outer| location.starts_with(outer)) {
+                locations.push(location);
+            }
+        }
+
+        let mut matched = Vec::with_capacity(locations.len());
+        let locations: Vec<&[usize]> = locations.iter().map(Vec::as_slice).collect();
+        collect_mut(element, &locations, &mut matched);
+        matched.into_iter()
+    }
+
+    /// Find the first element matched by the path, and parse its text as a `T`, or its
+    /// attribute if the path ends in `@name`
+    pub fn find_value<T: FromStr>(&self, element: &Element) -> Result<Option<T>, TreexmlError> {
+        let (location, el) = self
+            .first_from(&self.steps, true, Vec::new(), element)
+            .ok_or_else(|| self.not_found())?;
+        self.value(element, &location, el)
+    }
+
+    /// Find every element matched by the path, and parse the text of those that have any, or
+    /// their attribute if the path ends in `@name`
+    pub fn find_values<T: FromStr>(&self, element: &Element) -> Result<Vec<T>, TreexmlError> {
+        let mut values = Vec::new();
+        for (location, el) in self.locate(element) {
+            values.extend(self.value(element, &location, el)?);
+        }
+        Ok(values)
+    }
+
+    /// Count the elements matched by the path
+    pub fn count(&self, element: &Element) -> usize {
+        self.locate(element).len()
+    }
+
+    /// Set the text of the element matched by the path, or the attribute if the path ends in
+    /// `@name`, creating the element as `ensure` would
+    pub fn set_value<V: ToString>(
+        &self,
+        element: &mut Element,
+        value: V,
+    ) -> Result<(), TreexmlError> {
+        let el = self.ensure(element)?;
+        match self.attribute {
+            Some(ref name) => {
+                el.attributes.insert(name.clone(), value.to_string());
+            }
+            None => el.text = Some(value.to_string()),
+        }
+        Ok(())
+    }
+
+    fn not_found(&self) -> TreexmlError {
+        TreexmlError::ElementNotFound {
+            t: self.source.clone(),
+        }
+    }
+
+    /// Parse the value the path selects from `element`, which it matched at `location` within
+    /// `root`
+    fn value<T: FromStr>(
+        &self,
+        root: &Element,
+        location: &[usize],
+        element: &Element,
+    ) -> Result<Option<T>, TreexmlError> {
+        if let Some(ref name) = self.attribute {
+            return element
+                .attributes
+                .get(name)
+                .map(|value| parse_attribute(location_path(root, location), name, value))
+                .transpose();
+        }
+
+        if let Some(text) = element.text.as_ref() {
+            match T::from_str(text) {
+                Err(_) => Err(TreexmlError::ValueFromStr {
+                    path: location_path(root, location),
+                    t: text.to_string(),
+                    target: std::any::type_name::<T>(),
+                }),
+                Ok(value) => Ok(Some(value)),
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn has_attribute(&self, element: &Element) -> bool {
+        self.attribute
+            .as_ref()
+            .map_or(true, |name| element.attributes.contains_key(name))
+    }
+
+    /// Find the first element matched by the path, in document order
+    fn first<'a>(&self, element: &'a Element) -> Option<&'a Element> {
+        self.first_from(&self.steps, true, Vec::new(), element)
+            .map(|(_, el)| el)
+    }
+
+    fn first_from<'a>(
+        &self,
+        steps: &[Step],
+        check_attribute: bool,
+        location: Vec<usize>,
+        element: &'a Element,
+    ) -> Option<(Vec<usize>, &'a Element)> {
+        match steps.split_first() {
+            None if check_attribute && !self.has_attribute(element) => None,
+            None => Some((location, element)),
+            Some((step, rest)) => {
+                step.select(element)
+                    .into_iter()
+                    .find_map(|(relative, candidate)| {
+                        let mut location = location.clone();
+                        location.extend(relative);
+                        self.first_from(rest, check_attribute, location, candidate)
+                    })
+            }
+        }
+    }
+
+    /// Find the first element matched by the path, creating it and any missing ancestors if
+    /// there is none. The attribute step, if any, is ignored.
+    ///
+    /// The path is matched as far as possible, then the remaining steps are created: they must
+    /// name a single element, and may only use `[@name='value']` predicates, or an `[n]`
+    /// predicate selecting the element that would be appended.
+    ///
+    /// # Failures
+    ///
+    /// Returns `TreexmlError::PathNotCreatable` if a missing step can't be created
+    pub fn ensure<'a>(&self, element: &'a mut Element) -> Result<&'a mut Element, TreexmlError> {
+        let (matched, mut location) = (0..=self.steps.len())
+            .rev()
+            .find_map(|k| {
+                self.first_from(&self.steps[..k], false, Vec::new(), element)
+                    .map(|(location, _)| (k, location))
+            })
+            .unwrap();
+
+        let mut created = Vec::new();
+        let mut parent: &Element = resolve_mut(element, &location);
+        for step in &self.steps[matched..] {
+            created.push(
+                step.create(parent)
+                    .map_err(|msg| TreexmlError::PathNotCreatable {
+                        path: self.source.clone(),
+                        msg: msg.to_owned(),
+                    })?,
+            );
+            parent = created.last().unwrap();
+        }
+
+        let depth = created.len();
+        if let Some(mut chain) = created.pop() {
+            while let Some(mut outer) = created.pop() {
+                outer.children.push(chain);
+                chain = outer;
+            }
+            let parent = resolve_mut(element, &location);
+            location.push(parent.children.len());
+            location.extend(std::iter::repeat(0).take(depth - 1));
+            parent.children.push(chain);
+        }
+
+        Ok(resolve_mut(element, &location))
+    }
+
+    /// Find every element matched by the path, in document order, along with its location:
+    /// the child indices leading to it from `element`
+    pub(crate) fn locate<'a>(&self, element: &'a Element) -> Vec<(Vec<usize>, &'a Element)> {
+        let mut matched = vec![(Vec::new(), element)];
+        for step in &self.steps {
+            let mut next = Vec::new();
+            for (location, el) in matched {
+                for (relative, candidate) in step.select(el) {
+                    let mut location = location.clone();
+                    location.extend(relative);
+                    next.push((location, candidate));
+                }
+            }
+            next.sort_by(|a, b| a.0.cmp(&b.0));
+            next.dedup_by(|a, b| a.0 == b.0);
+            matched = next;
+        }
+        matched.retain(|(_, el)| self.has_attribute(el));
+        matched
+    }
+}
+
+impl FromStr for Path {
+    type Err = TreexmlError;
+
+    fn from_str(s: &str) -> Result<Path, TreexmlError> {
+        Path::compile(s)
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// The path of the element at `location` within `root`, such as `/root/server[2]`, indexing
+/// steps as `Diff` does
+fn location_path(root: &Element, location: &[usize]) -> String {
+    let mut path = format!("/{}", root.qualified_name());
+    let mut element = root;
+    for &index in location {
+        path = child_path(&path, element, index);
+        element = &element.children[index];
+    }
+    path
+}
+
+/// Borrow the element at `location`
+fn resolve_mut<'a>(mut element: &'a mut Element, location: &[usize]) -> &'a mut Element {
+    for &index in location {
+        element = &mut element.children[index];
+    }
+    element
+}
+
+/// Borrow the elements at each of `locations`, none of which may be inside another
+fn collect_mut<'a>(
+    element: &'a mut Element,
+    locations: &[&[usize]],
+    matched: &mut Vec<&'a mut Element>,
+) {
+    if locations.iter().any(|location| location.is_empty()) {
+        matched.push(element);
+        return;
+    }
+
+    let mut locations = locations;
+    for (index, child) in element.children.iter_mut().enumerate() {
+        let count = locations
+            .iter()
+            .take_while(|location| location[0] == index)
+            .count();
+        if count > 0 {
+            let inner: Vec<&[usize]> = locations[..count]
+                .iter()
+                .map(|location| &location[1..])
+                .collect();
+            collect_mut(child, &inner, matched);
+            locations = &locations[count..];
+        }
+        if locations.is_empty() {
+            break;
+        }
+    }
+}
+
+impl Step {
+    fn matches(&self, element: &Element) -> bool {
+        match self.test {
+            NameTest::Any => true,
+            NameTest::Local(ref name) => element.name == *name,
+            NameTest::Prefixed(ref prefix, ref name) => {
+                element.name == *name && element.prefix.as_ref() == Some(prefix)
+            }
+        }
+    }
+
+    /// Create an element that this step would select from `parent`
+    fn create(&self, parent: &Element) -> Result<Element, &'static str> {
+        if self.descendant {
+            return Err("descendant steps can't be created");
+        }
+        let mut element = match self.test {
+            NameTest::Any => return Err("wildcard steps can't be created"),
+            NameTest::Local(ref name) => Element::new(name),
+            NameTest::Prefixed(ref prefix, ref name) => Element {
+                prefix: Some(prefix.clone()),
+                ..Element::new(name)
+            },
+        };
+
+        let mut existing = parent.children.iter().filter(|el| self.matches(el)).count();
+        for predicate in &self.predicates {
+            match predicate {
+                Predicate::Index(index) if *index == existing + 1 => existing = 0,
+                Predicate::Index(_) => return Err("indexes can only create the next element"),
+                Predicate::HasAttribute(_) => {
+                    return Err("attribute tests without a value can't be created")
+                }
+                Predicate::AttributeEquals(name, value) => {
+                    existing = parent
+                        .children
+                        .iter()
+                        .filter(|el| self.matches(el) && el.attributes.get(name) == Some(value))
+                        .count();
+                    element.attributes.insert(name.clone(), value.clone());
+                }
+            }
+        }
+
+        Ok(element)
+    }
+
+    /// The elements selected by this step from `element`, with their locations relative to it
+    fn select<'a>(&self, element: &'a Element) -> Vec<(Vec<usize>, &'a Element)> {
+        let mut selected: Vec<(Vec<usize>, &Element)> = if self.descendant {
+            let mut descendants = Vec::new();
+            let mut stack: Vec<(Vec<usize>, &Element)> = element
+                .children
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(index, el)| (vec![index], el))
+                .collect();
+            while let Some((location, el)) = stack.pop() {
+                stack.extend(el.children.iter().enumerate().rev().map(|(index, child)| {
+                    let mut location = location.clone();
+                    location.push(index);
+                    (location, child)
+                }));
+                descendants.push((location, el));
+            }
+            descendants
+        } else {
+            element
+                .children
+                .iter()
+                .enumerate()
+                .map(|(index, el)| (vec![index], el))
+                .collect()
+        };
+        selected.retain(|(_, el)| self.matches(el));
+
+        for predicate in &self.predicates {
+            match predicate {
+                Predicate::Index(index) => {
+                    selected = selected.into_iter().nth(index - 1).into_iter().collect()
+                }
+                Predicate::HasAttribute(name) => {
+                    selected.retain(|(_, el)| el.attributes.contains_key(name))
+                }
+                Predicate::AttributeEquals(name, value) => {
+                    selected.retain(|(_, el)| el.attributes.get(name) == Some(value))
+                }
+            }
+        }
+
+        selected
+    }
+}
@@ -0,0 +1,45 @@
+//! Namespace scopes, for resolving prefixes through the bindings of an element's ancestors
+
+use std::rc::Rc;
+
+use crate::Element;
+
+/// An element along with its ancestors
+///
+/// Elements only record the namespaces they declare, so prefixes are resolved by walking up
+/// from the element that uses them.
+#[derive(Debug)]
+pub(crate) struct Scope<'a> {
+    pub(crate) element: &'a Element,
+    pub(crate) parent: Option<Rc<Scope<'a>>>,
+}
+
+impl<'a> Scope<'a> {
+    /// The scope of `element` as the root of a document
+    pub(crate) fn root(element: &'a Element) -> Rc<Scope<'a>> {
+        Rc::new(Scope {
+            element,
+            parent: None,
+        })
+    }
+
+    /// The scope of `child`, an element inside `parent`
+    pub(crate) fn child(parent: &Rc<Scope<'a>>, child: &'a Element) -> Rc<Scope<'a>> {
+        Rc::new(Scope {
+            element: child,
+            parent: Some(parent.clone()),
+        })
+    }
+
+    /// The namespace URI bound to `prefix`, with the default namespace under `""`
+    pub(crate) fn namespace(&self, prefix: &str) -> Option<&'a str> {
+        let mut current = Some(self);
+        while let Some(scope) = current {
+            if let Some(uri) = scope.element.declared_namespace(prefix) {
+                return Some(uri).filter(|uri| !uri.is_empty());
+            }
+            current = scope.parent.as_deref();
+        }
+        None
+    }
+}
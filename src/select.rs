@@ -0,0 +1,585 @@
+//! CSS selectors accepted by `Element::select`
+//!
+//! Supported are type selectors (`item`, `*`, and `ns|item`, `*|item` or `|item` to match by
+//! namespace), `#id`, `.class`, attribute selectors (`[a]`, `[a=v]`, `[a~=v]`, `[a^=v]`,
+//! `[a$=v]`, `[a*=v]`, `[a|=v]`), the `:first-child`, `:last-child`, `:only-child`,
+//! `:nth-child()` and `:nth-last-child()` pseudo-classes, all four combinators, and
+//! comma-separated selector lists.
+//!
+//! As with CSS `@namespace` rules, `ns` prefixes are resolved to namespace URIs through a map
+//! given by the caller, which is then compared to the namespace an element's prefix is bound to
+//! by it or its ancestors. A default namespace in the map applies to type selectors without a
+//! prefix, which otherwise match elements in any namespace.
+
+use std::iter::Enumerate;
+use std::slice::Iter;
+
+use crate::{Element, TreexmlError};
+
+/// A parsed selector list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Selector {
+    alternatives: Vec<Complex>,
+}
+
+/// Compound selectors joined by combinators: `combinators[i]` sits between `compounds[i]`
+/// and `compounds[i + 1]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Complex {
+    compounds: Vec<Compound>,
+    combinators: Vec<Combinator>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combinator {
+    Descendant,
+    Child,
+    NextSibling,
+    SubsequentSibling,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Compound {
+    namespace: NamespaceTest,
+    /// `None` for `*`
+    name: Option<String>,
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+enum NamespaceTest {
+    /// `*|`, or no prefix given without a default namespace
+    #[default]
+    Any,
+    /// `|name`
+    NoNamespace,
+    /// `ns|name`, or no prefix given with a default namespace, by URI
+    Namespace(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AttributeOp {
+    Exists,
+    Equals,
+    Includes,
+    DashMatch,
+    Prefix,
+    Suffix,
+    Substring,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Filter {
+    Attribute(String, AttributeOp, String),
+    /// `:nth-child(an+b)`, counting from the end if the flag is set
+    NthChild(i64, i64, bool),
+    OnlyChild,
+}
+
+/// An element under consideration, with the ancestors leading to it and its index among its
+/// parent's children
+#[derive(Clone, Copy)]
+struct Position<'a, 'c> {
+    ancestors: &'c [(&'a Element, usize)],
+    element: &'a Element,
+    index: usize,
+}
+
+impl<'a, 'c> Position<'a, 'c> {
+    fn parent(&self) -> Option<Position<'a, 'c>> {
+        self.ancestors
+            .split_last()
+            .map(|(&(element, index), ancestors)| Position {
+                ancestors,
+                element,
+                index,
+            })
+    }
+
+    /// The namespace URI of the element, which its prefix is bound to by it or an ancestor
+    fn namespace(&self) -> Option<&'a str> {
+        let prefix = self.element.prefix.as_deref().unwrap_or("");
+        let ancestors = self.ancestors.iter().rev().map(|&(element, _)| element);
+        std::iter::once(self.element)
+            .chain(ancestors)
+            .find_map(|element| element.declared_namespace(prefix))
+            .filter(|uri| !uri.is_empty())
+    }
+
+    fn siblings(&self) -> &'a [Element] {
+        match self.ancestors.last() {
+            Some((parent, _)) => &parent.children,
+            None => &[],
+        }
+    }
+
+    /// The preceding siblings, nearest first
+    fn preceding(self) -> impl Iterator<Item = Position<'a, 'c>> {
+        let siblings = self.siblings();
+        (0..self.index).rev().map(move |index| Position {
+            ancestors: self.ancestors,
+            element: &siblings[index],
+            index,
+        })
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_' || !c.is_ascii()
+}
+
+struct Parser<'s> {
+    source: &'s str,
+    pos: usize,
+    /// Namespace URIs by prefix, with the default namespace under `""`
+    namespaces: &'s [(&'s str, &'s str)],
+}
+
+impl<'s> Parser<'s> {
+    fn error(&self, msg: &str) -> TreexmlError {
+        TreexmlError::SelectorSyntax {
+            selector: self.source.to_owned(),
+            pos: self.pos,
+            msg: msg.to_owned(),
+        }
+    }
+
+    /// The namespace URI `prefix` is declared for
+    fn namespace(&self, prefix: &str) -> Option<&'s str> {
+        self.namespaces
+            .iter()
+            .find(|(declared, _)| *declared == prefix)
+            .map(|&(_, uri)| uri)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), TreexmlError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> bool {
+        let rest = &self.source[self.pos..];
+        let trimmed = rest.trim_start();
+        self.pos += rest.len() - trimmed.len();
+        rest.len() != trimmed.len()
+    }
+
+    /// An identifier, with `\` escaping the next character
+    fn ident(&mut self) -> Result<String, TreexmlError> {
+        let mut ident = String::new();
+        while let Some(c) = self.peek() {
+            if c == '\\' {
+                self.pos += 1;
+                match self.peek() {
+                    Some(escaped) => {
+                        ident.push(escaped);
+                        self.pos += escaped.len_utf8();
+                    }
+                    None => return Err(self.error("expected an escaped character")),
+                }
+            } else if is_ident_char(c) {
+                ident.push(c);
+                self.pos += c.len_utf8();
+            } else {
+                break;
+            }
+        }
+        if ident.is_empty() {
+            Err(self.error("expected a name"))
+        } else {
+            Ok(ident)
+        }
+    }
+
+    fn parse(&mut self) -> Result<Selector, TreexmlError> {
+        let mut alternatives = Vec::new();
+        loop {
+            self.skip_whitespace();
+            alternatives.push(self.complex()?);
+            if self.peek().is_none() {
+                return Ok(Selector { alternatives });
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn complex(&mut self) -> Result<Complex, TreexmlError> {
+        let mut compounds = vec![self.compound()?];
+        let mut combinators = Vec::new();
+        loop {
+            let whitespace = self.skip_whitespace();
+            let combinator = match self.peek() {
+                Some('>') => Combinator::Child,
+                Some('+') => Combinator::NextSibling,
+                Some('~') => Combinator::SubsequentSibling,
+                None | Some(',') => {
+                    return Ok(Complex {
+                        compounds,
+                        combinators,
+                    })
+                }
+                _ if whitespace => Combinator::Descendant,
+                _ => return Err(self.error("expected a combinator")),
+            };
+            if combinator != Combinator::Descendant {
+                self.pos += 1;
+                self.skip_whitespace();
+            }
+            combinators.push(combinator);
+            compounds.push(self.compound()?);
+        }
+    }
+
+    fn compound(&mut self) -> Result<Compound, TreexmlError> {
+        let start = self.pos;
+        let mut compound = Compound::default();
+
+        // Type selector, with an optional prefix
+        let first = if self.eat('*') {
+            Some(None)
+        } else if self.peek().is_some_and(|c| is_ident_char(c) || c == '\\') {
+            Some(Some(self.ident()?))
+        } else {
+            None
+        };
+        if self.eat('|') {
+            compound.namespace = match first {
+                None => NamespaceTest::NoNamespace,
+                Some(None) => NamespaceTest::Any,
+                Some(Some(prefix)) => match self.namespace(&prefix) {
+                    Some(uri) => NamespaceTest::Namespace(uri.to_owned()),
+                    None => {
+                        self.pos = start;
+                        return Err(self.error("undeclared namespace prefix"));
+                    }
+                },
+            };
+            compound.name = if self.eat('*') {
+                None
+            } else {
+                Some(self.ident()?)
+            };
+        } else {
+            compound.name = first.flatten();
+            if let Some(uri) = self.namespace("") {
+                compound.namespace = NamespaceTest::Namespace(uri.to_owned());
+            }
+        }
+
+        loop {
+            match self.peek() {
+                Some('#') => {
+                    self.pos += 1;
+                    let id = self.ident()?;
+                    compound.filters.push(Filter::Attribute(
+                        "id".to_owned(),
+                        AttributeOp::Equals,
+                        id,
+                    ));
+                }
+                Some('.') => {
+                    self.pos += 1;
+                    let class = self.ident()?;
+                    compound.filters.push(Filter::Attribute(
+                        "class".to_owned(),
+                        AttributeOp::Includes,
+                        class,
+                    ));
+                }
+                Some('[') => {
+                    self.pos += 1;
+                    compound.filters.push(self.attribute()?);
+                }
+                Some(':') => {
+                    self.pos += 1;
+                    compound.filters.push(self.pseudo_class()?);
+                }
+                _ => break,
+            }
+        }
+
+        if self.pos == start {
+            return Err(self.error("expected a selector"));
+        }
+        Ok(compound)
+    }
+
+    fn attribute(&mut self) -> Result<Filter, TreexmlError> {
+        self.skip_whitespace();
+        let mut name = self.ident()?;
+        if self.peek() == Some('|') && !self.source[self.pos..].starts_with("|=") {
+            self.pos += 1;
+            name = format!("{}:{}", name, self.ident()?);
+        }
+        self.skip_whitespace();
+
+        if self.eat(']') {
+            return Ok(Filter::Attribute(name, AttributeOp::Exists, String::new()));
+        }
+
+        let op = match self.peek() {
+            Some('=') => AttributeOp::Equals,
+            Some('~') => AttributeOp::Includes,
+            Some('|') => AttributeOp::DashMatch,
+            Some('^') => AttributeOp::Prefix,
+            Some('$') => AttributeOp::Suffix,
+            Some('*') => AttributeOp::Substring,
+            _ => return Err(self.error("expected an attribute operator or ']'")),
+        };
+        self.pos += 1;
+        if op != AttributeOp::Equals {
+            self.expect('=')?;
+        }
+        self.skip_whitespace();
+
+        let value = match self.peek() {
+            Some(quote @ '"') | Some(quote @ '\'') => {
+                self.pos += 1;
+                let rest = &self.source[self.pos..];
+                let len = rest
+                    .find(quote)
+                    .ok_or_else(|| self.error("unterminated string"))?;
+                self.pos += len + 1;
+                rest[..len].to_owned()
+            }
+            _ => self.ident()?,
+        };
+        self.skip_whitespace();
+        self.expect(']')?;
+
+        Ok(Filter::Attribute(name, op, value))
+    }
+
+    fn pseudo_class(&mut self) -> Result<Filter, TreexmlError> {
+        let start = self.pos;
+        let name = self.ident()?;
+        Ok(match name.as_str() {
+            "first-child" => Filter::NthChild(0, 1, false),
+            "last-child" => Filter::NthChild(0, 1, true),
+            "only-child" => Filter::OnlyChild,
+            "nth-child" | "nth-last-child" => {
+                self.expect('(')?;
+                self.skip_whitespace();
+                let (a, b) = self.nth()?;
+                self.skip_whitespace();
+                self.expect(')')?;
+                Filter::NthChild(a, b, name == "nth-last-child")
+            }
+            _ => {
+                self.pos = start;
+                return Err(self.error("unsupported pseudo-class"));
+            }
+        })
+    }
+
+    /// The `an+b` argument of `:nth-child()`
+    fn nth(&mut self) -> Result<(i64, i64), TreexmlError> {
+        let rest = &self.source[self.pos..];
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || "+- ".contains(c)))
+            .unwrap_or(rest.len());
+        let arg: String = rest[..len].chars().filter(|c| !c.is_whitespace()).collect();
+        let parse = |s: &str| -> Option<(i64, i64)> {
+            match s {
+                "odd" => return Some((2, 1)),
+                "even" => return Some((2, 0)),
+                _ => {}
+            }
+            match s.find('n') {
+                None => Some((0, s.parse().ok()?)),
+                Some(n) => {
+                    let a = match &s[..n] {
+                        "" | "+" => 1,
+                        "-" => -1,
+                        a => a.parse().ok()?,
+                    };
+                    let b = match &s[n + 1..] {
+                        "" => 0,
+                        b if b.starts_with('+') => b[1..].parse().ok()?,
+                        b if b.starts_with('-') => b.parse().ok()?,
+                        _ => return None,
+                    };
+                    Some((a, b))
+                }
+            }
+        };
+        let nth = parse(&arg).ok_or_else(|| self.error("expected an+b, odd or even"))?;
+        self.pos += len;
+        Ok(nth)
+    }
+}
+
+impl Selector {
+    /// Parse a selector list, with `namespaces` mapping the prefixes it uses to URIs
+    ///
+    /// # Failures
+    ///
+    /// Returns `TreexmlError::SelectorSyntax` with the offset at which parsing failed, which
+    /// includes prefixes `namespaces` doesn't declare
+    pub(crate) fn parse(
+        selector: &str,
+        namespaces: &[(&str, &str)],
+    ) -> Result<Selector, TreexmlError> {
+        Parser {
+            source: selector,
+            pos: 0,
+            namespaces,
+        }
+        .parse()
+    }
+
+    /// The descendants of `element` matching the selector, in document order
+    pub(crate) fn select<'a>(&self, element: &'a Element) -> Descendants<'a, '_> {
+        Descendants {
+            selector: self,
+            ancestors: vec![(element, 0)],
+            stack: vec![element.children.iter().enumerate()],
+        }
+    }
+
+    fn matches(&self, position: Position) -> bool {
+        self.alternatives
+            .iter()
+            .any(|complex| complex.matches(complex.compounds.len() - 1, position))
+    }
+}
+
+/// An iterator over the descendants of an element matching a selector
+pub(crate) struct Descendants<'a, 's> {
+    selector: &'s Selector,
+    ancestors: Vec<(&'a Element, usize)>,
+    stack: Vec<Enumerate<Iter<'a, Element>>>,
+}
+
+impl<'a, 's> Iterator for Descendants<'a, 's> {
+    type Item = &'a Element;
+
+    fn next(&mut self) -> Option<&'a Element> {
+        loop {
+            match self.stack.last_mut()?.next() {
+                Some((index, element)) => {
+                    let matched = self.selector.matches(Position {
+                        ancestors: &self.ancestors,
+                        element,
+                        index,
+                    });
+                    self.ancestors.push((element, index));
+                    self.stack.push(element.children.iter().enumerate());
+                    if matched {
+                        return Some(element);
+                    }
+                }
+                None => {
+                    self.stack.pop();
+                    self.ancestors.pop();
+                }
+            }
+        }
+    }
+}
+
+impl Complex {
+    /// Whether `position` matches the compounds up to and including `i`
+    fn matches(&self, i: usize, position: Position) -> bool {
+        if !self.compounds[i].matches(position) {
+            return false;
+        }
+        if i == 0 {
+            return true;
+        }
+
+        match self.combinators[i - 1] {
+            Combinator::Child => position
+                .parent()
+                .is_some_and(|parent| self.matches(i - 1, parent)),
+            Combinator::Descendant => {
+                let mut ancestor = position.parent();
+                while let Some(current) = ancestor {
+                    if self.matches(i - 1, current) {
+                        return true;
+                    }
+                    ancestor = current.parent();
+                }
+                false
+            }
+            Combinator::NextSibling => position
+                .preceding()
+                .next()
+                .is_some_and(|sibling| self.matches(i - 1, sibling)),
+            Combinator::SubsequentSibling => position
+                .preceding()
+                .any(|sibling| self.matches(i - 1, sibling)),
+        }
+    }
+}
+
+impl Compound {
+    fn matches(&self, position: Position) -> bool {
+        let element = position.element;
+        if self.name.as_ref().is_some_and(|name| *name != element.name) {
+            return false;
+        }
+        let namespace_matches = match self.namespace {
+            NamespaceTest::Any => true,
+            NamespaceTest::NoNamespace => position.namespace().is_none(),
+            NamespaceTest::Namespace(ref uri) => position.namespace() == Some(uri),
+        };
+        namespace_matches && self.filters.iter().all(|filter| filter.matches(position))
+    }
+}
+
+impl Filter {
+    fn matches(&self, position: Position) -> bool {
+        match self {
+            Filter::Attribute(name, op, expected) => {
+                let value = match position.element.attributes.get(name) {
+                    Some(value) => value,
+                    None => return false,
+                };
+                match op {
+                    AttributeOp::Exists => true,
+                    AttributeOp::Equals => value == expected,
+                    AttributeOp::Includes => value.split_whitespace().any(|v| v == expected),
+                    AttributeOp::DashMatch => {
+                        value == expected || value.starts_with(&format!("{}-", expected))
+                    }
+                    AttributeOp::Prefix => !expected.is_empty() && value.starts_with(expected),
+                    AttributeOp::Suffix => !expected.is_empty() && value.ends_with(expected),
+                    AttributeOp::Substring => !expected.is_empty() && value.contains(expected),
+                }
+            }
+            Filter::NthChild(a, b, from_end) => {
+                if position.ancestors.is_empty() {
+                    return false;
+                }
+                let count = position.siblings().len();
+                let n = if *from_end {
+                    count - position.index
+                } else {
+                    position.index + 1
+                } as i64;
+                match a {
+                    0 => n == *b,
+                    a => (n - b) % a == 0 && (n - b) / a >= 0,
+                }
+            }
+            Filter::OnlyChild => position.siblings().len() == 1,
+        }
+    }
+}
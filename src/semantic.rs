@@ -0,0 +1,231 @@
+//! Semantic equality, as checked by `Element::semantically_eq`
+
+use std::rc::Rc;
+
+use crate::diff::{child_path, child_paths};
+use crate::scope::Scope;
+use crate::Element;
+
+/// Options controlling which differences `Element::semantically_eq` ignores
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EqOptions {
+    ignore_whitespace_text: bool,
+    ignore_prefixes: bool,
+    cdata_as_text: bool,
+}
+
+impl Default for EqOptions {
+    fn default() -> Self {
+        EqOptions {
+            ignore_whitespace_text: true,
+            ignore_prefixes: true,
+            cdata_as_text: true,
+        }
+    }
+}
+
+impl EqOptions {
+    /// Create options that ignore every kind of insignificant difference
+    pub fn new() -> EqOptions {
+        EqOptions::default()
+    }
+
+    /// Create options that only ignore attribute order
+    pub fn strict() -> EqOptions {
+        EqOptions {
+            ignore_whitespace_text: false,
+            ignore_prefixes: false,
+            cdata_as_text: false,
+        }
+    }
+
+    /// Treat text made up only of whitespace as absent
+    pub fn ignore_whitespace_text(&mut self, ignore: bool) -> &mut Self {
+        self.ignore_whitespace_text = ignore;
+        self
+    }
+
+    /// Compare element and attribute names by namespace URI rather than by prefix, where the
+    /// prefix is bound
+    ///
+    /// Otherwise names must have the same prefix, bound to the same URI.
+    pub fn ignore_prefixes(&mut self, ignore: bool) -> &mut Self {
+        self.ignore_prefixes = ignore;
+        self
+    }
+
+    /// Compare text and CDATA joined together, so that `<![CDATA[a<b]]>` equals `a&lt;b`
+    pub fn cdata_as_text(&mut self, merge: bool) -> &mut Self {
+        self.cdata_as_text = merge;
+        self
+    }
+
+    pub(crate) fn eq(&self, a: &Element, b: &Element) -> bool {
+        self.first_difference(a, b).is_none()
+    }
+
+    /// Find the first difference between two trees in document order, returning its path and a
+    /// description of it
+    pub(crate) fn first_difference(&self, a: &Element, b: &Element) -> Option<(String, String)> {
+        enum Task<'e> {
+            Compare(Rc<Scope<'e>>, Rc<Scope<'e>>, String),
+            Extra(String, String),
+        }
+
+        let path = format!("/{}", a.qualified_name());
+        let mut pending = vec![Task::Compare(Scope::root(a), Scope::root(b), path)];
+        while let Some(task) = pending.pop() {
+            let (a_scope, b_scope, path) = match task {
+                Task::Compare(a, b, path) => (a, b, path),
+                Task::Extra(path, msg) => return Some((path, msg)),
+            };
+            let (a, b) = (a_scope.element, b_scope.element);
+            if let Some((step, msg)) = self.shallow_difference(&a_scope, &b_scope) {
+                return Some((path + &step, msg));
+            }
+
+            // Extra children come after the ones both sides have
+            let (left, right) = (a.children.len(), b.children.len());
+            if left != right {
+                let (parent, extra, side) = if left > right {
+                    (a, right, "left")
+                } else {
+                    (b, left, "right")
+                };
+                pending.push(Task::Extra(
+                    child_path(&path, parent, extra),
+                    format!("element is only on the {}", side),
+                ));
+            }
+            let paths = child_paths(&path, a);
+            for ((x, y), path) in a.children.iter().zip(b.children.iter()).zip(paths).rev() {
+                pending.push(Task::Compare(
+                    Scope::child(&a_scope, x),
+                    Scope::child(&b_scope, y),
+                    path,
+                ));
+            }
+        }
+        None
+    }
+
+    /// Describe the first difference between two elements, ignoring their children, along with
+    /// the path step to the differing attribute or text if there is one
+    fn shallow_difference(&self, a_scope: &Scope, b_scope: &Scope) -> Option<(String, String)> {
+        let (a, b) = (a_scope.element, b_scope.element);
+        if a.name != b.name
+            || self.namespace(a_scope, a.prefix.as_deref())
+                != self.namespace(b_scope, b.prefix.as_deref())
+        {
+            return Some((
+                String::new(),
+                format!(
+                    "element is <{}> on the left and <{}> on the right",
+                    a.qualified_name(),
+                    b.qualified_name()
+                ),
+            ));
+        }
+
+        let texts = if self.cdata_as_text {
+            vec![("text", joined(a), joined(b))]
+        } else {
+            vec![
+                ("text", a.text.clone(), b.text.clone()),
+                ("CDATA", a.cdata.clone(), b.cdata.clone()),
+            ]
+        };
+        for (what, x, y) in texts {
+            let (x, y) = (self.text(&x), self.text(&y));
+            if x != y {
+                return Some((
+                    "/text()".to_owned(),
+                    format!("{} is {:?} on the left and {:?} on the right", what, x, y),
+                ));
+            }
+        }
+
+        for (name, value) in &a.attributes {
+            let key = self.attribute(a_scope, name);
+            match b
+                .attributes
+                .iter()
+                .find(|(n, _)| self.attribute(b_scope, n) == key)
+            {
+                None => {
+                    return Some((
+                        format!("/@{}", name),
+                        format!("attribute '{}' is only on the left", name),
+                    ))
+                }
+                Some((_, v)) if v != value => {
+                    return Some((
+                        format!("/@{}", name),
+                        format!(
+                            "attribute '{}' is {:?} on the left and {:?} on the right",
+                            name, value, v
+                        ),
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+        for name in b.attributes.keys() {
+            let key = self.attribute(b_scope, name);
+            if !a
+                .attributes
+                .keys()
+                .any(|n| self.attribute(a_scope, n) == key)
+            {
+                return Some((
+                    format!("/@{}", name),
+                    format!("attribute '{}' is only on the right", name),
+                ));
+            }
+        }
+        None
+    }
+
+    fn text<'t>(&self, text: &'t Option<String>) -> Option<&'t str> {
+        text.as_deref()
+            .filter(|t| !(self.ignore_whitespace_text && t.trim().is_empty()))
+    }
+
+    /// The namespace of a name with `prefix` in `scope`: its URI if prefixes are ignored and
+    /// it's bound, otherwise the prefix along with the URI it's bound to
+    fn namespace<'e>(&self, scope: &Scope<'e>, prefix: Option<&'e str>) -> Namespace<'e> {
+        let uri = scope.namespace(prefix.unwrap_or(""));
+        match uri {
+            Some(uri) if self.ignore_prefixes => Namespace::Uri(uri),
+            _ => Namespace::Prefixed(prefix, uri),
+        }
+    }
+
+    fn attribute<'e>(&self, scope: &Scope<'e>, name: &'e str) -> (Namespace<'e>, &'e str) {
+        match name.find(':') {
+            Some(colon) => (
+                self.namespace(scope, Some(&name[..colon])),
+                &name[colon + 1..],
+            ),
+            // Unprefixed attributes are never in the default namespace
+            None => (Namespace::Prefixed(None, None), name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Namespace<'a> {
+    Prefixed(Option<&'a str>, Option<&'a str>),
+    Uri(&'a str),
+}
+
+fn joined(element: &Element) -> Option<String> {
+    match (&element.text, &element.cdata) {
+        (None, None) => None,
+        (text, cdata) => Some(format!(
+            "{}{}",
+            text.as_deref().unwrap_or(""),
+            cdata.as_deref().unwrap_or("")
+        )),
+    }
+}
@@ -0,0 +1,108 @@
+//! Helpers for comparing XML in tests, enabled by the `testing` feature
+
+use std::fmt;
+
+use crate::{DiffOptions, Document, Element, EqOptions};
+
+/// Something that can be turned into an `Element` for comparison by `assert_xml_eq!`
+///
+/// Strings are parsed as documents, and must have a root element.
+pub trait AsXml {
+    /// Get the element to compare, panicking if there isn't one
+    fn as_xml(&self) -> Element;
+}
+
+impl AsXml for str {
+    #[track_caller]
+    fn as_xml(&self) -> Element {
+        match Document::parse(self.as_bytes()) {
+            Ok(Document {
+                root: Some(root), ..
+            }) => root,
+            Ok(_) => panic!("XML has no root element:\n{}", self),
+            Err(err) => panic!("XML failed to parse: {}\n{}", err, self),
+        }
+    }
+}
+
+impl AsXml for String {
+    #[track_caller]
+    fn as_xml(&self) -> Element {
+        self.as_str().as_xml()
+    }
+}
+
+impl AsXml for Element {
+    fn as_xml(&self) -> Element {
+        self.clone()
+    }
+}
+
+impl AsXml for Document {
+    #[track_caller]
+    fn as_xml(&self) -> Element {
+        self.root.clone().expect("Document has no root element")
+    }
+}
+
+impl<T: AsXml + ?Sized> AsXml for &T {
+    #[track_caller]
+    fn as_xml(&self) -> Element {
+        (**self).as_xml()
+    }
+}
+
+/// Panic unless `left` and `right` are semantically equal under `options`
+///
+/// The panic message names the first divergent path and includes a diff of the two trees.
+/// This is what `assert_xml_eq!` calls.
+#[track_caller]
+pub fn assert_xml_eq<L, R>(left: &L, right: &R, options: &EqOptions, msg: Option<fmt::Arguments>)
+where
+    L: AsXml + ?Sized,
+    R: AsXml + ?Sized,
+{
+    let (left, right) = (left.as_xml(), right.as_xml());
+    let (path, difference) = match options.first_difference(&left, &right) {
+        Some(found) => found,
+        None => return,
+    };
+
+    let diff = left.diff_with(
+        &right,
+        DiffOptions::new()
+            .ignore_attribute_order(true)
+            .ignore_whitespace_text(true),
+    );
+    let msg = msg.map(|msg| format!(": {}", msg)).unwrap_or_default();
+    panic!(
+        "assertion failed: `(left == right)` as XML{}\n first difference at {}: {}\n diff:\n{}\n  left: {}\n right: {}",
+        msg, path, difference, diff, left, right
+    );
+}
+
+/// Assert that two pieces of XML are semantically equal, as `Element::semantically_eq` with
+/// `EqOptions::new()` checks
+///
+/// Either side can be a string, an `Element` or a `Document`. On failure, the first divergent
+/// path is reported alongside a diff of the trees.
+///
+/// ```
+/// use treexml::assert_xml_eq;
+///
+/// assert_xml_eq!(r#"<a x="1" y="2"><b /></a>"#, "<a y='2' x='1'>\n  <b/>\n</a>");
+/// ```
+#[macro_export]
+macro_rules! assert_xml_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        $crate::testing::assert_xml_eq(&$left, &$right, &$crate::EqOptions::new(), None)
+    };
+    ($left:expr, $right:expr, $($arg:tt)+) => {
+        $crate::testing::assert_xml_eq(
+            &$left,
+            &$right,
+            &$crate::EqOptions::new(),
+            Some(format_args!($($arg)+)),
+        )
+    };
+}
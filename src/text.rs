@@ -0,0 +1,81 @@
+//! Options for `Element::text_content_with`
+
+use std::collections::HashSet;
+
+use crate::traverse::{Traverse, TraverseEvent};
+use crate::Element;
+
+/// Options controlling how `Element::text_content_with` joins text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextOptions {
+    blocks: HashSet<String>,
+    separator: String,
+    normalize_whitespace: bool,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        TextOptions {
+            blocks: HashSet::new(),
+            separator: "\n".to_owned(),
+            normalize_whitespace: false,
+        }
+    }
+}
+
+impl TextOptions {
+    /// Create options that join all text as it is
+    pub fn new() -> TextOptions {
+        TextOptions::default()
+    }
+
+    /// Treat elements named `name` as blocks, separating their text from the text around them
+    pub fn block<S: ToString>(&mut self, name: S) -> &mut Self {
+        self.blocks.insert(name.to_string());
+        self
+    }
+
+    /// Set the separator inserted between the text of blocks, `"\n"` by default
+    pub fn separator<S: ToString>(&mut self, separator: S) -> &mut Self {
+        self.separator = separator.to_string();
+        self
+    }
+
+    /// Collapse runs of whitespace into single spaces, and trim it from the ends of blocks
+    pub fn normalize_whitespace(&mut self, normalize: bool) -> &mut Self {
+        self.normalize_whitespace = normalize;
+        self
+    }
+
+    pub(crate) fn text_content(&self, element: &Element) -> String {
+        let mut segments = vec![String::new()];
+        for event in Traverse::new(element) {
+            let el = event.element();
+            if event.depth() > 0 && self.blocks.contains(&el.name) {
+                segments.push(String::new());
+            }
+            if let TraverseEvent::Enter { .. } = event {
+                let segment = segments.last_mut().unwrap();
+                segment.extend(el.text.as_deref());
+                segment.extend(el.cdata.as_deref());
+            }
+        }
+
+        let mut content = String::new();
+        for segment in segments {
+            let segment = if self.normalize_whitespace {
+                segment.split_whitespace().collect::<Vec<_>>().join(" ")
+            } else {
+                segment
+            };
+            if segment.is_empty() {
+                continue;
+            }
+            if !content.is_empty() {
+                content.push_str(&self.separator);
+            }
+            content.push_str(&segment);
+        }
+        content
+    }
+}
@@ -0,0 +1,221 @@
+//! Iterators over the descendants of an element
+//!
+//! All of them keep an explicit stack or queue rather than recursing, so they're safe to use
+//! on arbitrarily deep trees.
+
+use std::collections::VecDeque;
+use std::slice::{Iter, IterMut};
+
+use indexmap::IndexMap;
+
+use crate::Element;
+
+/// Depth-first, pre-order iterator over the descendants of an element
+///
+/// Returned by `Element::descendants`.
+#[derive(Debug, Clone)]
+pub struct Descendants<'a> {
+    stack: Vec<Iter<'a, Element>>,
+}
+
+impl<'a> Descendants<'a> {
+    pub(crate) fn new(element: &'a Element) -> Descendants<'a> {
+        Descendants {
+            stack: vec![element.children.iter()],
+        }
+    }
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = &'a Element;
+
+    fn next(&mut self) -> Option<&'a Element> {
+        loop {
+            match self.stack.last_mut()?.next() {
+                Some(el) => {
+                    self.stack.push(el.children.iter());
+                    return Some(el);
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+/// A descendant borrowed mutably, with everything but its children
+///
+/// The children are yielded separately by `DescendantsMut`, which is why they can't be
+/// borrowed here.
+#[derive(Debug)]
+pub struct ElementMut<'a> {
+    /// Tag prefix
+    pub prefix: &'a mut Option<String>,
+    /// Tag name
+    pub name: &'a mut String,
+    /// Tag attributes
+    pub attributes: &'a mut IndexMap<String, String>,
+    /// Namespace bindings declared on the element, not including those inherited from its
+    /// ancestors, as in `Element::namespaces`
+    pub namespaces: &'a mut IndexMap<String, String>,
+    /// Contents of the element
+    pub text: &'a mut Option<String>,
+    /// CDATA contents of the element
+    pub cdata: &'a mut Option<String>,
+}
+
+/// Depth-first, pre-order iterator over the descendants of an element; yields mutable borrows
+///
+/// Returned by `Element::descendants_mut`.
+#[derive(Debug)]
+pub struct DescendantsMut<'a> {
+    stack: Vec<IterMut<'a, Element>>,
+}
+
+impl<'a> DescendantsMut<'a> {
+    pub(crate) fn new(element: &'a mut Element) -> DescendantsMut<'a> {
+        DescendantsMut {
+            stack: vec![element.children.iter_mut()],
+        }
+    }
+}
+
+impl<'a> Iterator for DescendantsMut<'a> {
+    type Item = ElementMut<'a>;
+
+    fn next(&mut self) -> Option<ElementMut<'a>> {
+        loop {
+            match self.stack.last_mut()?.next() {
+                Some(el) => {
+                    let Element {
+                        prefix,
+                        name,
+                        attributes,
+                        namespaces,
+                        children,
+                        text,
+                        cdata,
+                    } = el;
+                    self.stack.push(children.iter_mut());
+                    return Some(ElementMut {
+                        prefix,
+                        name,
+                        attributes,
+                        namespaces,
+                        text,
+                        cdata,
+                    });
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Breadth-first iterator over the descendants of an element
+///
+/// Returned by `Element::breadth_first`.
+#[derive(Debug, Clone)]
+pub struct BreadthFirst<'a> {
+    queue: VecDeque<&'a Element>,
+}
+
+impl<'a> BreadthFirst<'a> {
+    pub(crate) fn new(element: &'a Element) -> BreadthFirst<'a> {
+        BreadthFirst {
+            queue: element.children.iter().collect(),
+        }
+    }
+}
+
+impl<'a> Iterator for BreadthFirst<'a> {
+    type Item = &'a Element;
+
+    fn next(&mut self) -> Option<&'a Element> {
+        let el = self.queue.pop_front()?;
+        self.queue.extend(el.children.iter());
+        Some(el)
+    }
+}
+
+/// An event yielded by `Traverse`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraverseEvent<'a> {
+    /// Entering an element, before any of its children
+    Enter {
+        /// The element entered
+        element: &'a Element,
+        /// Its depth below the element traversal started from, which is at depth 0
+        depth: usize,
+    },
+    /// Leaving an element, after all of its children
+    Leave {
+        /// The element left
+        element: &'a Element,
+        /// Its depth below the element traversal started from, which is at depth 0
+        depth: usize,
+    },
+}
+
+impl<'a> TraverseEvent<'a> {
+    /// The element entered or left
+    pub fn element(&self) -> &'a Element {
+        match *self {
+            TraverseEvent::Enter { element, .. } | TraverseEvent::Leave { element, .. } => element,
+        }
+    }
+
+    /// The depth of the element entered or left
+    pub fn depth(&self) -> usize {
+        match *self {
+            TraverseEvent::Enter { depth, .. } | TraverseEvent::Leave { depth, .. } => depth,
+        }
+    }
+}
+
+/// Iterator over enter and leave events for an element and its descendants, in document order
+///
+/// Returned by `Element::traverse`.
+#[derive(Debug, Clone)]
+pub struct Traverse<'a> {
+    start: Option<&'a Element>,
+    stack: Vec<(&'a Element, Iter<'a, Element>)>,
+}
+
+impl<'a> Traverse<'a> {
+    pub(crate) fn new(element: &'a Element) -> Traverse<'a> {
+        Traverse {
+            start: Some(element),
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Iterator for Traverse<'a> {
+    type Item = TraverseEvent<'a>;
+
+    fn next(&mut self) -> Option<TraverseEvent<'a>> {
+        if let Some(element) = self.start.take() {
+            self.stack.push((element, element.children.iter()));
+            return Some(TraverseEvent::Enter { element, depth: 0 });
+        }
+
+        let depth = self.stack.len().checked_sub(1)?;
+        match self.stack[depth].1.next() {
+            Some(element) => {
+                self.stack.push((element, element.children.iter()));
+                Some(TraverseEvent::Enter {
+                    element,
+                    depth: depth + 1,
+                })
+            }
+            None => {
+                let (element, _) = self.stack.pop().unwrap();
+                Some(TraverseEvent::Leave { element, depth })
+            }
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Checks of names and characters against the XML productions, as done by `Element::validate`
+
+use std::fmt;
+
+use crate::diff::child_paths;
+use crate::{Element, XmlVersion};
+
+/// What `Element::validate_with` does with characters the XML version doesn't allow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidChars {
+    /// Report them as problems
+    Report,
+    /// Accept the ones that can be written as character references, which in XML 1.1 are the
+    /// restricted control characters, and report the rest
+    Escape,
+    /// Remove the ones that can't be written at all from text, CDATA and attribute values,
+    /// and accept the rest as `Escape` does
+    Strip,
+}
+
+/// A problem with a name or character, as reported by `Element::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// Path to the element, attribute or text with the problem
+    pub path: String,
+    /// What's wrong
+    pub kind: ProblemKind,
+    /// The character at fault, for problems with characters
+    pub character: Option<char>,
+    /// The XML version the name or character was checked against
+    pub version: XmlVersion,
+}
+
+/// The kinds of `Problem`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// An element name that isn't a name without a colon
+    ElementName,
+    /// An element prefix that isn't a name without a colon
+    ElementPrefix,
+    /// An attribute name that isn't a name with at most one prefix
+    AttributeName,
+    /// A character the version only allows as a character reference, which wasn't accepted
+    Unescaped,
+    /// A character the version only allows as a character reference, in CDATA where
+    /// references can't be written
+    InCData,
+    /// A character the version doesn't allow at all
+    Disallowed,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let version = match self.version {
+            XmlVersion::Version10 => "XML 1.0",
+            XmlVersion::Version11 => "XML 1.1",
+        };
+        let character = self.character.map_or(0, u32::from);
+        match self.kind {
+            ProblemKind::ElementName => write!(f, "'{}': invalid element name", self.path),
+            ProblemKind::ElementPrefix => write!(f, "'{}': invalid element prefix", self.path),
+            ProblemKind::AttributeName => write!(f, "'{}': invalid attribute name", self.path),
+            ProblemKind::Unescaped => write!(
+                f,
+                "'{}': character U+{:04X} must be escaped in {}",
+                self.path, character, version
+            ),
+            ProblemKind::InCData => write!(
+                f,
+                "'{}': character U+{:04X} can't be written in CDATA in {}",
+                self.path, character, version
+            ),
+            ProblemKind::Disallowed => write!(
+                f,
+                "'{}': character U+{:04X} isn't allowed in {}",
+                self.path, character, version
+            ),
+        }
+    }
+}
+
+/// How a character may appear in a document of a given version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CharUse {
+    Literal,
+    Reference,
+    Invalid,
+}
+
+pub(crate) fn char_use(c: char, version: XmlVersion) -> CharUse {
+    match (c, version) {
+        ('\0', _) | ('\u{FFFE}', _) | ('\u{FFFF}', _) => CharUse::Invalid,
+        ('\t', _) | ('\n', _) | ('\r', _) => CharUse::Literal,
+        ('\u{1}'..='\u{1F}', XmlVersion::Version10) => CharUse::Invalid,
+        ('\u{1}'..='\u{1F}', XmlVersion::Version11) => CharUse::Reference,
+        ('\u{7F}'..='\u{84}', XmlVersion::Version11)
+        | ('\u{86}'..='\u{9F}', XmlVersion::Version11) => CharUse::Reference,
+        _ => CharUse::Literal,
+    }
+}
+
+fn is_name_start_char(c: char) -> bool {
+    matches!(c,
+        'A'..='Z' | '_' | 'a'..='z' | '\u{C0}'..='\u{D6}' | '\u{D8}'..='\u{F6}'
+        | '\u{F8}'..='\u{2FF}' | '\u{370}'..='\u{37D}' | '\u{37F}'..='\u{1FFF}'
+        | '\u{200C}'..='\u{200D}' | '\u{2070}'..='\u{218F}' | '\u{2C00}'..='\u{2FEF}'
+        | '\u{3001}'..='\u{D7FF}' | '\u{F900}'..='\u{FDCF}' | '\u{FDF0}'..='\u{FFFD}'
+        | '\u{10000}'..='\u{EFFFF}')
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start_char(c)
+        || matches!(c,
+            '-' | '.' | '0'..='9' | '\u{B7}' | '\u{300}'..='\u{36F}' | '\u{203F}'..='\u{2040}')
+}
+
+/// Whether `name` matches the `NCName` production, a name without a colon
+///
+/// XML 1.0 (fifth edition) and 1.1 allow the same name characters.
+pub(crate) fn is_ncname(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(is_name_start_char) && chars.all(is_name_char)
+}
+
+/// Whether `name` is a valid attribute name, with at most one prefix
+pub(crate) fn is_attribute_name(name: &str) -> bool {
+    match name.find(':') {
+        Some(colon) => is_ncname(&name[..colon]) && is_ncname(&name[colon + 1..]),
+        None => is_ncname(name),
+    }
+}
+
+/// Remove the characters `version` doesn't allow from `root` and its descendants
+pub(crate) fn strip(root: &mut Element, version: XmlVersion) {
+    let allowed = |c: char| char_use(c, version) != CharUse::Invalid;
+    let strip_str = |s: &mut String| {
+        if !s.chars().all(allowed) {
+            s.retain(allowed);
+        }
+    };
+
+    let mut pending = vec![root];
+    while let Some(el) = pending.pop() {
+        el.text.iter_mut().for_each(strip_str);
+        el.cdata.iter_mut().for_each(strip_str);
+        el.attributes.values_mut().for_each(strip_str);
+        // Only unshare children that need changing
+        if el.children.iter().any(|child| needs_strip(child, version)) {
+            pending.extend(el.children.iter_mut());
+        }
+    }
+}
+
+fn needs_strip(root: &Element, version: XmlVersion) -> bool {
+    let invalid = |s: &String| s.chars().any(|c| char_use(c, version) == CharUse::Invalid);
+    let mut pending = vec![root];
+    while let Some(el) = pending.pop() {
+        if el.text.iter().chain(el.cdata.iter()).any(invalid) || el.attributes.values().any(invalid)
+        {
+            return true;
+        }
+        pending.extend(el.children.iter());
+    }
+    false
+}
+
+/// Every problem with the names and characters in `root` and its descendants, in document
+/// order
+pub(crate) fn problems(root: &Element, version: XmlVersion, chars: InvalidChars) -> Vec<Problem> {
+    let problem = |path: String, kind, character| Problem {
+        path,
+        kind,
+        character,
+        version,
+    };
+    let check_chars = |s: &str, cdata: bool| {
+        s.chars().find_map(|c| {
+            let kind = match char_use(c, version) {
+                CharUse::Literal => return None,
+                CharUse::Reference if cdata => ProblemKind::InCData,
+                CharUse::Reference if chars != InvalidChars::Report => return None,
+                CharUse::Reference => ProblemKind::Unescaped,
+                CharUse::Invalid => ProblemKind::Disallowed,
+            };
+            Some((kind, c))
+        })
+    };
+
+    let mut problems = Vec::new();
+    let mut pending = vec![(root, format!("/{}", root.qualified_name()))];
+    while let Some((el, path)) = pending.pop() {
+        if !is_ncname(&el.name) {
+            problems.push(problem(path.clone(), ProblemKind::ElementName, None));
+        }
+        if let Some(ref prefix) = el.prefix {
+            if !is_ncname(prefix) {
+                problems.push(problem(path.clone(), ProblemKind::ElementPrefix, None));
+            }
+        }
+        for (name, value) in &el.attributes {
+            let attribute = format!("{}/@{}", path, name);
+            if !is_attribute_name(name) {
+                problems.push(problem(attribute.clone(), ProblemKind::AttributeName, None));
+            }
+            if let Some((kind, c)) = check_chars(value, false) {
+                problems.push(problem(attribute, kind, Some(c)));
+            }
+        }
+        let text = el
+            .text
+            .as_deref()
+            .and_then(|text| check_chars(text, false))
+            .into_iter()
+            .chain(
+                el.cdata
+                    .as_deref()
+                    .and_then(|cdata| check_chars(cdata, true)),
+            );
+        for (kind, c) in text {
+            problems.push(problem(format!("{}/text()", path), kind, Some(c)));
+        }
+
+        let paths = child_paths(&path, el);
+        for (child, path) in el.children.iter().zip(paths).rev() {
+            pending.push((child, path));
+        }
+    }
+    problems
+}
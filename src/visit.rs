@@ -0,0 +1,224 @@
+//! Visitors and transformers, driven by `Element::visit` and `Element::transform`
+//!
+//! Both walk the tree with an explicit stack rather than recursing, so they're safe to use on
+//! arbitrarily deep trees.
+
+use std::mem;
+use std::slice::Iter;
+use std::vec::IntoIter;
+
+use crate::Element;
+
+/// What a `Visitor` wants to happen after one of its methods returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisitControl {
+    /// Carry on
+    Continue,
+    /// Skip the contents of the element just visited: its text, CDATA and children. Behaves
+    /// like `Continue` when returned from anything but `visit_element`.
+    SkipChildren,
+    /// Stop visiting; no further methods are called
+    Stop,
+}
+
+/// Read-only traversal of an element tree, in document order
+///
+/// Every method defaults to doing nothing and continuing, so implementors only need to
+/// override the ones they care about.
+pub trait Visitor<'a> {
+    /// Called on entering an element, before its contents
+    fn visit_element(&mut self, _element: &'a Element) -> VisitControl {
+        VisitControl::Continue
+    }
+
+    /// Called on leaving an element, after its contents; also called for elements whose
+    /// contents were skipped
+    fn leave_element(&mut self, _element: &'a Element) -> VisitControl {
+        VisitControl::Continue
+    }
+
+    /// Called with the text of an element, before its children
+    fn visit_text(&mut self, _text: &'a str) -> VisitControl {
+        VisitControl::Continue
+    }
+
+    /// Called with the CDATA of an element, after its text and before its children
+    fn visit_cdata(&mut self, _cdata: &'a str) -> VisitControl {
+        VisitControl::Continue
+    }
+}
+
+/// What a `Transformer` wants done with the element it was given
+///
+/// To replace an element with another, assign to it and return `Keep` or `Skip`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transform {
+    /// Keep the element, with any changes made to it, and carry on into its contents
+    Keep,
+    /// Keep the element, with any changes made to it, without transforming its contents
+    Skip,
+    /// Put any number of elements in its place; the replacements aren't transformed
+    Expand(Vec<Element>),
+    /// Remove the element
+    Remove,
+    /// Keep the element and stop transforming; the rest of the tree is left as it is
+    Stop,
+}
+
+/// Rewriting of an element tree, in document order
+///
+/// Every method defaults to leaving things as they are, so implementors only need to override
+/// the ones they care about.
+pub trait Transformer {
+    /// Called on entering an element, before its contents; the element can be changed in
+    /// place, and the result decides what happens to it and whether to descend into it
+    fn transform_element(&mut self, _element: &mut Element) -> Transform {
+        Transform::Keep
+    }
+
+    /// Called on leaving an element, after its contents have been transformed; `Skip` behaves
+    /// like `Keep` here
+    fn leave_element(&mut self, _element: &mut Element) -> Transform {
+        Transform::Keep
+    }
+
+    /// Called with the text of an element; returning `None` removes it
+    fn transform_text(&mut self, text: String) -> Option<String> {
+        Some(text)
+    }
+
+    /// Called with the CDATA of an element; returning `None` removes it
+    fn transform_cdata(&mut self, cdata: String) -> Option<String> {
+        Some(cdata)
+    }
+}
+
+pub(crate) fn visit<'a, V: Visitor<'a>>(element: &'a Element, visitor: &mut V) {
+    let mut stack: Vec<(&'a Element, Iter<'a, Element>)> = Vec::new();
+    let mut entering = Some(element);
+
+    loop {
+        if let Some(el) = entering.take() {
+            match enter(el, visitor) {
+                VisitControl::Continue => stack.push((el, el.children.iter())),
+                VisitControl::SkipChildren => {
+                    if visitor.leave_element(el) == VisitControl::Stop {
+                        return;
+                    }
+                }
+                VisitControl::Stop => return,
+            }
+        }
+
+        let (el, children) = match stack.last_mut() {
+            Some(top) => top,
+            None => return,
+        };
+        match children.next() {
+            Some(child) => entering = Some(child),
+            None => {
+                let el = *el;
+                stack.pop();
+                if visitor.leave_element(el) == VisitControl::Stop {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Visit an element and its text and CDATA; `Continue` means carry on into its children
+fn enter<'a, V: Visitor<'a>>(element: &'a Element, visitor: &mut V) -> VisitControl {
+    let control = visitor.visit_element(element);
+    if control != VisitControl::Continue {
+        return control;
+    }
+    if let Some(ref text) = element.text {
+        if visitor.visit_text(text) == VisitControl::Stop {
+            return VisitControl::Stop;
+        }
+    }
+    if let Some(ref cdata) = element.cdata {
+        if visitor.visit_cdata(cdata) == VisitControl::Stop {
+            return VisitControl::Stop;
+        }
+    }
+    VisitControl::Continue
+}
+
+/// An element whose children have been taken out to be transformed
+struct Frame {
+    element: Element,
+    pending: IntoIter<Element>,
+    done: Vec<Element>,
+}
+
+impl Frame {
+    fn new<T: Transformer>(mut element: Element, transformer: &mut T) -> Frame {
+        element.text = element
+            .text
+            .take()
+            .and_then(|t| transformer.transform_text(t));
+        element.cdata = element
+            .cdata
+            .take()
+            .and_then(|c| transformer.transform_cdata(c));
+        let pending = mem::take(&mut element.children).into_vec().into_iter();
+        Frame {
+            element,
+            pending,
+            done: Vec::new(),
+        }
+    }
+
+    fn finish(mut self) -> Element {
+        self.done.extend(self.pending);
+        self.element.children = self.done.into();
+        self.element
+    }
+}
+
+pub(crate) fn transform<T: Transformer>(element: &mut Element, transformer: &mut T) {
+    let root = mem::take(element);
+    let mut stack = vec![Frame::new(root, transformer)];
+    let mut stopped = false;
+
+    loop {
+        let top = stack.last_mut().unwrap();
+        let next = if stopped { None } else { top.pending.next() };
+
+        let (outcome, el) = match next {
+            Some(mut child) => match transformer.transform_element(&mut child) {
+                Transform::Keep => {
+                    stack.push(Frame::new(child, transformer));
+                    continue;
+                }
+                outcome => (outcome, child),
+            },
+            None => {
+                let el = stack.pop().unwrap().finish();
+                if stack.is_empty() {
+                    *element = el;
+                    return;
+                }
+                let mut el = el;
+                if stopped {
+                    (Transform::Keep, el)
+                } else {
+                    (transformer.leave_element(&mut el), el)
+                }
+            }
+        };
+
+        let done = &mut stack.last_mut().unwrap().done;
+        match outcome {
+            Transform::Keep | Transform::Skip => done.push(el),
+            Transform::Expand(replacements) => done.extend(replacements),
+            Transform::Remove => {}
+            Transform::Stop => {
+                done.push(el);
+                stopped = true;
+            }
+        }
+    }
+}
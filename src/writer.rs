@@ -0,0 +1,858 @@
+//! Streaming output, as used by `Document::write`
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Write};
+use std::str;
+
+use crate::validate::{self, char_use, is_attribute_name, is_ncname, CharUse};
+use crate::{Element, InvalidChars, Problem, ProblemKind, TreexmlError, XmlVersion};
+
+/// Options controlling how XML is written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteOptions {
+    document_decl: bool,
+    indent: bool,
+    indent_str: String,
+    preserve: HashSet<String>,
+    max_line_width: usize,
+    wrap_attributes: AttributeWrap,
+    align_attributes: bool,
+    sort_attributes: bool,
+    attribute_priority: Vec<String>,
+    sort_children: HashMap<String, SortKey>,
+    normalize_whitespace: bool,
+    escape_text: EscapeOptions,
+    escape_attributes: EscapeOptions,
+    check_names: bool,
+    validate: bool,
+    strip_invalid_chars: bool,
+}
+
+/// When `TreeWriter` puts each attribute of an element on its own line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeWrap {
+    /// Keep attributes on the line of their tag
+    Never,
+    /// Wrap the attributes of every element with more than one
+    Always,
+    /// Wrap attributes when the start tag would go past the maximum line width
+    WhenTooLong,
+}
+
+/// What `TreeWriter` sorts the children of an element by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SortKey {
+    /// The qualified name of the child
+    Name,
+    /// The value of an attribute, with children that lack it first
+    Attribute(String),
+    /// The text of the child
+    Text,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            document_decl: true,
+            indent: true,
+            indent_str: "  ".to_owned(),
+            preserve: HashSet::new(),
+            max_line_width: 80,
+            wrap_attributes: AttributeWrap::Never,
+            align_attributes: true,
+            sort_attributes: false,
+            attribute_priority: Vec::new(),
+            sort_children: HashMap::new(),
+            normalize_whitespace: false,
+            escape_text: EscapeOptions::text(),
+            escape_attributes: EscapeOptions::attribute(),
+            check_names: true,
+            validate: false,
+            strip_invalid_chars: false,
+        }
+    }
+}
+
+impl WriteOptions {
+    /// Create options that write a document declaration and indent with two spaces, as
+    /// `Document::write` does
+    pub fn new() -> WriteOptions {
+        WriteOptions::default()
+    }
+
+    /// Create options for output that only depends on the content of a tree, with sorted
+    /// attributes and normalized whitespace
+    ///
+    /// Attribute values are always quoted with `"`, and escaped as set by `escape_attributes`.
+    pub fn normalized() -> WriteOptions {
+        let mut options = WriteOptions::default();
+        options.sort_attributes(true).normalize_whitespace(true);
+        options
+    }
+
+    /// Write the `<?xml ...?>` declaration before the first element
+    pub fn document_decl(&mut self, write: bool) -> &mut Self {
+        self.document_decl = write;
+        self
+    }
+
+    /// Put each element on its own line, indented by its depth
+    pub fn indent(&mut self, indent: bool) -> &mut Self {
+        self.indent = indent;
+        self
+    }
+
+    /// Set the string repeated to indent a line, two spaces by default
+    pub fn indent_string<S: ToString>(&mut self, indent_str: S) -> &mut Self {
+        self.indent_str = indent_str.to_string();
+        self
+    }
+
+    /// Never reindent the contents of elements named `name`, as if they had
+    /// `xml:space="preserve"`
+    pub fn preserve_space<S: ToString>(&mut self, name: S) -> &mut Self {
+        self.preserve.insert(name.to_string());
+        self
+    }
+
+    /// Set the line width that `AttributeWrap::WhenTooLong` wraps attributes to stay within,
+    /// 80 by default
+    pub fn max_line_width(&mut self, width: usize) -> &mut Self {
+        self.max_line_width = width;
+        self
+    }
+
+    /// Set when attributes are put one per line, never by default
+    pub fn wrap_attributes(&mut self, wrap: AttributeWrap) -> &mut Self {
+        self.wrap_attributes = wrap;
+        self
+    }
+
+    /// Align wrapped attributes with the first, which stays on the line of the tag, rather
+    /// than indenting them one level past the tag
+    pub fn align_attributes(&mut self, align: bool) -> &mut Self {
+        self.align_attributes = align;
+        self
+    }
+
+    /// Write attributes sorted by name, after those given to `attribute_priority`
+    pub fn sort_attributes(&mut self, sort: bool) -> &mut Self {
+        self.sort_attributes = sort;
+        self
+    }
+
+    /// Put attributes named `name` before the others when sorting them, in the order they're
+    /// given
+    pub fn attribute_priority<S: ToString>(&mut self, name: S) -> &mut Self {
+        self.attribute_priority.push(name.to_string());
+        self
+    }
+
+    /// Write the children of elements named `name` sorted by `key`, keeping the order of
+    /// children with equal keys
+    pub fn sort_children<S: ToString>(&mut self, name: S, key: SortKey) -> &mut Self {
+        self.sort_children.insert(name.to_string(), key);
+        self
+    }
+
+    /// Collapse runs of whitespace in text into single spaces, trimming it unless the element
+    /// has children, and leaving text where space is preserved as it is
+    pub fn normalize_whitespace(&mut self, normalize: bool) -> &mut Self {
+        self.normalize_whitespace = normalize;
+        self
+    }
+
+    /// Set which characters are escaped in text, `EscapeOptions::text()` by default
+    pub fn escape_text(&mut self, escape: &EscapeOptions) -> &mut Self {
+        self.escape_text = EscapeOptions {
+            attribute: false,
+            ..*escape
+        };
+        self
+    }
+
+    /// Set which characters are escaped in attribute values, `EscapeOptions::attribute()` by
+    /// default
+    pub fn escape_attributes(&mut self, escape: &EscapeOptions) -> &mut Self {
+        self.escape_attributes = EscapeOptions {
+            attribute: true,
+            ..*escape
+        };
+        self
+    }
+
+    /// Fail with `TreexmlError::InvalidName` on element and attribute names that aren't valid
+    /// XML names, rather than writing them as they are, which is the default
+    pub fn check_names(&mut self, check: bool) -> &mut Self {
+        self.check_names = check;
+        self
+    }
+
+    /// Check each element written whole against the productions of the document's version
+    /// before writing any of it, failing with every problem found as `validate` would
+    ///
+    /// Characters the version only allows as references are accepted in text and attribute
+    /// values, since they're written as references.
+    pub fn validate(&mut self, validate: bool) -> &mut Self {
+        self.validate = validate;
+        self
+    }
+
+    /// Drop characters the document's version doesn't allow from text, CDATA and attribute
+    /// values, rather than failing with `TreexmlError::InvalidXml`, which is the default
+    ///
+    /// CDATA holding characters the version only allows as references is written as text.
+    pub fn strip_invalid_chars(&mut self, strip: bool) -> &mut Self {
+        self.strip_invalid_chars = strip;
+        self
+    }
+}
+
+/// What has been written inside an open element, for indentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wrote {
+    Nothing,
+    Markup,
+    Text,
+}
+
+/// The indentation state of an open element
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Frame {
+    wrote: Wrote,
+    /// Whether the contents are written as they are, without indentation
+    inline: bool,
+    /// Whether space in text is kept as it is
+    preserve: bool,
+}
+
+/// A writer that streams XML to `W` an element at a time
+///
+/// Elements can be opened and closed one by one, or written whole, so a large document never
+/// has to be held in memory. Open elements are closed by `finish`.
+///
+/// When indenting, elements with mixed content or `xml:space="preserve"`, and elements the
+/// options say to preserve, have their contents written inline so their text isn't changed.
+/// Text written into an element makes the rest of it inline too.
+///
+/// ```
+/// use treexml::{Element, TreeWriter, WriteOptions};
+///
+/// let mut out = Vec::new();
+/// let mut writer = TreeWriter::new(&mut out, WriteOptions::new().document_decl(false));
+/// writer.start_element(&Element::new("records")).unwrap();
+/// for i in 0..2 {
+///     let mut record = Element::new("record");
+///     record.text = Some(i.to_string());
+///     writer.write_element(&record).unwrap();
+/// }
+/// writer.finish().unwrap();
+///
+/// assert_eq!(
+///     String::from_utf8(out).unwrap(),
+///     "<records>\n  <record>0</record>\n  <record>1</record>\n</records>"
+/// );
+/// ```
+#[derive(Debug)]
+pub struct TreeWriter<W: Write> {
+    inner: W,
+    options: WriteOptions,
+    decl_written: bool,
+    version: XmlVersion,
+    open: Vec<String>,
+    frames: Vec<Frame>,
+    tag_unclosed: bool,
+    column: usize,
+    /// How many `]` the text written last ended with, up to two
+    brackets: usize,
+    root_written: bool,
+}
+
+impl<W: Write> TreeWriter<W> {
+    /// Create a writer to `inner`, formatting according to `options`
+    pub fn new(inner: W, options: &WriteOptions) -> TreeWriter<W> {
+        TreeWriter {
+            inner,
+            options: options.clone(),
+            decl_written: false,
+            version: XmlVersion::Version10,
+            open: Vec::new(),
+            frames: vec![Frame {
+                wrote: Wrote::Nothing,
+                inline: false,
+                preserve: false,
+            }],
+            tag_unclosed: false,
+            column: 0,
+            brackets: 0,
+            root_written: false,
+        }
+    }
+
+    /// Write the document declaration, if the options ask for one, and set the version whose
+    /// characters are allowed
+    ///
+    /// If this isn't called first, a declaration for version 1.0 in UTF-8 is written before the
+    /// first element instead.
+    ///
+    /// # Failures
+    ///
+    /// Output is always UTF-8, so any other `encoding` is an error
+    pub fn start_document(
+        &mut self,
+        version: XmlVersion,
+        encoding: &str,
+    ) -> Result<(), TreexmlError> {
+        if !encoding.eq_ignore_ascii_case("UTF-8") {
+            return Err(TreexmlError::UnsupportedEncoding {
+                encoding: encoding.to_owned(),
+            });
+        }
+        if self.decl_written {
+            return Ok(());
+        }
+        self.decl_written = true;
+        self.version = version;
+        if !self.options.document_decl {
+            return Ok(());
+        }
+        let version = match version {
+            XmlVersion::Version10 => "1.0",
+            XmlVersion::Version11 => "1.1",
+        };
+        self.before_markup()?;
+        self.put(&format!(
+            "<?xml version=\"{}\" encoding=\"UTF-8\"?>",
+            version
+        ))?;
+        self.frame_mut().wrote = Wrote::Markup;
+        Ok(())
+    }
+
+    /// Open `element`, writing its attributes, text, CDATA and children and leaving it open
+    /// for more children
+    ///
+    /// # Failures
+    ///
+    /// Fails with `TreexmlError::MultipleRoots` if no element is open and the root element has
+    /// already been written
+    pub fn start_element(&mut self, element: &Element) -> Result<(), TreexmlError> {
+        self.check_root()?;
+        self.check(element)?;
+        self.write_start(element)?;
+        for child in self.child_order(element) {
+            self.write_element(child)?;
+        }
+        Ok(())
+    }
+
+    /// Close the most recently opened element
+    ///
+    /// # Failures
+    ///
+    /// Fails with `TreexmlError::NoOpenElement` if every element has been closed
+    pub fn end_element(&mut self) -> Result<(), TreexmlError> {
+        let name = self.open.pop().ok_or(TreexmlError::NoOpenElement)?;
+
+        if self.tag_unclosed {
+            self.tag_unclosed = false;
+            self.put(" />")?;
+        } else {
+            let level = self.open.len();
+            let frame = self.frame();
+            if self.options.indent && !frame.inline && frame.wrote == Wrote::Markup {
+                self.newline(level)?;
+            }
+            self.put("</")?;
+            self.put(&name)?;
+            self.put(">")?;
+        }
+        self.frames.pop();
+        self.frame_mut().wrote = Wrote::Markup;
+        Ok(())
+    }
+
+    /// Write `element` and its descendants whole
+    ///
+    /// # Failures
+    ///
+    /// Fails like `start_element` if it would be a second root element
+    pub fn write_element(&mut self, element: &Element) -> Result<(), TreexmlError> {
+        self.check_root()?;
+        self.check(element)?;
+        element.write(self)
+    }
+
+    /// Fail if the root element has been closed, since a document only has one
+    fn check_root(&self) -> Result<(), TreexmlError> {
+        if self.open.is_empty() && self.root_written {
+            Err(TreexmlError::MultipleRoots)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Validate `element` if the options ask for it
+    fn check(&self, element: &Element) -> Result<(), TreexmlError> {
+        if !self.options.validate {
+            return Ok(());
+        }
+        let problems = validate::problems(element, self.version, InvalidChars::Escape);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(TreexmlError::InvalidXml { problems })
+        }
+    }
+
+    /// Write text inside the most recently opened element
+    pub fn write_text(&mut self, text: &str) -> Result<(), TreexmlError> {
+        let text = self.normalize(text, false);
+        self.write_normalized(&text)
+    }
+
+    fn write_normalized(&mut self, text: &str) -> Result<(), TreexmlError> {
+        let text = self.strip(text);
+        // Text split across writes can still form `]]>`
+        let brackets = match self.frame().wrote {
+            Wrote::Text => self.brackets,
+            _ => 0,
+        };
+        let escaped = self
+            .options
+            .escape_text
+            .escape(&text, self.version, brackets)
+            .map_err(|c| self.invalid_char("/text()", ProblemKind::Disallowed, c))?;
+        self.close_tag()?;
+        self.put(&escaped)?;
+        self.wrote_text();
+        let trailing = escaped.chars().rev().take_while(|&c| c == ']').count();
+        if trailing < escaped.len() {
+            self.brackets = trailing.min(2);
+        } else {
+            self.brackets = (brackets + trailing).min(2);
+        }
+        Ok(())
+    }
+
+    /// Write a CDATA section inside the most recently opened element
+    ///
+    /// A section can't contain `]]>`, so it's split between the `]]` and the `>` there. When
+    /// text escaping writes non-ASCII as references, non-ASCII CDATA is written as text.
+    pub fn write_cdata(&mut self, cdata: &str) -> Result<(), TreexmlError> {
+        let cdata = self.strip(cdata);
+        let as_text = (self.options.escape_text.non_ascii && !cdata.is_ascii())
+            || (self.options.strip_invalid_chars
+                && cdata
+                    .chars()
+                    .any(|c| char_use(c, self.version) == CharUse::Reference));
+        if as_text {
+            return self.write_normalized(&cdata);
+        }
+        for c in cdata.chars() {
+            let kind = match char_use(c, self.version) {
+                CharUse::Literal => continue,
+                CharUse::Reference => ProblemKind::InCData,
+                CharUse::Invalid => ProblemKind::Disallowed,
+            };
+            return Err(self.invalid_char("/text()", kind, c));
+        }
+        self.close_tag()?;
+        self.put("<![CDATA[")?;
+        self.put(&cdata.replace("]]>", "]]]]><![CDATA[>"))?;
+        self.put("]]>")?;
+        self.wrote_text();
+        self.brackets = 0;
+        Ok(())
+    }
+
+    /// Close every open element and return the underlying writer
+    pub fn finish(mut self) -> Result<W, TreexmlError> {
+        while !self.open.is_empty() {
+            self.end_element()?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// Write the start tag of `element` followed by its text and CDATA
+    pub(crate) fn write_start(&mut self, element: &Element) -> Result<(), TreexmlError> {
+        if self.options.check_names {
+            let invalid = Some(&element.name)
+                .filter(|name| !is_ncname(name))
+                .or_else(|| {
+                    element
+                        .attributes
+                        .keys()
+                        .find(|name| !is_attribute_name(name))
+                });
+            if let Some(name) = invalid {
+                return Err(TreexmlError::InvalidName { name: name.clone() });
+            }
+        }
+        if self.options.escape_text.non_ascii || self.options.escape_attributes.non_ascii {
+            let non_ascii = Some(&element.name)
+                .filter(|name| !name.is_ascii())
+                .or_else(|| element.attributes.keys().find(|name| !name.is_ascii()));
+            if let Some(name) = non_ascii {
+                return Err(TreexmlError::NonAsciiName { name: name.clone() });
+            }
+        }
+        self.start_document(XmlVersion::Version10, "UTF-8")?;
+        self.root_written |= self.open.is_empty();
+        self.close_tag()?;
+        self.before_markup()?;
+
+        let mut attributes: Vec<(&String, &String)> = element.attributes.iter().collect();
+        if self.options.sort_attributes {
+            let priority = &self.options.attribute_priority;
+            attributes.sort_by_key(|(name, _)| {
+                let rank = priority.iter().position(|p| p == *name);
+                (rank.unwrap_or(priority.len()), *name)
+            });
+        }
+        let attributes = attributes
+            .into_iter()
+            .map(|(name, value)| {
+                match self
+                    .options
+                    .escape_attributes
+                    .escape(&self.strip(value), self.version, 0)
+                {
+                    Ok(value) => Ok(format!("{}=\"{}\"", name, value)),
+                    Err(c) => {
+                        let path = format!("/{}/@{}", element.name, name);
+                        Err(self.invalid_char(&path, ProblemKind::Disallowed, c))
+                    }
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let wrap = attributes.len() > 1
+            && match self.options.wrap_attributes {
+                AttributeWrap::Never => false,
+                AttributeWrap::Always => true,
+                AttributeWrap::WhenTooLong => {
+                    let width = element.name.chars().count()
+                        + attributes
+                            .iter()
+                            .map(|a| a.chars().count() + 1)
+                            .sum::<usize>()
+                        + 2;
+                    self.column + width > self.options.max_line_width
+                }
+            };
+        let continuation = if self.options.align_attributes {
+            " ".repeat(self.column + element.name.chars().count() + 2)
+        } else {
+            " ".repeat(self.column) + &self.options.indent_str
+        };
+
+        self.put("<")?;
+        self.put(&element.name)?;
+        for (i, attribute) in attributes.iter().enumerate() {
+            if wrap && i > 0 {
+                self.put("\n")?;
+                self.put(&continuation)?;
+            } else {
+                self.put(" ")?;
+            }
+            self.put(attribute)?;
+        }
+        let parent = self.frame();
+        let (inherited, preserve) = match element.attributes.get("xml:space").map(String::as_str) {
+            Some("preserve") => (true, true),
+            Some("default") => (false, false),
+            _ => (parent.inline, parent.preserve),
+        };
+        let preserve = preserve || self.options.preserve.contains(&element.name);
+        self.frame_mut().wrote = Wrote::Markup;
+        self.frames.push(Frame {
+            wrote: Wrote::Nothing,
+            inline: inherited || preserve,
+            preserve,
+        });
+        self.open.push(element.name.clone());
+        self.tag_unclosed = true;
+
+        let text = element
+            .text
+            .as_ref()
+            .map(|text| self.normalize(text, element.children.is_empty()))
+            .filter(|text| !text.is_empty());
+        let mixed = (text.is_some() || element.cdata.is_some()) && !element.children.is_empty();
+        self.frame_mut().inline |= mixed;
+
+        if let Some(text) = text {
+            self.write_normalized(&text)?;
+        }
+        if let Some(ref cdata) = element.cdata {
+            self.write_cdata(cdata)?;
+        }
+        Ok(())
+    }
+
+    /// The children of `element`, in the order the options say to write them
+    pub(crate) fn child_order<'e>(&self, element: &'e Element) -> Vec<&'e Element> {
+        let mut children: Vec<&Element> = element.children.iter().collect();
+        match self.options.sort_children.get(&element.name) {
+            Some(SortKey::Name) => children.sort_by_key(|child| child.qualified_name()),
+            Some(SortKey::Attribute(name)) => {
+                children.sort_by_key(|child| child.attributes.get(name))
+            }
+            Some(SortKey::Text) => children.sort_by_key(|child| child.text.as_ref()),
+            None => {}
+        }
+        children
+    }
+
+    /// Normalize the whitespace in `text` if the options say to and space isn't preserved,
+    /// trimming the ends if it's the only content of its element
+    ///
+    /// Text that's only whitespace is dropped either way.
+    fn normalize<'t>(&self, text: &'t str, trim: bool) -> Cow<'t, str> {
+        if !self.options.normalize_whitespace || self.frame().preserve {
+            return Cow::Borrowed(text);
+        }
+        let mut normalized = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !trim && !normalized.is_empty() {
+            if text.starts_with(char::is_whitespace) {
+                normalized.insert(0, ' ');
+            }
+            if text.ends_with(char::is_whitespace) {
+                normalized.push(' ');
+            }
+        }
+        Cow::Owned(normalized)
+    }
+
+    /// Finish a start tag left open in case the element turned out to be empty
+    fn close_tag(&mut self) -> Result<(), TreexmlError> {
+        if self.tag_unclosed {
+            self.tag_unclosed = false;
+            self.put(">")?;
+        }
+        Ok(())
+    }
+
+    /// Start a new line before markup, unless it follows text or is inline
+    fn before_markup(&mut self) -> Result<(), TreexmlError> {
+        let level = self.open.len();
+        let frame = self.frame();
+        if self.options.indent
+            && !frame.inline
+            && frame.wrote != Wrote::Text
+            && (level > 0 || frame.wrote == Wrote::Markup)
+        {
+            self.newline(level)?;
+        }
+        Ok(())
+    }
+
+    fn newline(&mut self, level: usize) -> Result<(), TreexmlError> {
+        self.put("\n")?;
+        for _ in 0..level {
+            self.inner.write_all(self.options.indent_str.as_bytes())?;
+            self.column += self.options.indent_str.chars().count();
+        }
+        Ok(())
+    }
+
+    /// Write `s`, keeping track of the column the output ends at
+    fn put(&mut self, s: &str) -> Result<(), TreexmlError> {
+        self.inner.write_all(s.as_bytes())?;
+        match s.rfind('\n') {
+            Some(newline) => self.column = s[newline + 1..].chars().count(),
+            None => self.column += s.chars().count(),
+        }
+        Ok(())
+    }
+
+    /// Note that text was written, after which the rest of the element is inline
+    fn wrote_text(&mut self) {
+        let inside = !self.open.is_empty();
+        let frame = self.frame_mut();
+        frame.wrote = Wrote::Text;
+        frame.inline |= inside;
+    }
+
+    fn frame(&self) -> Frame {
+        *self.frames.last().unwrap()
+    }
+
+    /// `text` without the characters the version doesn't allow, if the options say to drop them
+    fn strip<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        let allowed = |c: char| char_use(c, self.version) != CharUse::Invalid;
+        if !self.options.strip_invalid_chars || text.chars().all(allowed) {
+            Cow::Borrowed(text)
+        } else {
+            Cow::Owned(text.chars().filter(|&c| allowed(c)).collect())
+        }
+    }
+
+    /// The error for character `c` in the open element, at `path` below it
+    fn invalid_char(&self, path: &str, kind: ProblemKind, c: char) -> TreexmlError {
+        let mut element = String::new();
+        for name in &self.open {
+            element.push('/');
+            element.push_str(name);
+        }
+        TreexmlError::InvalidXml {
+            problems: vec![Problem {
+                path: element + path,
+                kind,
+                character: Some(c),
+                version: self.version,
+            }],
+        }
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+}
+
+/// Adapts a `fmt::Write` into an `io::Write`, so XML can be written to a formatter
+pub(crate) struct FmtWriter<'a, F: ?Sized>(pub(crate) &'a mut F);
+
+impl<F: fmt::Write + ?Sized> Write for FmtWriter<'_, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // The writer only ever splits its output between characters
+        let s =
+            str::from_utf8(buf).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        self.0
+            .write_str(s)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "formatter error"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Which characters `TreeWriter` escapes in text or in attribute values
+///
+/// `&` and `<` are always escaped, along with the control characters XML 1.1 only allows as
+/// references; writing characters the document's version doesn't allow at all is an error.
+/// In text, a `>` following `]]` is always escaped, since `]]>` isn't allowed there. In attribute
+/// values, `"` and line breaks are always escaped too.
+///
+/// Names can't hold references, so with `non_ascii` set writing a name outside ASCII is an
+/// error. CDATA can't either, so it's written as escaped text when it holds non-ASCII.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EscapeOptions {
+    quotes: bool,
+    apostrophes: bool,
+    greater_than: bool,
+    non_ascii: bool,
+    attribute: bool,
+}
+
+impl EscapeOptions {
+    /// Create options that escape text as `Document::write` does, only escaping what's needed
+    pub fn text() -> EscapeOptions {
+        EscapeOptions {
+            quotes: false,
+            apostrophes: false,
+            greater_than: false,
+            non_ascii: false,
+            attribute: false,
+        }
+    }
+
+    /// Create options that escape attribute values as `Document::write` does, with `>`, `"`
+    /// and `'` escaped
+    pub fn attribute() -> EscapeOptions {
+        EscapeOptions {
+            quotes: true,
+            apostrophes: true,
+            greater_than: true,
+            non_ascii: false,
+            attribute: true,
+        }
+    }
+
+    /// Escape `"` as `&quot;`
+    pub fn quotes(&mut self, escape: bool) -> &mut Self {
+        self.quotes = escape;
+        self
+    }
+
+    /// Escape `'` as `&apos;`
+    pub fn apostrophes(&mut self, escape: bool) -> &mut Self {
+        self.apostrophes = escape;
+        self
+    }
+
+    /// Escape every `>` as `&gt;`, not only those following `]]` in text
+    pub fn greater_than(&mut self, escape: bool) -> &mut Self {
+        self.greater_than = escape;
+        self
+    }
+
+    /// Write characters outside ASCII as character references, such as `&#xE9;`
+    pub fn non_ascii(&mut self, escape: bool) -> &mut Self {
+        self.non_ascii = escape;
+        self
+    }
+
+    /// The replacement for `c`, which follows `brackets` `]` characters, up to two
+    fn replacement(
+        &self,
+        c: char,
+        version: XmlVersion,
+        brackets: usize,
+    ) -> Option<Cow<'static, str>> {
+        let entity = match c {
+            '&' => "&amp;",
+            '<' => "&lt;",
+            // `]]>` isn't allowed in text
+            '>' if self.greater_than || (brackets == 2 && !self.attribute) => "&gt;",
+            '"' if self.quotes || self.attribute => "&quot;",
+            '\'' if self.apostrophes => "&apos;",
+            '\n' if self.attribute => "&#xA;",
+            '\r' if self.attribute => "&#xD;",
+            c if char_use(c, version) == CharUse::Reference
+                || (self.non_ascii && !c.is_ascii()) =>
+            {
+                return Some(Cow::Owned(format!("&#x{:X};", c as u32)))
+            }
+            _ => return None,
+        };
+        Some(Cow::Borrowed(entity))
+    }
+
+    /// Escape `text`, which follows `brackets` `]` characters, according to the options, or
+    /// return the first character `version` doesn't allow
+    fn escape<'t>(
+        &self,
+        text: &'t str,
+        version: XmlVersion,
+        mut brackets: usize,
+    ) -> Result<Cow<'t, str>, char> {
+        if let Some(c) = text
+            .chars()
+            .find(|&c| char_use(c, version) == CharUse::Invalid)
+        {
+            return Err(c);
+        }
+
+        let mut escaped: Option<String> = None;
+        for (i, c) in text.char_indices() {
+            match self.replacement(c, version, brackets) {
+                Some(replacement) => escaped
+                    .get_or_insert_with(|| text[..i].to_owned())
+                    .push_str(&replacement),
+                None => {
+                    if let Some(ref mut escaped) = escaped {
+                        escaped.push(c);
+                    }
+                }
+            }
+            brackets = if c == ']' { (brackets + 1).min(2) } else { 0 };
+        }
+        Ok(escaped.map_or(Cow::Borrowed(text), Cow::Owned))
+    }
+}
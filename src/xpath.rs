@@ -0,0 +1,1595 @@
+//! An XPath 1.0 evaluator over `Element` trees
+//!
+//! The element an expression is evaluated against acts as the document element: relative
+//! paths start from it, and absolute paths start from a root node whose only child it is.
+//! An element's text and CDATA are treated as text nodes that precede its child elements.
+
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use indexmap::IndexMap;
+
+use crate::{Element, TreexmlError};
+
+/// A compiled XPath 1.0 expression
+#[derive(Debug, Clone)]
+pub struct XPath {
+    source: String,
+    expr: Expr,
+}
+
+/// Namespace bindings used to resolve prefixes in an XPath expression
+///
+/// Prefixes that are not bound here are compared literally against element and attribute
+/// prefixes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XPathContext {
+    namespaces: IndexMap<String, String>,
+}
+
+/// A node selected by an XPath expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum XPathNode<'a> {
+    /// The root node, whose only child is the element the expression was evaluated against
+    Root(&'a Element),
+    /// An element
+    Element(&'a Element),
+    /// An attribute of `element`
+    Attribute {
+        element: &'a Element,
+        name: &'a str,
+        value: &'a str,
+    },
+    /// Text or CDATA contents of an element
+    Text(&'a str),
+}
+
+/// The result of evaluating an XPath expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum XPathValue<'a> {
+    /// Nodes in document order
+    NodeSet(Vec<XPathNode<'a>>),
+    String(String),
+    Number(f64),
+    Boolean(bool),
+}
+
+impl XPathContext {
+    /// Create an empty context
+    pub fn new() -> XPathContext {
+        XPathContext::default()
+    }
+
+    /// Bind `prefix` to the namespace `uri`
+    pub fn namespace<P, U>(&mut self, prefix: P, uri: U) -> &mut XPathContext
+    where
+        P: ToString,
+        U: ToString,
+    {
+        self.namespaces.insert(prefix.to_string(), uri.to_string());
+        self
+    }
+}
+
+impl XPath {
+    /// Compile an XPath expression
+    ///
+    /// # Failures
+    ///
+    /// Returns `TreexmlError::XPathSyntax` with the offset at which parsing failed
+    pub fn compile(expr: &str) -> Result<XPath, TreexmlError> {
+        let tokens = tokenize(expr)?;
+        let mut parser = Parser {
+            source: expr,
+            tokens,
+            pos: 0,
+        };
+        let parsed = parser.parse_expr()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(parser.error("unexpected token"));
+        }
+        Ok(XPath {
+            source: expr.to_owned(),
+            expr: parsed,
+        })
+    }
+
+    /// The source text of the expression
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Evaluate the expression with `element` as the context node
+    pub fn evaluate<'a>(
+        &self,
+        element: &'a Element,
+        context: &XPathContext,
+    ) -> Result<XPathValue<'a>, TreexmlError> {
+        Ok(match self.eval(element, context)? {
+            Value::Nodes(nodes) => XPathValue::NodeSet(nodes.iter().map(Node::public).collect()),
+            Value::Str(s) => XPathValue::String(s),
+            Value::Num(n) => XPathValue::Number(n),
+            Value::Bool(b) => XPathValue::Boolean(b),
+        })
+    }
+
+    /// Evaluate the expression with `element` as the context node, expecting a node-set
+    pub fn select<'a>(
+        &self,
+        element: &'a Element,
+        context: &XPathContext,
+    ) -> Result<Vec<XPathNode<'a>>, TreexmlError> {
+        match self.evaluate(element, context)? {
+            XPathValue::NodeSet(nodes) => Ok(nodes),
+            _ => Err(self.not_node_set()),
+        }
+    }
+
+    /// Evaluate the expression with `element` as the context node, expecting a node-set, and
+    /// give the location of each selected node
+    ///
+    /// Clones share their children, so nodes have to be told apart by location rather than
+    /// by address.
+    pub(crate) fn locate(
+        &self,
+        element: &Element,
+        context: &XPathContext,
+    ) -> Result<Vec<Location>, TreexmlError> {
+        match self.eval(element, context)? {
+            Value::Nodes(nodes) => Ok(nodes.iter().map(Node::location).collect()),
+            _ => Err(self.not_node_set()),
+        }
+    }
+
+    fn eval<'a>(
+        &self,
+        element: &'a Element,
+        context: &XPathContext,
+    ) -> Result<Value<'a>, TreexmlError> {
+        let eval = Evaluator {
+            source: &self.source,
+            context,
+        };
+        let node = Node::Element(Rc::new(Chain {
+            element,
+            index: 0,
+            parent: None,
+        }));
+        eval.eval(&self.expr, &Focus::single(node))
+    }
+
+    fn not_node_set(&self) -> TreexmlError {
+        TreexmlError::XPathEvaluation {
+            expr: self.source.clone(),
+            msg: "expression does not return a node-set".to_owned(),
+        }
+    }
+}
+
+impl FromStr for XPath {
+    type Err = TreexmlError;
+
+    fn from_str(s: &str) -> Result<XPath, TreexmlError> {
+        XPath::compile(s)
+    }
+}
+
+impl fmt::Display for XPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl<'a> XPathNode<'a> {
+    /// The XPath string-value of the node
+    pub fn string_value(&self) -> String {
+        match *self {
+            XPathNode::Root(element) | XPathNode::Element(element) => {
+                let mut s = String::new();
+                push_string_value(element, &mut s);
+                s
+            }
+            XPathNode::Attribute { value, .. } => value.to_owned(),
+            XPathNode::Text(text) => text.to_owned(),
+        }
+    }
+}
+
+impl<'a> XPathValue<'a> {
+    /// Convert the value as the XPath `string()` function would
+    pub fn to_string_value(&self) -> String {
+        match self {
+            XPathValue::NodeSet(nodes) => nodes
+                .first()
+                .map(XPathNode::string_value)
+                .unwrap_or_default(),
+            XPathValue::String(s) => s.clone(),
+            XPathValue::Number(n) => number_to_string(*n),
+            XPathValue::Boolean(b) => b.to_string(),
+        }
+    }
+}
+
+// Tokens
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Dot,
+    DotDot,
+    At,
+    Comma,
+    ColonColon,
+    Slash,
+    DoubleSlash,
+    Pipe,
+    Plus,
+    Minus,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Star,
+    Literal(String),
+    Number(f64),
+    /// An NCName, QName, or `prefix:*`
+    Name(String),
+    Variable(String),
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || !c.is_ascii()
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || c == '-' || c == '.'
+}
+
+fn tokenize(expr: &str) -> Result<Vec<(Token, usize)>, TreexmlError> {
+    let chars: Vec<(usize, char)> = expr.char_indices().collect();
+    let at = |i: usize| chars.get(i).map(|&(_, c)| c);
+    let offset = |i: usize| chars.get(i).map(|&(o, _)| o).unwrap_or(expr.len());
+    let error = |i: usize, msg: &str| TreexmlError::XPathSyntax {
+        expr: expr.to_owned(),
+        pos: offset(i),
+        msg: msg.to_owned(),
+    };
+    let read_ncname = |mut i: usize| {
+        while at(i).is_some_and(is_name_char) {
+            i += 1;
+        }
+        i
+    };
+
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while let Some(c) = at(i) {
+        let start = i;
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '@' => Token::At,
+            ',' => Token::Comma,
+            '|' => Token::Pipe,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '=' => Token::Eq,
+            '*' => Token::Star,
+            '!' if at(i + 1) == Some('=') => {
+                i += 1;
+                Token::NotEq
+            }
+            '<' if at(i + 1) == Some('=') => {
+                i += 1;
+                Token::LtEq
+            }
+            '<' => Token::Lt,
+            '>' if at(i + 1) == Some('=') => {
+                i += 1;
+                Token::GtEq
+            }
+            '>' => Token::Gt,
+            ':' if at(i + 1) == Some(':') => {
+                i += 1;
+                Token::ColonColon
+            }
+            '/' if at(i + 1) == Some('/') => {
+                i += 1;
+                Token::DoubleSlash
+            }
+            '/' => Token::Slash,
+            '.' if at(i + 1) == Some('.') => {
+                i += 1;
+                Token::DotDot
+            }
+            '.' if !at(i + 1).is_some_and(|c| c.is_ascii_digit()) => Token::Dot,
+            '"' | '\'' => {
+                let close = (i + 1..chars.len())
+                    .find(|&j| at(j) == Some(c))
+                    .ok_or_else(|| error(i, "unterminated string literal"))?;
+                let literal = expr[offset(i + 1)..offset(close)].to_owned();
+                i = close;
+                Token::Literal(literal)
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut end = i;
+                while at(end).is_some_and(|c| c.is_ascii_digit()) {
+                    end += 1;
+                }
+                if at(end) == Some('.') {
+                    end += 1;
+                    while at(end).is_some_and(|c| c.is_ascii_digit()) {
+                        end += 1;
+                    }
+                }
+                let number = expr[offset(i)..offset(end)]
+                    .parse()
+                    .map_err(|_| error(i, "invalid number"))?;
+                i = end - 1;
+                Token::Number(number)
+            }
+            '$' => {
+                if !at(i + 1).is_some_and(is_name_start) {
+                    return Err(error(i + 1, "expected a variable name"));
+                }
+                let end = read_ncname(i + 1);
+                let name = expr[offset(i + 1)..offset(end)].to_owned();
+                i = end - 1;
+                Token::Variable(name)
+            }
+            c if is_name_start(c) => {
+                let mut end = read_ncname(i);
+                if at(end) == Some(':') && at(end + 1) != Some(':') {
+                    match at(end + 1) {
+                        Some('*') => end += 2,
+                        Some(c) if is_name_start(c) => end = read_ncname(end + 1),
+                        _ => return Err(error(end + 1, "expected a local name after prefix")),
+                    }
+                }
+                let name = expr[offset(i)..offset(end)].to_owned();
+                i = end - 1;
+                Token::Name(name)
+            }
+            _ => return Err(error(i, "unexpected character")),
+        };
+        tokens.push((token, offset(start)));
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+// Syntax tree
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Axis {
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    Current,
+    Parent,
+    Ancestor,
+    AncestorOrSelf,
+    Attribute,
+    FollowingSibling,
+    PrecedingSibling,
+}
+
+impl Axis {
+    fn is_reverse(self) -> bool {
+        matches!(
+            self,
+            Axis::Parent | Axis::Ancestor | Axis::AncestorOrSelf | Axis::PrecedingSibling
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum NodeTest {
+    /// `*`, `prefix:*` or a QName; matches the principal node type of the axis
+    Name {
+        prefix: Option<String>,
+        local: Option<String>,
+    },
+    Node,
+    Text,
+    /// `comment()` and `processing-instruction()`, which never match
+    Nothing,
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    axis: Axis,
+    test: NodeTest,
+    predicates: Vec<Expr>,
+}
+
+#[derive(Debug, Clone)]
+enum PathStart {
+    Context,
+    Root,
+    Expr(Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Compare(CompareOp, Box<Expr>, Box<Expr>),
+    Arith(ArithOp, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Union(Box<Expr>, Box<Expr>),
+    Path(PathStart, Vec<Step>),
+    Filter(Box<Expr>, Vec<Expr>),
+    Literal(String),
+    Number(f64),
+    Function(String, Vec<Expr>),
+}
+
+/// Core functions with their minimum and maximum argument counts
+const FUNCTIONS: &[(&str, usize, usize)] = &[
+    ("last", 0, 0),
+    ("position", 0, 0),
+    ("count", 1, 1),
+    ("local-name", 0, 1),
+    ("namespace-uri", 0, 1),
+    ("name", 0, 1),
+    ("string", 0, 1),
+    ("concat", 2, usize::MAX),
+    ("starts-with", 2, 2),
+    ("contains", 2, 2),
+    ("substring-before", 2, 2),
+    ("substring-after", 2, 2),
+    ("substring", 2, 3),
+    ("string-length", 0, 1),
+    ("normalize-space", 0, 1),
+    ("translate", 3, 3),
+    ("boolean", 1, 1),
+    ("not", 1, 1),
+    ("true", 0, 0),
+    ("false", 0, 0),
+    ("number", 0, 1),
+    ("sum", 1, 1),
+    ("floor", 1, 1),
+    ("ceiling", 1, 1),
+    ("round", 1, 1),
+];
+
+fn is_node_type(name: &str) -> bool {
+    matches!(name, "node" | "text" | "comment" | "processing-instruction")
+}
+
+fn descendant_or_self() -> Step {
+    Step {
+        axis: Axis::DescendantOrSelf,
+        test: NodeTest::Node,
+        predicates: Vec::new(),
+    }
+}
+
+// Parser
+
+struct Parser<'s> {
+    source: &'s str,
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn error(&self, msg: &str) -> TreexmlError {
+        self.error_at(self.pos, msg)
+    }
+
+    fn error_at(&self, token: usize, msg: &str) -> TreexmlError {
+        TreexmlError::XPathSyntax {
+            expr: self.source.to_owned(),
+            pos: self
+                .tokens
+                .get(token)
+                .map(|&(_, offset)| offset)
+                .unwrap_or(self.source.len()),
+            msg: msg.to_owned(),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + n).map(|(token, _)| token)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_name(&mut self, name: &str) -> bool {
+        match self.peek() {
+            Some(Token::Name(n)) if n == name => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, token: &Token, what: &str) -> Result<(), TreexmlError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected {}", what)))
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, TreexmlError> {
+        let mut lhs = self.parse_and()?;
+        while self.eat_name("or") {
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.parse_and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, TreexmlError> {
+        let mut lhs = self.parse_equality()?;
+        while self.eat_name("and") {
+            lhs = Expr::And(Box::new(lhs), Box::new(self.parse_equality()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_equality(&mut self) -> Result<Expr, TreexmlError> {
+        let mut lhs = self.parse_relational()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Eq) => CompareOp::Eq,
+                Some(Token::NotEq) => CompareOp::NotEq,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            lhs = Expr::Compare(op, Box::new(lhs), Box::new(self.parse_relational()?));
+        }
+    }
+
+    fn parse_relational(&mut self) -> Result<Expr, TreexmlError> {
+        let mut lhs = self.parse_additive()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Lt) => CompareOp::Lt,
+                Some(Token::LtEq) => CompareOp::LtEq,
+                Some(Token::Gt) => CompareOp::Gt,
+                Some(Token::GtEq) => CompareOp::GtEq,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            lhs = Expr::Compare(op, Box::new(lhs), Box::new(self.parse_additive()?));
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, TreexmlError> {
+        let mut lhs = self.parse_multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => ArithOp::Add,
+                Some(Token::Minus) => ArithOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            lhs = Expr::Arith(op, Box::new(lhs), Box::new(self.parse_multiplicative()?));
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, TreexmlError> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Star) => ArithOp::Mul,
+                Some(Token::Name(n)) if n == "div" => ArithOp::Div,
+                Some(Token::Name(n)) if n == "mod" => ArithOp::Mod,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            lhs = Expr::Arith(op, Box::new(lhs), Box::new(self.parse_unary()?));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, TreexmlError> {
+        if self.eat(&Token::Minus) {
+            Ok(Expr::Negate(Box::new(self.parse_unary()?)))
+        } else {
+            self.parse_union()
+        }
+    }
+
+    fn parse_union(&mut self) -> Result<Expr, TreexmlError> {
+        let mut lhs = self.parse_path()?;
+        while self.eat(&Token::Pipe) {
+            lhs = Expr::Union(Box::new(lhs), Box::new(self.parse_path()?));
+        }
+        Ok(lhs)
+    }
+
+    fn at_step_start(&self) -> bool {
+        match self.peek() {
+            Some(Token::Dot) | Some(Token::DotDot) | Some(Token::At) | Some(Token::Star) => true,
+            Some(Token::Name(name)) => match self.peek_at(1) {
+                Some(Token::LParen) => is_node_type(name),
+                _ => true,
+            },
+            _ => false,
+        }
+    }
+
+    fn parse_path(&mut self) -> Result<Expr, TreexmlError> {
+        if self.eat(&Token::Slash) {
+            let mut steps = Vec::new();
+            if self.at_step_start() {
+                self.parse_relative(&mut steps)?;
+            }
+            Ok(Expr::Path(PathStart::Root, steps))
+        } else if self.eat(&Token::DoubleSlash) {
+            let mut steps = vec![descendant_or_self()];
+            self.parse_relative(&mut steps)?;
+            Ok(Expr::Path(PathStart::Root, steps))
+        } else if self.at_step_start() {
+            let mut steps = Vec::new();
+            self.parse_relative(&mut steps)?;
+            Ok(Expr::Path(PathStart::Context, steps))
+        } else {
+            let primary = self.parse_primary()?;
+            let predicates = self.parse_predicates()?;
+            let filter = if predicates.is_empty() {
+                primary
+            } else {
+                Expr::Filter(Box::new(primary), predicates)
+            };
+
+            let mut steps = Vec::new();
+            match self.peek() {
+                Some(Token::Slash) => {
+                    self.pos += 1;
+                }
+                Some(Token::DoubleSlash) => {
+                    self.pos += 1;
+                    steps.push(descendant_or_self());
+                }
+                _ => return Ok(filter),
+            }
+            self.parse_relative(&mut steps)?;
+            Ok(Expr::Path(PathStart::Expr(Box::new(filter)), steps))
+        }
+    }
+
+    fn parse_relative(&mut self, steps: &mut Vec<Step>) -> Result<(), TreexmlError> {
+        steps.push(self.parse_step()?);
+        loop {
+            if self.eat(&Token::Slash) {
+                steps.push(self.parse_step()?);
+            } else if self.eat(&Token::DoubleSlash) {
+                steps.push(descendant_or_self());
+                steps.push(self.parse_step()?);
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn parse_step(&mut self) -> Result<Step, TreexmlError> {
+        if self.eat(&Token::Dot) {
+            return Ok(Step {
+                axis: Axis::Current,
+                test: NodeTest::Node,
+                predicates: Vec::new(),
+            });
+        }
+        if self.eat(&Token::DotDot) {
+            return Ok(Step {
+                axis: Axis::Parent,
+                test: NodeTest::Node,
+                predicates: Vec::new(),
+            });
+        }
+
+        let axis = if self.eat(&Token::At) {
+            Axis::Attribute
+        } else if let (Some(Token::Name(name)), Some(Token::ColonColon)) =
+            (self.peek(), self.peek_at(1))
+        {
+            let axis = match name.as_str() {
+                "child" => Axis::Child,
+                "descendant" => Axis::Descendant,
+                "descendant-or-self" => Axis::DescendantOrSelf,
+                "self" => Axis::Current,
+                "parent" => Axis::Parent,
+                "ancestor" => Axis::Ancestor,
+                "ancestor-or-self" => Axis::AncestorOrSelf,
+                "attribute" => Axis::Attribute,
+                "following-sibling" => Axis::FollowingSibling,
+                "preceding-sibling" => Axis::PrecedingSibling,
+                "following" | "preceding" | "namespace" => {
+                    return Err(self.error("unsupported axis"))
+                }
+                _ => return Err(self.error("unknown axis")),
+            };
+            self.pos += 2;
+            axis
+        } else {
+            Axis::Child
+        };
+
+        let test = self.parse_node_test()?;
+        let predicates = self.parse_predicates()?;
+        Ok(Step {
+            axis,
+            test,
+            predicates,
+        })
+    }
+
+    fn parse_node_test(&mut self) -> Result<NodeTest, TreexmlError> {
+        let name = match self.peek() {
+            Some(Token::Star) => {
+                self.pos += 1;
+                return Ok(NodeTest::Name {
+                    prefix: None,
+                    local: None,
+                });
+            }
+            Some(Token::Name(name)) => name.clone(),
+            _ => return Err(self.error("expected a node test")),
+        };
+        if !is_node_type(&name) && self.peek_at(1) == Some(&Token::LParen) {
+            return Err(self.error(&format!("{}() is not a node test", name)));
+        }
+        self.pos += 1;
+
+        if is_node_type(&name) && self.eat(&Token::LParen) {
+            let test = match name.as_str() {
+                "node" => NodeTest::Node,
+                "text" => NodeTest::Text,
+                "processing-instruction" => {
+                    if let Some(Token::Literal(_)) = self.peek() {
+                        self.pos += 1;
+                    }
+                    NodeTest::Nothing
+                }
+                _ => NodeTest::Nothing,
+            };
+            self.expect(&Token::RParen, "')'")?;
+            return Ok(test);
+        }
+
+        Ok(match name.find(':') {
+            Some(colon) => {
+                let local = &name[colon + 1..];
+                NodeTest::Name {
+                    prefix: Some(name[..colon].to_owned()),
+                    local: if local == "*" {
+                        None
+                    } else {
+                        Some(local.to_owned())
+                    },
+                }
+            }
+            None => NodeTest::Name {
+                prefix: None,
+                local: Some(name),
+            },
+        })
+    }
+
+    fn parse_predicates(&mut self) -> Result<Vec<Expr>, TreexmlError> {
+        let mut predicates = Vec::new();
+        while self.eat(&Token::LBracket) {
+            predicates.push(self.parse_expr()?);
+            self.expect(&Token::RBracket, "']'")?;
+        }
+        Ok(predicates)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, TreexmlError> {
+        let start = self.pos;
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return Err(self.error("unexpected end of expression")),
+        };
+        self.pos += 1;
+
+        match token {
+            Token::LParen => {
+                let expr = self.parse_expr()?;
+                self.expect(&Token::RParen, "')'")?;
+                Ok(expr)
+            }
+            Token::Literal(s) => Ok(Expr::Literal(s)),
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Variable(_) => {
+                Err(self.error_at(start, "variable references are not supported"))
+            }
+            Token::Name(name) if self.peek() == Some(&Token::LParen) => {
+                self.pos += 1;
+                let mut args = Vec::new();
+                if !self.eat(&Token::RParen) {
+                    loop {
+                        args.push(self.parse_expr()?);
+                        if self.eat(&Token::RParen) {
+                            break;
+                        }
+                        self.expect(&Token::Comma, "',' or ')'")?;
+                    }
+                }
+
+                match FUNCTIONS.iter().find(|&&(f, _, _)| f == name) {
+                    None => Err(self.error_at(start, &format!("unknown function {}()", name))),
+                    Some(&(_, min, max)) if args.len() < min || args.len() > max => {
+                        Err(self
+                            .error_at(start, &format!("wrong number of arguments to {}()", name)))
+                    }
+                    Some(_) => Ok(Expr::Function(name, args)),
+                }
+            }
+            _ => Err(self.error_at(start, "unexpected token")),
+        }
+    }
+}
+
+// Evaluation
+
+/// An element along with its ancestors
+#[derive(Debug)]
+struct Chain<'a> {
+    element: &'a Element,
+    /// Position among the parent's children
+    index: usize,
+    parent: Option<Rc<Chain<'a>>>,
+}
+
+/// Where a selected node is, by the child indices leading to its element from the element the
+/// expression was evaluated against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Location {
+    Root,
+    Element(Vec<usize>),
+    /// An element and the index of one of its attributes
+    Attribute(Vec<usize>, usize),
+    /// An element's text (`false`) or CDATA (`true`)
+    Text(Vec<usize>, bool),
+}
+
+#[derive(Debug, Clone)]
+enum Node<'a> {
+    Root(&'a Element),
+    Element(Rc<Chain<'a>>),
+    /// An element and the index of one of its attributes
+    Attribute(Rc<Chain<'a>>, usize),
+    /// An element's text (`false`) or CDATA (`true`)
+    Text(Rc<Chain<'a>>, bool),
+}
+
+impl<'a> Node<'a> {
+    fn public(&self) -> XPathNode<'a> {
+        match self {
+            Node::Root(element) => XPathNode::Root(element),
+            Node::Element(chain) => XPathNode::Element(chain.element),
+            Node::Attribute(chain, index) => {
+                let (name, value) = chain.element.attributes.get_index(*index).unwrap();
+                XPathNode::Attribute {
+                    element: chain.element,
+                    name,
+                    value,
+                }
+            }
+            Node::Text(chain, cdata) => XPathNode::Text(text_of(chain.element, *cdata)),
+        }
+    }
+
+    fn location(&self) -> Location {
+        fn indices(chain: &Chain) -> Vec<usize> {
+            let mut indices = Vec::new();
+            let mut current = chain;
+            while let Some(ref parent) = current.parent {
+                indices.push(current.index);
+                current = parent;
+            }
+            indices.reverse();
+            indices
+        }
+
+        match self {
+            Node::Root(_) => Location::Root,
+            Node::Element(chain) => Location::Element(indices(chain)),
+            Node::Attribute(chain, index) => Location::Attribute(indices(chain), *index),
+            Node::Text(chain, cdata) => Location::Text(indices(chain), *cdata),
+        }
+    }
+
+    /// A key that sorts nodes into document order
+    fn order_key(&self) -> Vec<usize> {
+        fn element_key(chain: &Chain, key: &mut Vec<usize>) {
+            if let Some(ref parent) = chain.parent {
+                element_key(parent, key);
+            }
+            key.push(chain.index + 3);
+        }
+
+        let mut key = Vec::new();
+        match self {
+            Node::Root(_) => {}
+            Node::Element(chain) => element_key(chain, &mut key),
+            Node::Attribute(chain, index) => {
+                element_key(chain, &mut key);
+                key.push(0);
+                key.push(*index);
+            }
+            Node::Text(chain, cdata) => {
+                element_key(chain, &mut key);
+                key.push(if *cdata { 2 } else { 1 });
+            }
+        }
+        key
+    }
+
+    fn string_value(&self) -> String {
+        self.public().string_value()
+    }
+
+    fn children(&self) -> Vec<Node<'a>> {
+        match self {
+            Node::Root(element) => vec![Node::Element(Rc::new(Chain {
+                element,
+                index: 0,
+                parent: None,
+            }))],
+            Node::Element(chain) => {
+                let mut nodes = Vec::new();
+                if has_text(chain.element, false) {
+                    nodes.push(Node::Text(chain.clone(), false));
+                }
+                if has_text(chain.element, true) {
+                    nodes.push(Node::Text(chain.clone(), true));
+                }
+                nodes.extend(
+                    chain
+                        .element
+                        .children
+                        .iter()
+                        .enumerate()
+                        .map(|(index, element)| {
+                            Node::Element(Rc::new(Chain {
+                                element,
+                                index,
+                                parent: Some(chain.clone()),
+                            }))
+                        }),
+                );
+                nodes
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn parent(&self) -> Option<Node<'a>> {
+        match self {
+            Node::Root(_) => None,
+            Node::Element(chain) => Some(match chain.parent {
+                Some(ref parent) => Node::Element(parent.clone()),
+                None => Node::Root(chain.element),
+            }),
+            Node::Attribute(chain, _) | Node::Text(chain, _) => Some(Node::Element(chain.clone())),
+        }
+    }
+
+    fn descendants(&self, nodes: &mut Vec<Node<'a>>) {
+        let mut stack = self.children();
+        stack.reverse();
+        while let Some(node) = stack.pop() {
+            let mut children = node.children();
+            children.reverse();
+            nodes.push(node);
+            stack.append(&mut children);
+        }
+    }
+
+    fn siblings(&self, following: bool) -> Vec<Node<'a>> {
+        let (parent, position) = match self {
+            Node::Element(chain) => match chain.parent {
+                Some(ref parent) => (parent, None),
+                None => return Vec::new(),
+            },
+            Node::Text(chain, cdata) => (chain, Some(*cdata)),
+            _ => return Vec::new(),
+        };
+        let siblings = Node::Element(parent.clone()).children();
+        let index = siblings
+            .iter()
+            .position(|node| match (node, self, position) {
+                (Node::Element(a), Node::Element(b), None) => a.index == b.index,
+                (Node::Text(_, a), _, Some(b)) => *a == b,
+                _ => false,
+            })
+            .unwrap();
+
+        if following {
+            siblings.into_iter().skip(index + 1).collect()
+        } else {
+            siblings.into_iter().take(index).rev().collect()
+        }
+    }
+
+    fn axis(&self, axis: Axis) -> Vec<Node<'a>> {
+        let mut nodes = Vec::new();
+        match axis {
+            Axis::Child => nodes = self.children(),
+            Axis::Descendant => self.descendants(&mut nodes),
+            Axis::DescendantOrSelf => {
+                nodes.push(self.clone());
+                self.descendants(&mut nodes);
+            }
+            Axis::Current => nodes.push(self.clone()),
+            Axis::Parent => nodes.extend(self.parent()),
+            Axis::Ancestor | Axis::AncestorOrSelf => {
+                if axis == Axis::AncestorOrSelf {
+                    nodes.push(self.clone());
+                }
+                let mut current = self.parent();
+                while let Some(node) = current {
+                    current = node.parent();
+                    nodes.push(node);
+                }
+            }
+            Axis::Attribute => {
+                if let Node::Element(chain) = self {
+                    nodes.extend(
+                        (0..chain.element.attributes.len())
+                            .map(|index| Node::Attribute(chain.clone(), index)),
+                    );
+                }
+            }
+            Axis::FollowingSibling => nodes = self.siblings(true),
+            Axis::PrecedingSibling => nodes = self.siblings(false),
+        }
+        nodes
+    }
+}
+
+fn text_of(element: &Element, cdata: bool) -> &str {
+    let text = if cdata { &element.cdata } else { &element.text };
+    text.as_deref().unwrap_or("")
+}
+
+fn has_text(element: &Element, cdata: bool) -> bool {
+    !text_of(element, cdata).is_empty()
+}
+
+fn push_string_value(element: &Element, s: &mut String) {
+    let mut stack = vec![element];
+    while let Some(element) = stack.pop() {
+        s.push_str(text_of(element, false));
+        s.push_str(text_of(element, true));
+        stack.extend(element.children.iter().rev());
+    }
+}
+
+/// Look up the namespace URI bound to `prefix` for the element of `chain`, from its own
+/// declarations or those of its ancestors
+fn namespace_of<'e>(chain: &Chain<'e>, prefix: Option<&str>) -> Option<&'e str> {
+    let mut current = Some(chain);
+    while let Some(chain) = current {
+        if let Some(uri) = chain.element.declared_namespace(prefix.unwrap_or("")) {
+            return Some(uri).filter(|uri| !uri.is_empty());
+        }
+        current = chain.parent.as_deref();
+    }
+    None
+}
+
+fn split_qname(name: &str) -> (Option<&str>, &str) {
+    match name.find(':') {
+        Some(colon) => (Some(&name[..colon]), &name[colon + 1..]),
+        None => (None, name),
+    }
+}
+
+fn sort_nodes(nodes: &mut Vec<Node>) {
+    let mut keyed: Vec<(Vec<usize>, Node)> = nodes
+        .drain(..)
+        .map(|node| (node.order_key(), node))
+        .collect();
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    keyed.dedup_by(|a, b| a.0 == b.0);
+    nodes.extend(keyed.into_iter().map(|(_, node)| node));
+}
+
+fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_owned()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.to_owned()
+    } else if n == 0.0 {
+        "0".to_owned()
+    } else {
+        n.to_string()
+    }
+}
+
+fn string_to_number(s: &str) -> f64 {
+    let s = s.trim();
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let valid = !digits.is_empty()
+        && digits != "."
+        && digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && digits.matches('.').count() <= 1;
+    if valid {
+        s.parse().unwrap_or(f64::NAN)
+    } else {
+        f64::NAN
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value<'a> {
+    Nodes(Vec<Node<'a>>),
+    Str(String),
+    Num(f64),
+    Bool(bool),
+}
+
+impl<'a> Value<'a> {
+    fn to_str(&self) -> String {
+        match self {
+            Value::Nodes(nodes) => nodes.first().map(Node::string_value).unwrap_or_default(),
+            Value::Str(s) => s.clone(),
+            Value::Num(n) => number_to_string(*n),
+            Value::Bool(b) => b.to_string(),
+        }
+    }
+
+    fn to_num(&self) -> f64 {
+        match self {
+            Value::Num(n) => *n,
+            Value::Bool(b) => {
+                if *b {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            _ => string_to_number(&self.to_str()),
+        }
+    }
+
+    fn to_bool(&self) -> bool {
+        match self {
+            Value::Nodes(nodes) => !nodes.is_empty(),
+            Value::Str(s) => !s.is_empty(),
+            Value::Num(n) => *n != 0.0 && !n.is_nan(),
+            Value::Bool(b) => *b,
+        }
+    }
+}
+
+/// The context position and size along with the context node
+struct Focus<'a> {
+    node: Node<'a>,
+    position: usize,
+    size: usize,
+}
+
+impl<'a> Focus<'a> {
+    fn single(node: Node<'a>) -> Focus<'a> {
+        Focus {
+            node,
+            position: 1,
+            size: 1,
+        }
+    }
+}
+
+struct Evaluator<'e> {
+    source: &'e str,
+    context: &'e XPathContext,
+}
+
+impl<'e> Evaluator<'e> {
+    fn error(&self, msg: &str) -> TreexmlError {
+        TreexmlError::XPathEvaluation {
+            expr: self.source.to_owned(),
+            msg: msg.to_owned(),
+        }
+    }
+
+    fn eval<'a>(&self, expr: &Expr, focus: &Focus<'a>) -> Result<Value<'a>, TreexmlError> {
+        Ok(match expr {
+            Expr::Or(lhs, rhs) => {
+                Value::Bool(self.eval(lhs, focus)?.to_bool() || self.eval(rhs, focus)?.to_bool())
+            }
+            Expr::And(lhs, rhs) => {
+                Value::Bool(self.eval(lhs, focus)?.to_bool() && self.eval(rhs, focus)?.to_bool())
+            }
+            Expr::Compare(op, lhs, rhs) => Value::Bool(compare(
+                *op,
+                &self.eval(lhs, focus)?,
+                &self.eval(rhs, focus)?,
+            )),
+            Expr::Arith(op, lhs, rhs) => {
+                let a = self.eval(lhs, focus)?.to_num();
+                let b = self.eval(rhs, focus)?.to_num();
+                Value::Num(match op {
+                    ArithOp::Add => a + b,
+                    ArithOp::Sub => a - b,
+                    ArithOp::Mul => a * b,
+                    ArithOp::Div => a / b,
+                    ArithOp::Mod => a % b,
+                })
+            }
+            Expr::Negate(expr) => Value::Num(-self.eval(expr, focus)?.to_num()),
+            Expr::Union(lhs, rhs) => {
+                let mut nodes = self.eval_nodes(lhs, focus)?;
+                nodes.append(&mut self.eval_nodes(rhs, focus)?);
+                sort_nodes(&mut nodes);
+                Value::Nodes(nodes)
+            }
+            Expr::Path(start, steps) => {
+                let mut nodes = match start {
+                    PathStart::Context => vec![focus.node.clone()],
+                    PathStart::Root => {
+                        let mut node = focus.node.clone();
+                        while let Some(parent) = node.parent() {
+                            node = parent;
+                        }
+                        vec![node]
+                    }
+                    PathStart::Expr(expr) => self.eval_nodes(expr, focus)?,
+                };
+                for step in steps {
+                    nodes = self.eval_step(step, &nodes)?;
+                }
+                Value::Nodes(nodes)
+            }
+            Expr::Filter(expr, predicates) => {
+                let nodes = self.eval_nodes(expr, focus)?;
+                Value::Nodes(self.filter(nodes, predicates)?)
+            }
+            Expr::Literal(s) => Value::Str(s.clone()),
+            Expr::Number(n) => Value::Num(*n),
+            Expr::Function(name, args) => self.call(name, args, focus)?,
+        })
+    }
+
+    fn eval_nodes<'a>(
+        &self,
+        expr: &Expr,
+        focus: &Focus<'a>,
+    ) -> Result<Vec<Node<'a>>, TreexmlError> {
+        match self.eval(expr, focus)? {
+            Value::Nodes(nodes) => Ok(nodes),
+            _ => Err(self.error("expected a node-set")),
+        }
+    }
+
+    fn eval_step<'a>(
+        &self,
+        step: &Step,
+        context: &[Node<'a>],
+    ) -> Result<Vec<Node<'a>>, TreexmlError> {
+        let mut result = Vec::new();
+        for node in context {
+            let candidates = node
+                .axis(step.axis)
+                .into_iter()
+                .filter(|candidate| self.test(&step.test, step.axis, candidate))
+                .collect();
+            result.append(&mut self.filter(candidates, &step.predicates)?);
+        }
+        if context.len() > 1 || step.axis.is_reverse() {
+            sort_nodes(&mut result);
+        }
+        Ok(result)
+    }
+
+    fn filter<'a>(
+        &self,
+        mut nodes: Vec<Node<'a>>,
+        predicates: &[Expr],
+    ) -> Result<Vec<Node<'a>>, TreexmlError> {
+        for predicate in predicates {
+            let size = nodes.len();
+            let mut kept = Vec::with_capacity(size);
+            for (i, node) in nodes.into_iter().enumerate() {
+                let focus = Focus {
+                    node,
+                    position: i + 1,
+                    size,
+                };
+                let keep = match self.eval(predicate, &focus)? {
+                    Value::Num(n) => n == focus.position as f64,
+                    value => value.to_bool(),
+                };
+                if keep {
+                    kept.push(focus.node);
+                }
+            }
+            nodes = kept;
+        }
+        Ok(nodes)
+    }
+
+    fn test(&self, test: &NodeTest, axis: Axis, node: &Node) -> bool {
+        match test {
+            NodeTest::Node => true,
+            NodeTest::Text => matches!(node, Node::Text(..)),
+            NodeTest::Nothing => false,
+            NodeTest::Name { prefix, local } => {
+                let (chain, node_prefix, node_local) = match node {
+                    Node::Element(chain) if axis != Axis::Attribute => (
+                        &**chain,
+                        chain.element.prefix.as_deref(),
+                        chain.element.name.as_str(),
+                    ),
+                    Node::Attribute(chain, index) if axis == Axis::Attribute => {
+                        let (name, _) = chain.element.attributes.get_index(*index).unwrap();
+                        let (prefix, local) = split_qname(name);
+                        (&**chain, prefix, local)
+                    }
+                    _ => return false,
+                };
+                if local.as_ref().is_some_and(|local| local != node_local) {
+                    return false;
+                }
+                let is_attribute = axis == Axis::Attribute;
+                match prefix {
+                    None if local.is_none() => true,
+                    // Unprefixed attributes and unprefixed elements outside a default
+                    // namespace have no namespace
+                    None => {
+                        node_prefix.is_none()
+                            && (is_attribute || namespace_of(chain, None).is_none())
+                    }
+                    Some(prefix) => {
+                        let uri = self.context.namespaces.get(prefix).map(String::as_str);
+                        let node_uri = match node_prefix {
+                            None if is_attribute => None,
+                            node_prefix => namespace_of(chain, node_prefix),
+                        };
+                        match (uri, node_uri) {
+                            (Some(uri), Some(node_uri)) => uri == node_uri,
+                            _ => node_prefix == Some(prefix.as_str()),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn call<'a>(
+        &self,
+        name: &str,
+        args: &[Expr],
+        focus: &Focus<'a>,
+    ) -> Result<Value<'a>, TreexmlError> {
+        let arg = |i: usize| self.eval(&args[i], focus);
+        let string_arg = |i: usize| -> Result<String, TreexmlError> {
+            match args.get(i) {
+                Some(expr) => Ok(self.eval(expr, focus)?.to_str()),
+                None => Ok(focus.node.string_value()),
+            }
+        };
+        let node_arg = |i: usize| -> Result<Option<Node<'a>>, TreexmlError> {
+            match args.get(i) {
+                Some(expr) => Ok(self.eval_nodes(expr, focus)?.into_iter().next()),
+                None => Ok(Some(focus.node.clone())),
+            }
+        };
+
+        Ok(match name {
+            "last" => Value::Num(focus.size as f64),
+            "position" => Value::Num(focus.position as f64),
+            "count" => Value::Num(self.eval_nodes(&args[0], focus)?.len() as f64),
+            "local-name" | "name" | "namespace-uri" => {
+                let s = match node_arg(0)? {
+                    Some(Node::Element(chain)) => {
+                        let element = chain.element;
+                        match name {
+                            "local-name" => element.name.clone(),
+                            "namespace-uri" => namespace_of(&chain, element.prefix.as_deref())
+                                .unwrap_or("")
+                                .to_owned(),
+                            _ => match element.prefix {
+                                Some(ref prefix) => format!("{}:{}", prefix, element.name),
+                                None => element.name.clone(),
+                            },
+                        }
+                    }
+                    Some(Node::Attribute(chain, index)) => {
+                        let (qname, _) = chain.element.attributes.get_index(index).unwrap();
+                        let (prefix, local) = split_qname(qname);
+                        match name {
+                            "local-name" => local.to_owned(),
+                            "namespace-uri" => prefix
+                                .and_then(|prefix| namespace_of(&chain, Some(prefix)))
+                                .unwrap_or("")
+                                .to_owned(),
+                            _ => qname.clone(),
+                        }
+                    }
+                    _ => String::new(),
+                };
+                Value::Str(s)
+            }
+            "string" => Value::Str(string_arg(0)?),
+            "concat" => {
+                let mut s = String::new();
+                for i in 0..args.len() {
+                    s.push_str(&string_arg(i)?);
+                }
+                Value::Str(s)
+            }
+            "starts-with" => Value::Bool(string_arg(0)?.starts_with(&string_arg(1)?)),
+            "contains" => Value::Bool(string_arg(0)?.contains(&string_arg(1)?)),
+            "substring-before" => {
+                let (s, pattern) = (string_arg(0)?, string_arg(1)?);
+                Value::Str(s.find(&pattern).map_or("", |i| &s[..i]).to_owned())
+            }
+            "substring-after" => {
+                let (s, pattern) = (string_arg(0)?, string_arg(1)?);
+                Value::Str(
+                    s.find(&pattern)
+                        .map_or("", |i| &s[i + pattern.len()..])
+                        .to_owned(),
+                )
+            }
+            "substring" => {
+                let s = string_arg(0)?;
+                let start = round(arg(1)?.to_num());
+                let end = match args.get(2) {
+                    Some(_) => start + round(arg(2)?.to_num()),
+                    None => f64::INFINITY,
+                };
+                Value::Str(
+                    s.chars()
+                        .enumerate()
+                        .filter(|&(i, _)| {
+                            let position = (i + 1) as f64;
+                            position >= start && position < end
+                        })
+                        .map(|(_, c)| c)
+                        .collect(),
+                )
+            }
+            "string-length" => Value::Num(string_arg(0)?.chars().count() as f64),
+            "normalize-space" => Value::Str(
+                string_arg(0)?
+                    .split_whitespace()
+                    .collect::<Vec<&str>>()
+                    .join(" "),
+            ),
+            "translate" => {
+                let (s, from, to) = (string_arg(0)?, string_arg(1)?, string_arg(2)?);
+                let to: Vec<char> = to.chars().collect();
+                Value::Str(
+                    s.chars()
+                        .filter_map(|c| match from.chars().position(|f| f == c) {
+                            Some(i) => to.get(i).cloned(),
+                            None => Some(c),
+                        })
+                        .collect(),
+                )
+            }
+            "boolean" => Value::Bool(arg(0)?.to_bool()),
+            "not" => Value::Bool(!arg(0)?.to_bool()),
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            "number" => Value::Num(match args.first() {
+                Some(_) => arg(0)?.to_num(),
+                None => string_to_number(&focus.node.string_value()),
+            }),
+            "sum" => Value::Num(
+                self.eval_nodes(&args[0], focus)?
+                    .iter()
+                    .map(|node| string_to_number(&node.string_value()))
+                    .sum(),
+            ),
+            "floor" => Value::Num(arg(0)?.to_num().floor()),
+            "ceiling" => Value::Num(arg(0)?.to_num().ceil()),
+            "round" => Value::Num(round(arg(0)?.to_num())),
+            _ => return Err(self.error(&format!("unknown function {}()", name))),
+        })
+    }
+}
+
+/// Round half up, as XPath's `round()` does
+fn round(n: f64) -> f64 {
+    if n.is_nan() || n.is_infinite() {
+        n
+    } else {
+        (n + 0.5).floor()
+    }
+}
+
+fn compare_atoms(op: CompareOp, a: &Value, b: &Value) -> bool {
+    match op {
+        CompareOp::Eq | CompareOp::NotEq => {
+            let equal = match (a, b) {
+                (Value::Bool(_), _) | (_, Value::Bool(_)) => a.to_bool() == b.to_bool(),
+                (Value::Num(_), _) | (_, Value::Num(_)) => a.to_num() == b.to_num(),
+                _ => a.to_str() == b.to_str(),
+            };
+            equal == (op == CompareOp::Eq)
+        }
+        CompareOp::Lt => a.to_num() < b.to_num(),
+        CompareOp::LtEq => a.to_num() <= b.to_num(),
+        CompareOp::Gt => a.to_num() > b.to_num(),
+        CompareOp::GtEq => a.to_num() >= b.to_num(),
+    }
+}
+
+fn compare(op: CompareOp, a: &Value, b: &Value) -> bool {
+    let strings = |nodes: &[Node]| -> Vec<Value> {
+        nodes
+            .iter()
+            .map(|node| Value::Str(node.string_value()))
+            .collect()
+    };
+    match (a, b) {
+        (Value::Nodes(a), Value::Nodes(b)) => {
+            let b = strings(b);
+            strings(a)
+                .iter()
+                .any(|a| b.iter().any(|b| compare_atoms(op, a, b)))
+        }
+        (Value::Nodes(_), Value::Bool(_)) | (Value::Bool(_), Value::Nodes(_)) => {
+            compare_atoms(op, &Value::Bool(a.to_bool()), &Value::Bool(b.to_bool()))
+        }
+        (Value::Nodes(nodes), other) => strings(nodes)
+            .iter()
+            .any(|a| compare_atoms(op, &coerce(a, other), other)),
+        (other, Value::Nodes(nodes)) => strings(nodes)
+            .iter()
+            .any(|b| compare_atoms(op, other, &coerce(b, other))),
+        _ => compare_atoms(op, a, b),
+    }
+}
+
+/// Convert a node's string-value to the type of the value it's compared against
+fn coerce<'a>(string: &Value<'a>, other: &Value) -> Value<'a> {
+    match other {
+        Value::Num(_) => Value::Num(string.to_num()),
+        _ => string.clone(),
+    }
+}
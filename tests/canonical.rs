@@ -0,0 +1,336 @@
+extern crate treexml;
+
+mod canonical {
+
+    use treexml::{Canonicalization, Canonicalizer, Document, Element, TreexmlError};
+
+    fn parse(doc_raw: &str) -> Element {
+        Document::parse(doc_raw.as_bytes()).unwrap().root.unwrap()
+    }
+
+    fn canonicalize(method: Canonicalization, doc_raw: &str) -> String {
+        let mut v = Vec::new();
+        Canonicalizer::new(method)
+            .canonicalize(doc_raw.as_bytes(), &mut v)
+            .unwrap();
+        String::from_utf8(v).unwrap()
+    }
+
+    // Examples from section 3 of the Canonical XML 1.0 spec. Those in sections 3.3 to 3.5 rely
+    // on the DTD, which isn't processed.
+
+    #[test]
+    fn pis_comments_and_outside_of_document_element() {
+        let doc_raw = r#"<?xml version="1.0"?>
+
+<?xml-stylesheet   href="doc.xsl"
+   type="text/xsl"   ?>
+
+<!DOCTYPE doc SYSTEM "doc.dtd">
+
+<doc>Hello, world!<!-- Comment 1 --></doc>
+
+<?pi-without-data     ?>
+
+<!-- Comment 2 -->
+
+<!-- Comment 3 -->
+"#;
+        let without_comments = r#"<?xml-stylesheet href="doc.xsl"
+   type="text/xsl"   ?>
+<doc>Hello, world!</doc>
+<?pi-without-data?>"#;
+        let with_comments = r#"<?xml-stylesheet href="doc.xsl"
+   type="text/xsl"   ?>
+<doc>Hello, world!<!-- Comment 1 --></doc>
+<?pi-without-data?>
+<!-- Comment 2 -->
+<!-- Comment 3 -->"#;
+
+        for &method in &[Canonicalization::C14n10, Canonicalization::C14n11] {
+            assert_eq!(canonicalize(method, doc_raw), without_comments);
+        }
+        for &method in &[
+            Canonicalization::C14n10WithComments,
+            Canonicalization::C14n11WithComments,
+        ] {
+            assert_eq!(canonicalize(method, doc_raw), with_comments);
+        }
+    }
+
+    #[test]
+    fn whitespace_in_document_content() {
+        let doc_raw = r#"<doc>
+   <clean>   </clean>
+   <dirty>   A   B   </dirty>
+   <mixed>
+      A
+      <clean>   </clean>
+      B
+      <dirty>   A   B   </dirty>
+      C
+   </mixed>
+</doc>"#;
+
+        assert_eq!(canonicalize(Canonicalization::C14n10, doc_raw), doc_raw);
+    }
+
+    #[test]
+    fn utf8_encoding() {
+        let doc_raw = r#"<?xml version="1.0" encoding="ISO-8859-1"?>
+<doc>&#169;</doc>"#;
+
+        assert_eq!(
+            canonicalize(Canonicalization::C14n10, doc_raw),
+            "<doc>\u{a9}</doc>"
+        );
+    }
+
+    #[test]
+    fn line_endings_and_attribute_whitespace() {
+        let doc_raw = "<doc a=\"x\r\ny\tz&#9;\">one\r\ntwo\rthree&#13;</doc>";
+
+        assert_eq!(
+            canonicalize(Canonicalization::C14n10, doc_raw),
+            "<doc a=\"x y z&#x9;\">one\ntwo\nthree&#xD;</doc>"
+        );
+    }
+
+    #[test]
+    fn tree() {
+        let root = parse(
+            r#"<doc><e1 /><e5 a:attr="out" b:attr="sorted" attr2="all" attr="I'm" xmlns:b="http://www.ietf.org" xmlns:a="http://www.w3.org" xmlns="http://example.org"/><e6 xmlns="" xmlns:a="http://www.w3.org"><e7 xmlns="http://www.ietf.org"><e8 xmlns="" xmlns:a="http://www.w3.org" /></e7></e6></doc>"#,
+        );
+
+        assert_eq!(
+            root.canonicalize(Canonicalization::C14n10).unwrap(),
+            concat!(
+                "<doc><e1></e1>",
+                r#"<e5 xmlns="http://example.org" xmlns:a="http://www.w3.org" xmlns:b="http://www.ietf.org" attr="I'm" attr2="all" b:attr="sorted" a:attr="out"></e5>"#,
+                r#"<e6 xmlns:a="http://www.w3.org"><e7 xmlns="http://www.ietf.org"><e8 xmlns=""></e8></e7></e6>"#,
+                "</doc>",
+            )
+        );
+    }
+
+    #[test]
+    fn tree_errors() {
+        let root = parse("<doc>text<child /></doc>");
+        match root.canonicalize(Canonicalization::C14n10) {
+            Err(TreexmlError::Canonicalization { .. }) => {}
+            other => panic!("Mixed content should have failed, got {:?}", other),
+        }
+
+        let root = parse("<doc><child /></doc>");
+        match root.canonicalize(Canonicalization::C14n10WithComments) {
+            Err(TreexmlError::Canonicalization { .. }) => {}
+            other => panic!("Comments should have failed, got {:?}", other),
+        }
+
+        let mut unbound = Element::new("child");
+        unbound.prefix = Some("p".to_owned());
+        assert!(unbound.canonicalize(Canonicalization::C14n10).is_err());
+    }
+
+    #[test]
+    fn character_modifications() {
+        let root = parse(
+            r#"<doc>
+               <text>First line&#x0d;&#10;Second line</text>
+               <value>&#x32;</value>
+               <compute><![CDATA[value>"0" && value<"10" ?"valid":"error"]]></compute>
+               <compute expr='value>"0" &amp;&amp; value&lt;"10" ?"valid":"error"'>valid</compute>
+               <norm attr=' &apos;   &#x20;&#13;&#xa;&#9;   &apos; '/>
+            </doc>"#,
+        );
+        let expected = concat!(
+            "<doc>",
+            "<text>First line&#xD;\nSecond line</text>",
+            "<value>2</value>",
+            r#"<compute>value&gt;"0" &amp;&amp; value&lt;"10" ?"valid":"error"</compute>"#,
+            r#"<compute expr="value>&quot;0&quot; &amp;&amp; value&lt;&quot;10&quot; ?&quot;valid&quot;:&quot;error&quot;">valid</compute>"#,
+            r#"<norm attr=" '    &#xD;&#xA;&#x9;   ' "></norm>"#,
+            "</doc>",
+        );
+
+        let mut v = Vec::new();
+        root.write_canonical(&mut v, Canonicalization::C14n11)
+            .unwrap();
+        assert_eq!(String::from_utf8(v).unwrap(), expected);
+    }
+
+    #[test]
+    fn subtree_and_built_namespaces() {
+        // A subtree only knows the declarations made inside it
+        let root = parse(r#"<a xmlns="urn:a"><p:b xmlns:p="urn:p" z="1" p:y="2"><c /></p:b></a>"#);
+        assert_eq!(
+            root.children[0]
+                .canonicalize(Canonicalization::C14n10)
+                .unwrap(),
+            r#"<p:b xmlns:p="urn:p" z="1" p:y="2"><c></c></p:b>"#
+        );
+
+        let mut built = Element::new("r");
+        built
+            .attributes
+            .insert("xmlns".to_owned(), "urn:r".to_owned());
+        built.children.push(Element::new("s"));
+        assert_eq!(
+            built.canonicalize(Canonicalization::C14n10).unwrap(),
+            r#"<r xmlns="urn:r"><s></s></r>"#
+        );
+    }
+
+    #[test]
+    fn exclusive_subtrees() {
+        // From section 2.2 of the Exclusive XML Canonicalization spec
+        let first = r#"<n0:local xmlns:n0="foo:bar" xmlns:n3="ftp://example.org">
+   <n1:elem2 xmlns:n1="http://example.net" xml:lang="en">
+      <n3:stuff xmlns:n3="ftp://example.org"/>
+   </n1:elem2>
+</n0:local>"#;
+        let second = r#"<n2:pdu xmlns:n1="http://example.com"
+           xmlns:n2="http://foo.example"
+           xml:lang="fr"
+           xml:space="retain">
+   <n1:elem2 xmlns:n1="http://example.net" xml:lang="en">
+      <n3:stuff xmlns:n3="ftp://example.org"/>
+   </n1:elem2>
+</n2:pdu>"#;
+
+        let subtree = |method, inclusive: &[&str], doc_raw: &str| {
+            let mut v = Vec::new();
+            Canonicalizer::new(method)
+                .inclusive_prefixes(inclusive)
+                .subtree(|el| el.name == "elem2")
+                .canonicalize(doc_raw.as_bytes(), &mut v)
+                .unwrap();
+            String::from_utf8(v).unwrap()
+        };
+
+        assert_eq!(
+            subtree(Canonicalization::C14n10, &[], first),
+            r#"<n1:elem2 xmlns:n0="foo:bar" xmlns:n1="http://example.net" xmlns:n3="ftp://example.org" xml:lang="en">
+      <n3:stuff></n3:stuff>
+   </n1:elem2>"#
+        );
+        assert_eq!(
+            subtree(Canonicalization::C14n10, &[], second),
+            r#"<n1:elem2 xmlns:n1="http://example.net" xmlns:n2="http://foo.example" xml:lang="en" xml:space="retain">
+      <n3:stuff xmlns:n3="ftp://example.org"></n3:stuff>
+   </n1:elem2>"#
+        );
+
+        let exclusive = r#"<n1:elem2 xmlns:n1="http://example.net" xml:lang="en">
+      <n3:stuff xmlns:n3="ftp://example.org"></n3:stuff>
+   </n1:elem2>"#;
+        assert_eq!(subtree(Canonicalization::ExcC14n, &[], first), exclusive);
+        assert_eq!(subtree(Canonicalization::ExcC14n, &[], second), exclusive);
+
+        assert_eq!(
+            subtree(Canonicalization::ExcC14n, &["n3"], first),
+            r#"<n1:elem2 xmlns:n1="http://example.net" xmlns:n3="ftp://example.org" xml:lang="en">
+      <n3:stuff></n3:stuff>
+   </n1:elem2>"#
+        );
+    }
+
+    #[test]
+    fn subtree_xml_attributes() {
+        let doc_raw = r#"<a xml:lang="fr" xml:space="preserve" xml:id="a"><!-- c --><b><c xml:lang="en">t</c></b></a>"#;
+        let subtree = |method| {
+            let mut v = Vec::new();
+            Canonicalizer::new(method)
+                .subtree(|el| el.name == "c")
+                .canonicalize(doc_raw.as_bytes(), &mut v)
+                .map(|_| String::from_utf8(v).unwrap())
+        };
+
+        assert_eq!(
+            subtree(Canonicalization::C14n10WithComments).unwrap(),
+            r#"<c xml:id="a" xml:lang="en" xml:space="preserve">t</c>"#
+        );
+        assert_eq!(
+            subtree(Canonicalization::C14n11).unwrap(),
+            r#"<c xml:lang="en" xml:space="preserve">t</c>"#
+        );
+        assert_eq!(
+            subtree(Canonicalization::ExcC14n).unwrap(),
+            r#"<c xml:lang="en">t</c>"#
+        );
+
+        let doc_raw = r#"<a xml:base="http://example.org/"><c /></a>"#;
+        let mut v = Vec::new();
+        match Canonicalizer::new(Canonicalization::C14n11)
+            .subtree(|el| el.name == "c")
+            .canonicalize(doc_raw.as_bytes(), &mut v)
+        {
+            Err(TreexmlError::Canonicalization { .. }) => {}
+            other => panic!("xml:base fix-up should have failed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn pretty_printed_source() {
+        let doc_raw = r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol">
+   <saml:Assertion xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion" ID="a1">
+      <saml:Issuer>idp</saml:Issuer>
+      <saml:Subject>
+         <saml:NameID>user</saml:NameID>
+      </saml:Subject>
+   </saml:Assertion>
+</samlp:Response>"#;
+        let expected = r#"<saml:Assertion xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion" ID="a1">
+      <saml:Issuer>idp</saml:Issuer>
+      <saml:Subject>
+         <saml:NameID>user</saml:NameID>
+      </saml:Subject>
+   </saml:Assertion>"#;
+
+        let mut v = Vec::new();
+        Canonicalizer::new(Canonicalization::ExcC14n)
+            .subtree(|el| el.name == "Assertion")
+            .canonicalize(doc_raw.as_bytes(), &mut v)
+            .unwrap();
+        assert_eq!(String::from_utf8(v).unwrap(), expected);
+
+        // The parsed tree has lost the indentation, which is why the tree methods are only
+        // meant for trees built in code
+        let assertion = parse(doc_raw).children[0].clone();
+        let from_tree = assertion.canonicalize_exclusive(&[]).unwrap();
+        assert_ne!(from_tree, expected);
+        assert_eq!(
+            from_tree,
+            expected
+                .split('\n')
+                .map(str::trim_start)
+                .collect::<String>()
+        );
+    }
+
+    #[test]
+    fn exclusive() {
+        let doc_raw = r#"<p:Signed xmlns="urn:d" xmlns:p="urn:p" xmlns:q="urn:q"><Info q:id="1"><p:Value /></Info></p:Signed>"#;
+        let signed = parse(doc_raw);
+        assert_eq!(
+            signed.canonicalize_exclusive(&[]).unwrap(),
+            r#"<p:Signed xmlns:p="urn:p"><Info xmlns="urn:d" xmlns:q="urn:q" q:id="1"><p:Value></p:Value></Info></p:Signed>"#
+        );
+        assert_eq!(
+            signed.canonicalize(Canonicalization::ExcC14n).unwrap(),
+            signed.canonicalize_exclusive(&[]).unwrap()
+        );
+
+        let expected = r#"<p:Signed xmlns="urn:d" xmlns:p="urn:p"><Info xmlns:q="urn:q" q:id="1"><p:Value></p:Value></Info></p:Signed>"#;
+        assert_eq!(
+            signed.canonicalize_exclusive(&["#default"]).unwrap(),
+            expected
+        );
+        let mut v = Vec::new();
+        Canonicalizer::new(Canonicalization::ExcC14n)
+            .inclusive_prefixes(&["#default"])
+            .canonicalize(doc_raw.as_bytes(), &mut v)
+            .unwrap();
+        assert_eq!(String::from_utf8(v).unwrap(), expected);
+    }
+}
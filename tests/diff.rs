@@ -0,0 +1,142 @@
+extern crate treexml;
+
+mod diff {
+
+    use treexml::{DiffOptions, Document, Edit, Element, Path};
+
+    fn parse(doc_raw: &str) -> Element {
+        Document::parse(doc_raw.as_bytes()).unwrap().root.unwrap()
+    }
+
+    #[test]
+    fn identical() {
+        let old = parse(r#"<config><server port="80">web</server></config>"#);
+
+        assert!(old.diff(&old.clone()).is_empty());
+    }
+
+    #[test]
+    fn attributes_and_text() {
+        let old = parse(r#"<config><server host="a" port="80" tls="no">web</server></config>"#);
+        let new = parse(r#"<config><server port="8080" host="a" ipv6="yes">db</server></config>"#);
+
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff.edits,
+            vec![
+                Edit::AttributeChanged {
+                    path: "/config/server".to_owned(),
+                    name: "port".to_owned(),
+                    old: "80".to_owned(),
+                    new: "8080".to_owned(),
+                },
+                Edit::AttributeRemoved {
+                    path: "/config/server".to_owned(),
+                    name: "tls".to_owned(),
+                    value: "no".to_owned(),
+                },
+                Edit::AttributeAdded {
+                    path: "/config/server".to_owned(),
+                    name: "ipv6".to_owned(),
+                    value: "yes".to_owned(),
+                },
+                Edit::AttributesReordered {
+                    path: "/config/server".to_owned(),
+                    old: vec!["host".to_owned(), "port".to_owned()],
+                    new: vec!["port".to_owned(), "host".to_owned()],
+                },
+                Edit::TextChanged {
+                    path: "/config/server".to_owned(),
+                    old: Some("web".to_owned()),
+                    new: Some("db".to_owned()),
+                },
+            ]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "changed /config/server/@port from \"80\" to \"8080\"\n\
+             removed /config/server/@tls (was \"no\")\n\
+             added /config/server/@ipv6 = \"yes\"\n\
+             reordered attributes of /config/server from host, port to port, host\n\
+             changed text of /config/server from \"web\" to \"db\"\n"
+        );
+
+        let diff = old.diff_with(&new, DiffOptions::new().ignore_attribute_order(true));
+        assert_eq!(diff.edits.len(), 4);
+    }
+
+    #[test]
+    fn children() {
+        let old = parse("<list><a>1</a><b>2</b><c>3</c><d>4</d></list>");
+        let new = parse("<list><b>2</b><c>three</c><a>1</a><e /><d>4</d><d>5</d></list>");
+
+        assert_eq!(
+            old.diff(&new).to_string(),
+            "changed text of /list/c from \"3\" to \"three\"\n\
+             moved /list/a to /list/a\n\
+             inserted /list/e\n\
+             inserted /list/d[2]\n"
+        );
+        assert_eq!(
+            new.diff(&old).to_string(),
+            "removed /list/e\n\
+             removed /list/d[2]\n\
+             moved /list/b to /list/b\n\
+             changed text of /list/c from \"three\" to \"3\"\n"
+        );
+    }
+
+    #[test]
+    fn ignored() {
+        let old = parse(r#"<log at="1"><entry id="1">start</entry><time>09:00</time></log>"#);
+        let mut new = parse(r#"<log at="2"><entry id="1">start</entry><time>10:00</time></log>"#);
+        new.text = Some("\n".to_owned());
+
+        let mut options = DiffOptions::new();
+        options
+            .ignore("time".parse::<Path>().unwrap())
+            .ignore(Path::compile("@at").unwrap())
+            .ignore_whitespace_text(true);
+        assert!(old.diff_with(&new, &options).is_empty());
+        assert_eq!(old.diff(&new).edits.len(), 3);
+    }
+
+    #[test]
+    fn ignored_shared_subtrees() {
+        let mut frag = Element::new("frag");
+        frag.children.push(Element::new("leaf"));
+        let mut old = Element::new("root");
+        old.children.push(frag.clone());
+        old.children.push(frag);
+        let mut new = old.clone();
+        for frag in new.children.iter_mut() {
+            frag.children[0].text = Some("changed".to_owned());
+        }
+
+        let mut options = DiffOptions::new();
+        options.ignore(Path::compile("frag[2]/leaf").unwrap());
+        assert_eq!(
+            old.diff_with(&new, &options).to_string(),
+            "changed text of /root/frag[1]/leaf from nothing to \"changed\"\n"
+        );
+    }
+
+    #[test]
+    fn long_child_lists() {
+        let mut old = Element::new("list");
+        for i in 0..10_000 {
+            let mut item = Element::new("item");
+            item.text = Some(i.to_string());
+            old.children.push(item);
+        }
+        let mut new = old.clone();
+        new.children[0].text = Some("first".to_owned());
+        new.children[9_999].text = Some("last".to_owned());
+
+        assert_eq!(
+            old.diff(&new).to_string(),
+            "changed text of /list/item[1] from \"0\" to \"first\"\n\
+             changed text of /list/item[10000] from \"9999\" to \"last\"\n"
+        );
+    }
+}
@@ -0,0 +1,131 @@
+extern crate treexml;
+
+mod merge {
+
+    use treexml::{ChildMode, Conflict, Document, Element, MatchBy, MergeRules, TreexmlError};
+
+    fn parse(doc_raw: &str) -> Element {
+        Document::parse(doc_raw.as_bytes()).unwrap().root.unwrap()
+    }
+
+    fn base() -> Element {
+        parse(
+            r#"
+            <config env="base">
+                <log level="info">stdout</log>
+                <servers>
+                    <server id="a" port="80" />
+                    <server id="b" port="81" />
+                </servers>
+                <plugins><plugin>auth</plugin></plugins>
+                <hosts><host>one</host><host>two</host></hosts>
+            </config>
+            "#,
+        )
+    }
+
+    fn production() -> Element {
+        parse(
+            r#"
+            <config env="production">
+                <log level="warn" />
+                <servers>
+                    <server id="b" port="443" />
+                    <server id="c" port="8080" />
+                </servers>
+                <plugins><plugin>metrics</plugin></plugins>
+                <hosts><host /><host>TWO</host></hosts>
+            </config>
+            "#,
+        )
+    }
+
+    #[test]
+    fn defaults() {
+        let merged = MergeRules::new().merge(&base(), &[&production()]).unwrap();
+        let root = merged.root();
+
+        assert_eq!(root.attributes["env"], "production");
+        assert_eq!(
+            root.find_value::<String>("log/@level").unwrap().unwrap(),
+            "warn"
+        );
+        assert_eq!(root.find("log").unwrap().text.as_deref(), Some("stdout"));
+        // Matched by name, so every server merges into the first
+        assert_eq!(root.count("servers/server").unwrap(), 2);
+        assert_eq!(root.count("plugins/plugin").unwrap(), 1);
+
+        assert_eq!(merged.origin("log/@level").unwrap(), Some(1));
+        assert_eq!(merged.text_origin("log").unwrap(), Some(0));
+        assert_eq!(merged.origin("plugins").unwrap(), Some(0));
+        assert_eq!(merged.origin("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn rules() {
+        let mut rules = MergeRules::new();
+        rules
+            .match_by(
+                "servers".parse().unwrap(),
+                MatchBy::Attribute("id".to_owned()),
+            )
+            .children("plugins".parse().unwrap(), ChildMode::Append)
+            .match_by("hosts".parse().unwrap(), MatchBy::Position)
+            .conflicts("log".parse().unwrap(), Conflict::Keep);
+        let host = parse(r#"<config><plugins><plugin>debug</plugin></plugins></config>"#);
+
+        let merged = rules.merge(&base(), &[&production(), &host]).unwrap();
+        let root = merged.root();
+
+        assert_eq!(
+            root.find_values::<String>("servers/server/@port").unwrap(),
+            vec!["80", "443", "8080"]
+        );
+        assert_eq!(
+            root.find_values::<String>("plugins/plugin").unwrap(),
+            vec!["auth", "metrics", "debug"]
+        );
+        assert_eq!(
+            root.find_values::<String>("hosts/host").unwrap(),
+            vec!["one", "TWO"]
+        );
+        assert_eq!(
+            root.find_value::<String>("log/@level").unwrap().unwrap(),
+            "info"
+        );
+
+        assert_eq!(merged.origin("servers/server[@id='a']").unwrap(), Some(0));
+        assert_eq!(
+            merged.origin("servers/server[@id='b']/@port").unwrap(),
+            Some(1)
+        );
+        assert_eq!(merged.origin("servers/server[@id='c']").unwrap(), Some(1));
+        assert_eq!(merged.origin("plugins/plugin[3]").unwrap(), Some(2));
+        assert_eq!(merged.text_origin("hosts/host[2]").unwrap(), Some(1));
+    }
+
+    #[test]
+    fn replace_and_conflicts() {
+        let mut rules = MergeRules::new();
+        rules
+            .default_conflicts(Conflict::Error)
+            .children("hosts".parse().unwrap(), ChildMode::Replace)
+            .conflicts("//*".parse().unwrap(), Conflict::Override);
+        let layer = parse(r#"<config><hosts><host>three</host></hosts></config>"#);
+
+        let merged = rules.merge(&base(), &[&layer]).unwrap();
+        assert_eq!(
+            merged.root().find_values::<String>("hosts/host").unwrap(),
+            vec!["three"]
+        );
+        assert_eq!(merged.origin("hosts/host").unwrap(), Some(1));
+
+        match rules
+            .merge(&base(), &[&production()])
+            .expect_err("Should have errored")
+        {
+            TreexmlError::MergeConflict { path, .. } => assert_eq!(path, "/config"),
+            _ => panic!("Error should have been MergeConflict"),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+extern crate treexml;
+
+mod patch {
+
+    use treexml::{Document, Element, Patch, TreexmlError};
+
+    fn parse(doc_raw: &str) -> Element {
+        Document::parse(doc_raw.as_bytes()).unwrap().root.unwrap()
+    }
+
+    fn config() -> Document {
+        let doc_raw = r#"
+        <config xmlns:v="urn:vendor">
+            <server host="a" port="80">web</server>
+            <v:feature name="beta" />
+            <limits><max>10</max></limits>
+        </config>
+        "#;
+
+        Document::parse(doc_raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn apply() {
+        let patch = Patch::parse(
+            r#"
+            <diff xmlns:x="urn:vendor">
+                <add sel="/config/server" type="@tls">yes</add>
+                <replace sel="/config/server/@port">8080</replace>
+                <replace sel="/config/server/text()">api</replace>
+                <remove sel="/config/x:feature" />
+                <add sel="/config/server" pos="before"><proxy /></add>
+                <add sel="/config/limits" pos="prepend"><min>1</min></add>
+                <replace sel="/config/limits/max"><max>20</max></replace>
+            </diff>
+            "#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let mut doc = config();
+        patch.apply(&mut doc).unwrap();
+        let root = doc.root.unwrap();
+
+        let names: Vec<&str> = root.children.iter().map(|el| el.name.as_str()).collect();
+        assert_eq!(names, vec!["proxy", "server", "limits"]);
+        let server = root.find("server").unwrap();
+        assert_eq!(server.attributes["port"], "8080");
+        assert_eq!(server.attributes["tls"], "yes");
+        assert_eq!(server.text.as_deref(), Some("api"));
+        assert_eq!(root.find_values::<u32>("limits/*").unwrap(), vec![1, 20]);
+    }
+
+    #[test]
+    fn atomic() {
+        let patch = Patch::parse(
+            r#"
+            <diff>
+                <remove sel="/config/server/@host" />
+                <remove sel="/config/missing" />
+            </diff>
+            "#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let mut doc = config();
+        match patch.apply(&mut doc).expect_err("Should have errored") {
+            TreexmlError::PatchFailed { sel, .. } => assert_eq!(sel, "/config/missing"),
+            _ => panic!("Error should have been PatchFailed"),
+        }
+        assert_eq!(doc, config());
+
+        let ambiguous = Patch::parse(r#"<diff><remove sel="//*" /></diff>"#.as_bytes()).unwrap();
+        assert!(ambiguous.apply(&mut doc).is_err());
+    }
+
+    #[test]
+    fn shared_subtrees() {
+        let mut frag = Element::new("frag");
+        frag.children.push(Element::new("leaf"));
+        frag.children[0].text = Some("x".to_owned());
+        let mut root = Element::new("root");
+        root.children.push(frag.clone());
+        root.children.push(frag);
+
+        let patch = Patch::parse(
+            r#"
+            <diff>
+                <add sel="/root/frag[2]/leaf" type="@k">v</add>
+                <replace sel="/root/frag[2]/leaf/text()">y</replace>
+            </diff>
+            "#
+            .as_bytes(),
+        )
+        .unwrap();
+        patch.apply_to(&mut root).unwrap();
+
+        let first = root.find("frag[1]/leaf").unwrap();
+        assert_eq!(first.attributes.get("k"), None);
+        assert_eq!(first.text.as_deref(), Some("x"));
+        let second = root.find("frag[2]/leaf").unwrap();
+        assert_eq!(second.attributes["k"], "v");
+        assert_eq!(second.text.as_deref(), Some("y"));
+    }
+
+    #[test]
+    fn invalid() {
+        for doc_raw in &[
+            r#"<diff><move sel="/a" /></diff>"#,
+            r#"<diff><add /></diff>"#,
+            r#"<diff><add sel="/a" pos="inside" /></diff>"#,
+            r#"<diff><add sel="/a" type="@b" pos="before" /></diff>"#,
+        ] {
+            match Patch::parse(doc_raw.as_bytes()).expect_err("Should have errored") {
+                TreexmlError::InvalidPatch { .. } => {}
+                err => panic!("Error should have been InvalidPatch, got {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn between() {
+        let old =
+            parse(r#"<list a="1" b="2"><item>1</item><item>2</item><x>keep</x><y>move</y></list>"#);
+        let new = parse(
+            r#"<list b="3" c="4"><y>move</y><item>1</item><x>kept<![CDATA[!]]></x><item>3</item></list>"#,
+        );
+
+        let patch = Patch::between(&old, &new);
+        let mut patched = old.clone();
+        patch.apply_to(&mut patched).unwrap();
+        assert_eq!(patched, new);
+
+        // Round trip through text
+        let reparsed = Patch::parse(patch.to_string().as_bytes()).unwrap();
+        let mut patched = old.clone();
+        reparsed.apply_to(&mut patched).unwrap();
+        assert!(patched.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn between_default_namespace() {
+        let old = parse(r#"<feed xmlns="urn:atom"><entry>a</entry><entry>b</entry></feed>"#);
+        let new = parse(r#"<feed xmlns="urn:atom"><entry>b</entry><entry>c</entry></feed>"#);
+
+        let patch = Patch::between(&old, &new);
+        assert_eq!(
+            patch
+                .as_element()
+                .attributes
+                .get("xmlns:d1")
+                .map(String::as_str),
+            Some("urn:atom")
+        );
+        let mut patched = old.clone();
+        patch.apply_to(&mut patched).unwrap();
+        assert_eq!(patched, new);
+    }
+}
@@ -28,8 +28,7 @@ mod read {
 
         #[test]
         fn no_root_tag() {
-            let doc_raw = r#"
-            <?xml version="1.1" encoding="UTF-8"?>
+            let doc_raw = r#"<?xml version="1.1" encoding="UTF-8"?>
             "#;
 
             let _ = Document::parse(doc_raw.as_bytes()).expect_err("Should have errored");
@@ -38,7 +37,7 @@ mod read {
 
     mod tags {
 
-        use treexml::Document;
+        use treexml::{Document, Element};
 
         #[test]
         fn self_closing() {
@@ -51,6 +50,27 @@ mod read {
             let _ = Document::parse(doc_raw.as_bytes()).unwrap();
         }
 
+        #[test]
+        fn declared_namespaces() {
+            let doc_raw = r#"<a:root xmlns:a="urn:a" xmlns="urn:d"><a:child><leaf xmlns="" /></a:child></a:root>"#;
+
+            let root = Document::parse(doc_raw.as_bytes()).unwrap().root.unwrap();
+
+            assert_eq!(root.namespaces.len(), 2);
+            assert!(root.children[0].namespaces.is_empty());
+            let leaf = &root.children[0].children[0];
+            assert_eq!(leaf.namespaces.get("").map(String::as_str), Some(""));
+
+            let mut built = Element::new("child");
+            built.prefix = Some("a".to_owned());
+            built.children.push({
+                let mut leaf = Element::new("leaf");
+                leaf.namespaces.insert(String::new(), String::new());
+                leaf
+            });
+            assert_eq!(root.children[0], built);
+        }
+
         #[test]
         fn self_closing_no_space() {
             let doc_raw = r#"
@@ -91,7 +111,7 @@ mod read {
     }
 
     mod element {
-        use treexml::{Document, Element, TreexmlError};
+        use treexml::{Document, Element, Path, TextOptions, TreexmlError};
 
         #[test]
         fn find_child_none() {
@@ -245,6 +265,308 @@ mod read {
             }
         }
 
+        #[test]
+        fn find_predicates() {
+            let doc_raw = r#"
+            <root>
+                <servers>
+                    <server name="web"><port>80</port></server>
+                    <server name="db"><port>5432</port></server>
+                    <server name="cache" />
+                </servers>
+            </root>
+            "#;
+
+            let doc = Document::parse(doc_raw.as_bytes()).unwrap();
+            let root = doc.root.unwrap();
+
+            assert_eq!(
+                root.find_value("servers/server[2]/port").unwrap(),
+                Some(5432)
+            );
+            assert_eq!(
+                root.find_value("servers/server[@name='db']/port").unwrap(),
+                Some(5432)
+            );
+            assert_eq!(
+                root.find("servers/*[@name][3]").unwrap().attributes["name"],
+                "cache"
+            );
+            assert!(root.find("servers/server[4]").is_err());
+        }
+
+        #[test]
+        fn find_explores_branches() {
+            let doc_raw = r#"
+            <root>
+                <server><name>web</name></server>
+                <server><port>8080</port></server>
+            </root>
+            "#;
+
+            let doc = Document::parse(doc_raw.as_bytes()).unwrap();
+            let root = doc.root.unwrap();
+
+            assert_eq!(root.find_value("server/port").unwrap(), Some(8080));
+        }
+
+        #[test]
+        fn find_descendants() {
+            let doc_raw = r#"
+            <root xmlns:x="urn:x">
+                <a><deep><leaf>1</leaf></deep></a>
+                <b><x:leaf>2</x:leaf></b>
+            </root>
+            "#;
+
+            let doc = Document::parse(doc_raw.as_bytes()).unwrap();
+            let root = doc.root.unwrap();
+
+            assert_eq!(root.find_value("//leaf").unwrap(), Some(1));
+            assert_eq!(root.find_value("//x:leaf").unwrap(), Some(2));
+            assert_eq!(root.find_value("b//leaf").unwrap(), Some(2));
+            assert_eq!(root.find_value("*/*/leaf").unwrap(), Some(1));
+        }
+
+        #[test]
+        fn find_all() {
+            let doc_raw = r#"
+            <root>
+                <items>
+                    <item>1</item>
+                    <item>2</item>
+                    <group><item>3</item></group>
+                </items>
+                <items>
+                    <item>4</item>
+                    <item />
+                </items>
+            </root>
+            "#;
+
+            let doc = Document::parse(doc_raw.as_bytes()).unwrap();
+            let root = doc.root.unwrap();
+
+            let texts: Vec<&str> = root
+                .find_all("items/item")
+                .unwrap()
+                .filter_map(|el| el.text.as_deref())
+                .collect();
+            assert_eq!(texts, vec!["1", "2", "4"]);
+
+            assert_eq!(root.find_values::<i32>("//item").unwrap(), vec![1, 2, 3, 4]);
+            assert_eq!(root.count("items/item").unwrap(), 4);
+            assert_eq!(root.count("//item").unwrap(), 5);
+            assert_eq!(root.count("//items[2]").unwrap(), 1);
+            assert_eq!(root.count("missing").unwrap(), 0);
+
+            match root
+                .find_values::<bool>("//item")
+                .expect_err("Should have errored")
+            {
+                TreexmlError::ValueFromStr { path, t, target } => {
+                    assert_eq!(path, "/root/items[1]/item[1]");
+                    assert_eq!(t, "1");
+                    assert_eq!(target, "bool");
+                }
+                _ => panic!("Error should have been ValueFromStr"),
+            }
+        }
+
+        #[test]
+        fn find_all_mut() {
+            let doc_raw = r#"
+            <root>
+                <section><title>a</title><section><title>b</title></section></section>
+                <section><title>c</title></section>
+            </root>
+            "#;
+
+            let doc = Document::parse(doc_raw.as_bytes()).unwrap();
+            let mut root = doc.root.unwrap();
+
+            for title in root.find_all_mut("//title").unwrap() {
+                title.text = title.text.as_ref().map(|t| t.to_uppercase());
+            }
+            assert_eq!(
+                root.find_values::<String>("//title").unwrap(),
+                vec!["A", "B", "C"]
+            );
+
+            // Nested matches are reached through the outer one
+            assert_eq!(root.find_all_mut("//section").unwrap().count(), 2);
+        }
+
+        #[test]
+        fn find_mut() {
+            let doc_raw = r#"
+            <root>
+                <a><b><c>1</c></b></a>
+            </root>
+            "#;
+
+            let doc = Document::parse(doc_raw.as_bytes()).unwrap();
+            let mut root = doc.root.unwrap();
+
+            root.find_mut("a/b/c").unwrap().text = Some("2".to_owned());
+            assert_eq!(root.find_value("a/b/c").unwrap(), Some(2));
+
+            match root.find_mut("a/z").expect_err("Should have errored") {
+                TreexmlError::ElementNotFound { .. } => {}
+                _ => panic!("Error should have been ElementNotFound"),
+            }
+        }
+
+        #[test]
+        fn set_value() {
+            let doc_raw = r#"
+            <config>
+                <server name="web"><port>80</port></server>
+            </config>
+            "#;
+
+            let doc = Document::parse(doc_raw.as_bytes()).unwrap();
+            let mut root = doc.root.unwrap();
+
+            root.set_value("server/port", 8080).unwrap();
+            root.set_value("server/host", "localhost").unwrap();
+            root.set_value("server[@name='db']/port", 5432).unwrap();
+            root.set_value("logging/level", "debug").unwrap();
+
+            assert_eq!(root.find_value("server/port").unwrap(), Some(8080));
+            assert_eq!(
+                root.find_value("server/host").unwrap(),
+                Some("localhost".to_owned())
+            );
+            assert_eq!(
+                root.find_value("server[@name='db']/port").unwrap(),
+                Some(5432)
+            );
+            assert_eq!(
+                root.find_value("logging/level").unwrap(),
+                Some("debug".to_owned())
+            );
+            assert_eq!(root.count("server").unwrap(), 2);
+            assert_eq!(root.children.len(), 3);
+        }
+
+        #[test]
+        fn ensure_path() {
+            let mut root = Element::new("root");
+
+            root.ensure_path("list/item[1]").unwrap();
+            root.ensure_path("list/item[2]").unwrap().text = Some("second".to_owned());
+            root.ensure_path("list/item[1]").unwrap().text = Some("first".to_owned());
+
+            assert_eq!(
+                root.find_values::<String>("list/item").unwrap(),
+                vec!["first", "second"]
+            );
+
+            for path in &["list/item[4]", "other/*", "//missing", "list/item[@id]"] {
+                match root.ensure_path(path).expect_err("Should have errored") {
+                    TreexmlError::PathNotCreatable { .. } => {}
+                    _ => panic!("Error should have been PathNotCreatable"),
+                }
+            }
+            assert_eq!(root.count("list/item").unwrap(), 2);
+        }
+
+        #[test]
+        fn find_attribute() {
+            let doc_raw = r#"
+            <root>
+                <server name="web" />
+                <server name="db" port="5432" />
+                <server name="cache" port="fast" />
+            </root>
+            "#;
+
+            let doc = Document::parse(doc_raw.as_bytes()).unwrap();
+            let mut root = doc.root.unwrap();
+
+            assert_eq!(root.find_value("server/@port").unwrap(), Some(5432));
+            assert_eq!(
+                root.find_values::<String>("//@name").unwrap(),
+                vec!["web", "db", "cache"]
+            );
+            assert_eq!(root.count("server/@port").unwrap(), 2);
+
+            match root
+                .find_values::<u16>("server/@port")
+                .expect_err("Should have errored")
+            {
+                TreexmlError::AttributeFromStr {
+                    path,
+                    attr,
+                    value,
+                    target,
+                } => {
+                    assert_eq!(path, "/root/server[3]");
+                    assert_eq!(attr, "port");
+                    assert_eq!(value, "fast");
+                    assert_eq!(target, "u16");
+                }
+                _ => panic!("Error should have been AttributeFromStr"),
+            }
+
+            root.set_value("server[@name='web']/@port", 80).unwrap();
+            assert_eq!(root.find_value("server[1]/@port").unwrap(), Some(80));
+
+            match root.find("server/@port/name") {
+                Err(TreexmlError::PathSyntax { .. }) => {}
+                _ => panic!("Error should have been PathSyntax"),
+            }
+        }
+
+        #[test]
+        fn attr() {
+            let mut el = Element::new("server");
+            el.prefix = Some("net".to_owned());
+            el.attributes.insert("port".to_owned(), "8080".to_owned());
+            el.attributes
+                .insert("host".to_owned(), "local host".to_owned());
+
+            assert_eq!(el.attr::<u16>("port").unwrap(), Some(8080));
+            assert_eq!(el.attr::<u16>("timeout").unwrap(), None);
+            assert_eq!(el.attr_required::<String>("host").unwrap(), "local host");
+
+            match el
+                .attr_required::<u16>("timeout")
+                .expect_err("Should have errored")
+            {
+                TreexmlError::AttributeNotFound { path, attr } => {
+                    assert_eq!((path.as_str(), attr.as_str()), ("/net:server", "timeout"));
+                }
+                _ => panic!("Error should have been AttributeNotFound"),
+            }
+
+            let err = el.attr::<bool>("host").expect_err("Should have errored");
+            assert_eq!(
+                err.to_string(),
+                "Attribute 'host' on '/net:server' could not be parsed as bool: 'local host'"
+            );
+        }
+
+        #[test]
+        fn find_syntax_error() {
+            let doc_raw = r#"
+            <root><child /></root>
+            "#;
+
+            let doc = Document::parse(doc_raw.as_bytes()).unwrap();
+            let root = doc.root.unwrap();
+
+            match root.find("child[0]").expect_err("Should have errored") {
+                TreexmlError::PathSyntax { pos, .. } => assert_eq!(pos, 6),
+                _ => panic!("Error should have been PathSyntax"),
+            }
+            match root.find("child/").expect_err("Should have errored") {
+                TreexmlError::PathSyntax { pos, .. } => assert_eq!(pos, 6),
+                _ => panic!("Error should have been PathSyntax"),
+            }
+        }
+
         #[test]
         fn find_value() {
             let doc_raw = r#"
@@ -263,10 +585,134 @@ mod read {
 
             let cant_parse = root.find_value::<i32>("word");
             println!("cant parse was {:?}", cant_parse);
-            match cant_parse.expect_err("Should have errored") {
-                TreexmlError::ValueFromStr { .. } => {}
+            let err = cant_parse.expect_err("Should have errored");
+            match err {
+                TreexmlError::ValueFromStr { ref path, .. } => assert_eq!(path, "/root/word"),
                 _ => panic!("Error should have been ValueFromStr"),
             }
+            assert_eq!(
+                err.to_string(),
+                "Text of '/root/word' could not be parsed as i32: 'hello'"
+            );
+        }
+
+        #[test]
+        fn compiled_path() {
+            let records: Vec<Element> = ["<r><id>1</id><tag k='a' /></r>", "<r><id>2</id></r>"]
+                .iter()
+                .map(|raw| Document::parse(raw.as_bytes()).unwrap().root.unwrap())
+                .collect();
+
+            let id: Path = "id".parse().unwrap();
+            let tag = Path::compile("tag/@k").unwrap();
+            assert_eq!(tag.as_str(), "tag/@k");
+            assert_eq!(tag.attribute(), Some("k"));
+
+            let ids: Vec<Option<u32>> = records.iter().map(|r| id.find_value(r).unwrap()).collect();
+            assert_eq!(ids, vec![Some(1), Some(2)]);
+            assert_eq!(
+                tag.find_value::<String>(&records[0]).unwrap(),
+                Some("a".to_owned())
+            );
+            assert_eq!(tag.count(&records[1]), 0);
+            match tag.find(&records[1]).expect_err("Should have errored") {
+                TreexmlError::ElementNotFound { t } => assert_eq!(t, "tag/@k"),
+                _ => panic!("Error should have been ElementNotFound"),
+            }
+
+            match "id[".parse::<Path>().expect_err("Should have errored") {
+                TreexmlError::PathSyntax { pos, .. } => assert_eq!(pos, 3),
+                _ => panic!("Error should have been PathSyntax"),
+            }
+        }
+
+        #[test]
+        fn text_content() {
+            let doc_raw = r#"
+            <doc>
+                <h1>Title</h1>
+                <p>Hi <b>there</b></p>
+                <p>Some   <![CDATA[<raw>]]>
+                    text</p>
+            </doc>
+            "#;
+
+            let doc = Document::parse(doc_raw.as_bytes()).unwrap();
+            let root = doc.root.unwrap();
+
+            assert_eq!(root.find("p").unwrap().text_content(), "Hi there");
+            assert_eq!(
+                root.text_content_with(TextOptions::new().block("h1").block("p")),
+                "Title\nHi there\nSome   \n                    text<raw>"
+            );
+            assert_eq!(
+                root.text_content_with(
+                    TextOptions::new()
+                        .block("p")
+                        .separator(" | ")
+                        .normalize_whitespace(true)
+                ),
+                "Title | Hi there | Some text<raw>"
+            );
+        }
+    }
+
+    mod shared {
+        use treexml::{Children, Document, Element};
+
+        #[test]
+        fn clone_shares_children() {
+            let doc_raw = r#"
+            <root>
+                <child>1</child>
+                <child>2</child>
+            </root>
+            "#;
+
+            let doc = Document::parse(doc_raw.as_bytes()).unwrap();
+            let copy = doc.clone();
+
+            let root = doc.root.as_ref().unwrap();
+            let root_copy = copy.root.as_ref().unwrap();
+            assert!(Children::ptr_eq(&root.children, &root_copy.children));
+        }
+
+        #[test]
+        fn mutation_detaches_clone() {
+            let mut fragment = Element::new("fragment");
+            fragment.children.push(Element::new("leaf"));
+
+            let mut root = Element::new("root");
+            root.children.push(fragment.clone());
+            root.children.push(fragment.clone());
+
+            root.children[0].children[0].text = Some("changed".to_owned());
+
+            assert!(!Children::ptr_eq(
+                &root.children[0].children,
+                &fragment.children
+            ));
+            assert!(Children::ptr_eq(
+                &root.children[1].children,
+                &fragment.children
+            ));
+            assert_eq!(fragment.children[0].text, None);
+            assert_eq!(root.children[1].children[0].text, None);
+        }
+
+        #[test]
+        fn vec_migration() {
+            fn push_leaf(children: &mut Vec<Element>) {
+                children.push(Element::new("leaf"));
+            }
+
+            let mut element = Element::new("root");
+            element.children = vec![Element::new("child")].into();
+            push_leaf(&mut element.children);
+
+            let children: Vec<Element> = element.clone().children.into_vec();
+            assert_eq!(children, vec![Element::new("child"), Element::new("leaf")]);
+            assert_eq!(Vec::from(element.children), children);
         }
     }
 
@@ -325,8 +771,7 @@ mod read {
 
         #[test]
         fn parse_document() {
-            let doc_raw = r#"
-            <?xml version="1.1" encoding="UTF-8"?>
+            let doc_raw = r#"<?xml version="1.1" encoding="UTF-8"?>
             <root>
                 <child attr_a="1">content</child>
                 <child attr_a="2"></child>
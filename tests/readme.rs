@@ -5,8 +5,7 @@ mod readme {
     use treexml::{Document, ElementBuilder as E};
     #[test]
     fn read() {
-        let doc_raw = r#"
-    <?xml version="1.1" encoding="UTF-8"?>
+        let doc_raw = r#"<?xml version="1.1" encoding="UTF-8"?>
     <table>
         <fruit type="apple">worm</fruit>
         <vegetable />
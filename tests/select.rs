@@ -0,0 +1,162 @@
+extern crate treexml;
+
+mod select {
+
+    use treexml::{Document, Element, TreexmlError};
+
+    fn catalog() -> Element {
+        let doc_raw = r#"
+        <catalog xmlns:m="urn:media">
+            <section id="fiction">
+                <item class="active featured" type="book" lang="en-GB">Dune</item>
+                <item class="active" type="film">Alien</item>
+                <item type="book" lang="en">Emma</item>
+            </section>
+            <section id="music">
+                <m:item class="active" type="album">Low</m:item>
+                <note>Vinyl only</note>
+            </section>
+        </catalog>
+        "#;
+
+        Document::parse(doc_raw.as_bytes()).unwrap().root.unwrap()
+    }
+
+    fn texts(elements: Vec<&Element>) -> Vec<&str> {
+        elements
+            .into_iter()
+            .map(|el| el.text.as_deref().unwrap_or(""))
+            .collect()
+    }
+
+    #[test]
+    fn type_class_and_attributes() {
+        let root = catalog();
+
+        assert_eq!(
+            texts(root.select("section > item.active[type=book]").unwrap()),
+            vec!["Dune"]
+        );
+        assert_eq!(
+            texts(root.select("item.active").unwrap()),
+            vec!["Dune", "Alien", "Low"]
+        );
+        assert_eq!(
+            texts(root.select("#fiction [lang|=en]").unwrap()),
+            vec!["Dune", "Emma"]
+        );
+        assert_eq!(
+            texts(root.select("[type^=bo], [type$=um]").unwrap()),
+            vec!["Dune", "Emma", "Low"]
+        );
+        assert_eq!(
+            texts(root.select("[class~=featured], [type*='il']").unwrap()),
+            vec!["Dune", "Alien"]
+        );
+    }
+
+    #[test]
+    fn namespaces() {
+        let root = catalog();
+
+        // Prefixes are matched by the namespace they're bound to
+        let media = [("media", "urn:media")];
+        assert_eq!(
+            texts(root.select_with("media|item", &media).unwrap()),
+            vec!["Low"]
+        );
+        assert_eq!(texts(root.select("|item").unwrap()).len(), 3);
+        assert_eq!(texts(root.select("*|item").unwrap()).len(), 4);
+        assert_eq!(texts(root.select("item").unwrap()).len(), 4);
+        assert_eq!(
+            texts(root.select_with("item", &[("", "urn:media")]).unwrap()),
+            vec!["Low"]
+        );
+
+        match root.select("m|item").expect_err("Should have errored") {
+            TreexmlError::SelectorSyntax { pos, .. } => assert_eq!(pos, 0),
+            err => panic!("Error should have been SelectorSyntax, got {:?}", err),
+        }
+
+        let doc_raw = r#"<a:x xmlns:a="u"><y xmlns="u" /><a:z xmlns:a="v" /></a:x>"#;
+        let root = Document::parse(doc_raw.as_bytes()).unwrap().root.unwrap();
+        let found = root.select_with("b|*", &[("b", "u")]).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "y");
+        assert_eq!(
+            root.select_first_with("b|z", &[("b", "v")])
+                .unwrap()
+                .map(|el| el.name.as_str()),
+            Some("z")
+        );
+    }
+
+    #[test]
+    fn structural() {
+        let root = catalog();
+
+        assert_eq!(
+            texts(root.select("item:first-child").unwrap()),
+            vec!["Dune", "Low"]
+        );
+        assert_eq!(
+            texts(root.select("section :last-child").unwrap()),
+            vec!["Emma", "Vinyl only"]
+        );
+        assert_eq!(
+            texts(root.select("item:nth-child(2n+1)").unwrap()),
+            vec!["Dune", "Emma", "Low"]
+        );
+        assert_eq!(
+            texts(root.select("item:nth-last-child(-n+2)").unwrap()),
+            vec!["Alien", "Emma", "Low"]
+        );
+    }
+
+    #[test]
+    fn sibling_combinators() {
+        let root = catalog();
+
+        assert_eq!(
+            texts(root.select("[type=book] + item").unwrap()),
+            vec!["Alien"]
+        );
+        assert_eq!(
+            texts(root.select("[type=book] ~ item").unwrap()),
+            vec!["Alien", "Emma"]
+        );
+        assert_eq!(
+            texts(root.select("item ~ note").unwrap()),
+            vec!["Vinyl only"]
+        );
+    }
+
+    #[test]
+    fn select_first() {
+        let root = catalog();
+
+        assert_eq!(
+            root.select_first("section + section > *")
+                .unwrap()
+                .unwrap()
+                .name,
+            "item"
+        );
+        assert_eq!(root.select_first("video").unwrap(), None);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let root = catalog();
+
+        match root.select("item[type=").expect_err("Should have errored") {
+            TreexmlError::SelectorSyntax { pos, .. } => assert_eq!(pos, 10),
+            _ => panic!("Error should have been SelectorSyntax"),
+        }
+        match root.select("item:hover").expect_err("Should have errored") {
+            TreexmlError::SelectorSyntax { pos, .. } => assert_eq!(pos, 5),
+            _ => panic!("Error should have been SelectorSyntax"),
+        }
+        assert!(root.select("section >").is_err());
+    }
+}
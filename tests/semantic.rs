@@ -0,0 +1,65 @@
+extern crate treexml;
+
+mod semantic {
+
+    use treexml::{Document, Element, EqOptions};
+
+    fn parse(doc_raw: &str) -> Element {
+        Document::parse(doc_raw.as_bytes()).unwrap().root.unwrap()
+    }
+
+    #[test]
+    fn insignificant_differences() {
+        let fixture = parse(
+            r#"<a:feed xmlns:a="urn:atom" a:lang="en" id="1"><a:title>x &lt; y</a:title></a:feed>"#,
+        );
+        let generated = parse(
+            r#"<feed xmlns="urn:atom" xmlns:b="urn:atom" id="1" b:lang="en"><title><![CDATA[x < y]]></title></feed>"#,
+        );
+
+        assert_ne!(fixture, generated);
+        assert!(fixture.semantically_eq(&generated, &EqOptions::new()));
+        assert!(!fixture.semantically_eq(&generated, &EqOptions::strict()));
+        assert!(!fixture.semantically_eq(&generated, EqOptions::new().ignore_prefixes(false)));
+        assert!(!fixture.semantically_eq(&generated, EqOptions::new().cdata_as_text(false)));
+
+        let mut spaced = fixture.clone();
+        spaced.text = Some("\n  ".to_owned());
+        assert!(fixture.semantically_eq(&spaced, &EqOptions::new()));
+        assert!(!fixture.semantically_eq(&spaced, EqOptions::new().ignore_whitespace_text(false)));
+    }
+
+    #[test]
+    fn significant_differences() {
+        let a = parse(r#"<r xmlns:x="urn:one"><x:item>1</x:item></r>"#);
+
+        for other in &[
+            r#"<r xmlns:x="urn:two"><x:item>1</x:item></r>"#,
+            r#"<r xmlns:x="urn:one"><x:item>2</x:item></r>"#,
+            r#"<r xmlns:x="urn:one"><x:item>1</x:item><x:item /></r>"#,
+            r#"<r xmlns:x="urn:one"><x:item n="1">1</x:item></r>"#,
+        ] {
+            assert!(!a.semantically_eq(&parse(other), &EqOptions::new()));
+        }
+
+        // Strict comparison keeps prefixes, but they still have to mean the same thing
+        let strict = EqOptions::strict();
+        for (left, right) in &[
+            (
+                r#"<x:r xmlns:x="urn:one" />"#,
+                r#"<x:r xmlns:x="urn:two" />"#,
+            ),
+            (
+                r#"<r xmlns:x="urn:one" x:a="1" />"#,
+                r#"<r xmlns:x="urn:two" x:a="1" />"#,
+            ),
+            (r#"<r xmlns="urn:one" />"#, r#"<r xmlns="urn:two" />"#),
+        ] {
+            assert!(!parse(left).semantically_eq(&parse(right), &strict));
+        }
+        assert!(a.semantically_eq(
+            &parse(r#"<r xmlns:x="urn:one"><x:item>1</x:item></r>"#),
+            &strict
+        ));
+    }
+}
@@ -0,0 +1,41 @@
+extern crate treexml;
+
+mod testing {
+
+    use treexml::{assert_xml_eq, Document, Element};
+
+    #[test]
+    fn equal() {
+        let doc =
+            Document::parse(r#"<a xmlns:p="urn:p"><p:b k="v">1</p:b></a>"#.as_bytes()).unwrap();
+        let root: Element = doc.root.clone().unwrap();
+
+        assert_xml_eq!(doc, root);
+        assert_xml_eq!(
+            root,
+            r#"<a xmlns:q="urn:p">
+                <q:b k="v"><![CDATA[1]]></q:b>
+            </a>"#,
+            "with {} text",
+            "CDATA"
+        );
+        assert_xml_eq!("<a/>".to_owned(), "<a></a>");
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "first difference at /a/b[2]/@k: attribute 'k' is \"1\" on the left and \"2\" on the right"
+    )]
+    fn first_difference() {
+        assert_xml_eq!(
+            r#"<a><b k="1" /><b k="1"><c /></b></a>"#,
+            r#"<a><b k="1" /><b k="2" /></a>"#
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "first difference at /a/c: element is only on the right")]
+    fn extra_element() {
+        assert_xml_eq!(r#"<a><b /></a>"#, r#"<a><b /><c /></a>"#);
+    }
+}
@@ -0,0 +1,105 @@
+extern crate treexml;
+
+mod traverse {
+
+    use treexml::{Document, Element, TraverseEvent};
+
+    fn page() -> Element {
+        let doc_raw = r#"
+        <page>
+            <header><img src="logo.png" /></header>
+            <body>
+                <p>One <img src="a.png" /></p>
+                <p>Two</p>
+            </body>
+        </page>
+        "#;
+
+        Document::parse(doc_raw.as_bytes()).unwrap().root.unwrap()
+    }
+
+    fn names<'a>(elements: impl Iterator<Item = &'a Element>) -> Vec<&'a str> {
+        elements.map(|el| el.name.as_str()).collect()
+    }
+
+    #[test]
+    fn descendants() {
+        let root = page();
+
+        assert_eq!(
+            names(root.descendants()),
+            vec!["header", "img", "body", "p", "img", "p"]
+        );
+        assert_eq!(
+            root.descendants()
+                .filter(|el| el.name == "img")
+                .map(|el| el.attributes["src"].as_str())
+                .collect::<Vec<_>>(),
+            vec!["logo.png", "a.png"]
+        );
+        assert_eq!(Element::new("leaf").descendants().count(), 0);
+    }
+
+    #[test]
+    fn descendants_mut() {
+        let mut root = page();
+
+        for el in root.descendants_mut() {
+            if el.name == "img" {
+                el.attributes.insert("alt".to_owned(), String::new());
+            }
+            if *el.name == "p" {
+                *el.name = "para".to_owned();
+            }
+        }
+
+        assert_eq!(root.find_all("//img[@alt]").unwrap().count(), 2);
+        assert_eq!(root.count("body/para").unwrap(), 2);
+    }
+
+    #[test]
+    fn breadth_first() {
+        let root = page();
+
+        assert_eq!(
+            names(root.breadth_first()),
+            vec!["header", "body", "img", "p", "p", "img"]
+        );
+    }
+
+    #[test]
+    fn traverse() {
+        let root = page();
+
+        let events: Vec<String> = root
+            .find("header")
+            .unwrap()
+            .traverse()
+            .map(|event| match event {
+                TraverseEvent::Enter { element, depth } => format!("+{}{}", element.name, depth),
+                TraverseEvent::Leave { element, depth } => format!("-{}{}", element.name, depth),
+            })
+            .collect();
+        assert_eq!(events, vec!["+header0", "+img1", "-img1", "-header0"]);
+
+        let deepest = root.traverse().map(|event| event.depth()).max();
+        assert_eq!(deepest, Some(3));
+    }
+
+    #[test]
+    fn deep_tree() {
+        let mut root = Element::new("n");
+        {
+            let mut el = &mut root;
+            for _ in 0..2000 {
+                el.children.push(Element::new("n"));
+                el = el.children.last_mut().unwrap();
+            }
+        }
+
+        assert_eq!(root.descendants().count(), 2000);
+        assert_eq!(root.descendants_mut().count(), 2000);
+        assert_eq!(root.breadth_first().count(), 2000);
+        assert_eq!(root.traverse().count(), 4002);
+    }
+}
@@ -0,0 +1,139 @@
+extern crate treexml;
+
+mod validate {
+
+    use treexml::{
+        Document, Element, InvalidChars, Problem, ProblemKind, TreexmlError, WriteOptions,
+        XmlVersion,
+    };
+
+    fn problems(result: Result<(), TreexmlError>) -> Vec<Problem> {
+        match result.expect_err("Should have errored") {
+            TreexmlError::InvalidXml { problems } => problems,
+            err => panic!("Error should have been InvalidXml, got {:?}", err),
+        }
+    }
+
+    fn problem(path: &str, kind: ProblemKind, character: Option<char>) -> Problem {
+        Problem {
+            path: path.to_owned(),
+            kind,
+            character,
+            version: XmlVersion::Version10,
+        }
+    }
+
+    fn document(version: XmlVersion) -> Document {
+        let mut root = Element::new("root");
+        root.attributes.insert("ok".to_owned(), "a\tb".to_owned());
+        let mut item = Element::new("item");
+        item.text = Some("bell\u{7}".to_owned());
+        root.children.push(item.clone());
+        item.text = Some("del\u{80}".to_owned());
+        root.children.push(item);
+        let mut bad = Element::new("1st");
+        bad.attributes
+            .insert("has space".to_owned(), "\0".to_owned());
+        root.children.push(bad);
+
+        Document {
+            version,
+            root: Some(root),
+            ..Document::default()
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert!(Element::try_new("item-1.x").is_ok());
+        for name in &["1st", "has space", "p:x", ""] {
+            match Element::try_new(name).expect_err("Should have errored") {
+                TreexmlError::InvalidName { name: n } => assert_eq!(&n, name),
+                err => panic!("Error should have been InvalidName, got {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn report() {
+        assert_eq!(
+            problems(document(XmlVersion::Version10).validate()),
+            vec![
+                problem(
+                    "/root/item[1]/text()",
+                    ProblemKind::Disallowed,
+                    Some('\u{7}')
+                ),
+                problem("/root/1st", ProblemKind::ElementName, None),
+                problem("/root/1st/@has space", ProblemKind::AttributeName, None),
+                problem("/root/1st/@has space", ProblemKind::Disallowed, Some('\0')),
+            ]
+        );
+
+        let problems = problems(document(XmlVersion::Version11).validate());
+        assert_eq!(
+            problems
+                .iter()
+                .take(2)
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "'/root/item[1]/text()': character U+0007 must be escaped in XML 1.1",
+                "'/root/item[2]/text()': character U+0080 must be escaped in XML 1.1",
+            ]
+        );
+        assert_eq!(problems[0].kind, ProblemKind::Unescaped);
+        assert_eq!(problems[0].character, Some('\u{7}'));
+    }
+
+    #[test]
+    fn write() {
+        // XML 1.0 has no references for C0 controls, but allows C1 controls as they are
+        let mut root = Element::new("root");
+        root.text = Some("bell\u{7}".to_owned());
+        let doc = Document {
+            root: Some(root.clone()),
+            ..Document::default()
+        };
+        match doc.to_xml_string(&WriteOptions::new()) {
+            Err(TreexmlError::InvalidXml { problems }) => assert_eq!(
+                problems,
+                vec![problem(
+                    "/root/text()",
+                    ProblemKind::Disallowed,
+                    Some('\u{7}')
+                )]
+            ),
+            other => panic!("Error should have been InvalidXml, got {:?}", other),
+        }
+        root.text = Some("del\u{80}".to_owned());
+        assert!(root
+            .to_xml_string(&WriteOptions::new())
+            .unwrap()
+            .ends_with("<root>del\u{80}</root>"));
+
+        // Validating first reports every problem before any of the root is written
+        let mut out = Vec::new();
+        let result = document(XmlVersion::Version11)
+            .write_with_options(&mut out, WriteOptions::new().validate(true));
+        assert_eq!(problems(result).len(), 3);
+        assert!(!String::from_utf8(out).unwrap().contains("<root"));
+    }
+
+    #[test]
+    fn escape_and_strip() {
+        let mut doc = document(XmlVersion::Version11);
+        doc.root.as_mut().unwrap().children.pop();
+        doc.validate_with(InvalidChars::Escape).unwrap();
+        assert!(doc
+            .to_string()
+            .contains("<item>bell&#x7;</item>\n  <item>del&#x80;</item>"));
+
+        let mut doc = document(XmlVersion::Version10);
+        assert_eq!(problems(doc.validate_with(InvalidChars::Strip)).len(), 2);
+        let root = doc.root.unwrap();
+        assert_eq!(root.children[0].text.as_deref(), Some("bell"));
+        assert_eq!(root.children[1].text.as_deref(), Some("del\u{80}"));
+        assert_eq!(root.children[2].attributes["has space"], "");
+    }
+}
@@ -0,0 +1,118 @@
+extern crate treexml;
+
+mod visit {
+
+    use treexml::{Document, Element, Transform, Transformer, VisitControl, Visitor};
+
+    fn article() -> Element {
+        let doc_raw = r#"
+        <article>
+            <title>Trees</title>
+            <section draft="yes"><p>Hidden</p></section>
+            <section><p>Roots</p><p>Leaves</p></section>
+            <footer>End</footer>
+        </article>
+        "#;
+
+        Document::parse(doc_raw.as_bytes()).unwrap().root.unwrap()
+    }
+
+    #[derive(Default)]
+    struct Outline<'a> {
+        events: Vec<String>,
+        texts: Vec<&'a str>,
+    }
+
+    impl<'a> Visitor<'a> for Outline<'a> {
+        fn visit_element(&mut self, element: &'a Element) -> VisitControl {
+            self.events.push(format!("+{}", element.name));
+            if element.attributes.contains_key("draft") {
+                VisitControl::SkipChildren
+            } else if element.name == "footer" {
+                VisitControl::Stop
+            } else {
+                VisitControl::Continue
+            }
+        }
+
+        fn leave_element(&mut self, element: &'a Element) -> VisitControl {
+            self.events.push(format!("-{}", element.name));
+            VisitControl::Continue
+        }
+
+        fn visit_text(&mut self, text: &'a str) -> VisitControl {
+            self.texts.push(text);
+            VisitControl::Continue
+        }
+    }
+
+    #[test]
+    fn visitor() {
+        let root = article();
+        let mut outline = Outline::default();
+        root.visit(&mut outline);
+
+        assert_eq!(
+            outline.events,
+            vec![
+                "+article", "+title", "-title", "+section", "-section", "+section", "+p", "-p",
+                "+p", "-p", "-section", "+footer",
+            ]
+        );
+        assert_eq!(outline.texts, vec!["Trees", "Roots", "Leaves"]);
+    }
+
+    struct Rewrite;
+
+    impl Transformer for Rewrite {
+        fn transform_element(&mut self, element: &mut Element) -> Transform {
+            match element.name.as_str() {
+                "section" if element.attributes.contains_key("draft") => Transform::Remove,
+                "footer" => Transform::Stop,
+                "title" => {
+                    *element = Element::new("h1");
+                    Transform::Skip
+                }
+                "p" => {
+                    let mut rule = Element::new("hr");
+                    rule.attributes.insert("class".to_owned(), "gap".to_owned());
+                    element.name = "para".to_owned();
+                    Transform::Expand(vec![element.clone(), rule])
+                }
+                _ => Transform::Keep,
+            }
+        }
+
+        fn leave_element(&mut self, element: &mut Element) -> Transform {
+            element
+                .attributes
+                .insert("count".to_owned(), element.children.len().to_string());
+            Transform::Keep
+        }
+
+        fn transform_text(&mut self, text: String) -> Option<String> {
+            Some(text.to_uppercase())
+        }
+    }
+
+    #[test]
+    fn transformer() {
+        let mut root = article();
+        root.transform(&mut Rewrite);
+
+        let names: Vec<&str> = root.children.iter().map(|el| el.name.as_str()).collect();
+        assert_eq!(names, vec!["h1", "section", "footer"]);
+        assert_eq!(root.children[0].text, None);
+
+        let section = &root.children[1];
+        assert_eq!(section.attributes["count"], "4");
+        let names: Vec<&str> = section.children.iter().map(|el| el.name.as_str()).collect();
+        assert_eq!(names, vec!["para", "hr", "para", "hr"]);
+        // Expanded elements aren't transformed further
+        assert_eq!(section.children[0].text.as_deref(), Some("Roots"));
+
+        // Stopped before the footer's text was transformed
+        assert_eq!(root.children[2].text.as_deref(), Some("End"));
+        assert!(!root.attributes.contains_key("count"));
+    }
+}
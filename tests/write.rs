@@ -50,7 +50,9 @@ mod write {
 
     mod element {
 
-        use treexml::{Document, Element};
+        use treexml::{
+            Document, Element, ElementBuilder as E, TreexmlError, WriteOptions, XmlVersion,
+        };
 
         #[test]
         fn stringify() {
@@ -68,6 +70,64 @@ mod write {
 
             assert_eq!(child2.to_string(), elem_ref);
         }
+
+        #[test]
+        fn fallible_strings() {
+            let mut root = Element::new("root");
+            root.children.push(Element::new("child"));
+
+            let condensed = WriteOptions::new()
+                .document_decl(false)
+                .indent(false)
+                .clone();
+            assert_eq!(
+                root.to_xml_string(&condensed).unwrap(),
+                "<root><child /></root>"
+            );
+
+            let doc = Document {
+                root: Some(root),
+                ..Document::default()
+            };
+            let mut s = String::from("doc: ");
+            doc.write_to_fmt(&mut s, &condensed).unwrap();
+            assert_eq!(s, "doc: <root><child /></root>");
+            assert_eq!(
+                doc.to_xml_string(&WriteOptions::new()).unwrap(),
+                doc.to_string()
+            );
+
+            match Element::new("bad name").to_xml_string(&WriteOptions::new()) {
+                Err(TreexmlError::InvalidName { name }) => assert_eq!(name, "bad name"),
+                other => panic!("Error should have been InvalidName, got {:?}", other),
+            }
+            let mut bad_attribute = Element::new("root");
+            bad_attribute
+                .attributes
+                .insert("1st".to_owned(), "value".to_owned());
+            let doc = Document {
+                root: Some(bad_attribute),
+                ..Document::default()
+            };
+            assert!(doc.to_xml_string(&WriteOptions::new()).is_err());
+            assert!(doc.to_string().contains("1st=\"value\""));
+
+            // Display can't fail without panicking, so it writes invalid names as they are
+            assert_eq!(Element::new("bad name").to_string(), "<bad name />");
+
+            // and drops characters the version doesn't allow
+            let control = E::new("t").attr("a", "x\u{1}y").text("bell\u{1}").element();
+            assert!(control.to_xml_string(&WriteOptions::new()).is_err());
+            assert_eq!(control.to_string(), "<t a=\"xy\">bell</t>");
+            let cdata = E::new("t").cdata("\u{1}\u{0}").element();
+            let doc = Document {
+                version: XmlVersion::Version11,
+                root: Some(cdata.clone()),
+                ..Document::default()
+            };
+            assert_eq!(cdata.to_string(), "<t><![CDATA[]]></t>");
+            assert!(doc.to_string().ends_with("\n<t>&#x1;</t>"));
+        }
     }
 
     mod contents {
@@ -198,4 +258,357 @@ mod write {
             assert_eq!(doc.to_string(), doc_ref);
         }
     }
+
+    mod mixed {
+        use treexml::{Document, ElementBuilder as E, WriteOptions};
+
+        #[test]
+        fn inline_contents() {
+            let doc = Document::build(E::new("doc").children(vec![
+                E::new("p")
+                    .text("Some ")
+                    .children(vec![E::new("em").children(vec![&mut E::new("b")])]),
+                E::new("code")
+                    .attr("xml:space", "preserve")
+                    .children(vec![E::new("line").children(vec![
+                        E::new("x").attr("xml:space", "default").children(vec![&mut E::new("y")]),
+                    ])]),
+                E::new("pre").children(vec![&mut E::new("line")]),
+                E::new("list").children(vec![&mut E::new("item")]),
+            ]));
+
+            let doc_ref = concat!(
+                "<doc>\n",
+                "  <p>Some <em><b /></em></p>\n",
+                "  <code xml:space=\"preserve\"><line><x xml:space=\"default\">\n",
+                "        <y />\n",
+                "      </x></line></code>\n",
+                "  <pre><line /></pre>\n",
+                "  <list>\n",
+                "    <item />\n",
+                "  </list>\n",
+                "</doc>"
+            );
+
+            let mut options = WriteOptions::new();
+            options.document_decl(false).preserve_space("pre");
+            assert_eq!(doc.to_xml_string(&options).unwrap(), doc_ref);
+        }
+    }
+
+    mod wrap {
+        use treexml::{AttributeWrap, Document, ElementBuilder as E, WriteOptions};
+
+        fn manifest() -> Document {
+            let mut doc = Document::build(
+                E::new("manifest")
+                    .attr(
+                        "xmlns:android",
+                        "http://schemas.android.com/apk/res/android",
+                    )
+                    .attr("package", "com.example")
+                    .children(vec![
+                        E::new("uses-sdk").attr("minSdk", 21).attr("targetSdk", 33),
+                        E::new("application").attr("android:label", "Example"),
+                    ]),
+            );
+            doc.root.as_mut().unwrap().children[0]
+                .attributes
+                .insert("android:maxSdkVersion".to_owned(), "34".to_owned());
+            doc
+        }
+
+        #[test]
+        fn when_too_long() {
+            let mut options = WriteOptions::new();
+            options
+                .document_decl(false)
+                .max_line_width(70)
+                .wrap_attributes(AttributeWrap::WhenTooLong);
+
+            let doc_ref = concat!(
+                "<manifest xmlns:android=\"http://schemas.android.com/apk/res/android\"\n",
+                "          package=\"com.example\">\n",
+                "  <uses-sdk minSdk=\"21\" targetSdk=\"33\" android:maxSdkVersion=\"34\" />\n",
+                "  <application android:label=\"Example\" />\n",
+                "</manifest>"
+            );
+            assert_eq!(manifest().to_xml_string(&options).unwrap(), doc_ref);
+        }
+
+        #[test]
+        fn always_unaligned() {
+            let mut options = WriteOptions::new();
+            options
+                .document_decl(false)
+                .indent_string("    ")
+                .wrap_attributes(AttributeWrap::Always)
+                .align_attributes(false);
+
+            let doc_ref = concat!(
+                "<manifest xmlns:android=\"http://schemas.android.com/apk/res/android\"\n",
+                "    package=\"com.example\">\n",
+                "    <uses-sdk minSdk=\"21\"\n",
+                "        targetSdk=\"33\"\n",
+                "        android:maxSdkVersion=\"34\" />\n",
+                "    <application android:label=\"Example\" />\n",
+                "</manifest>"
+            );
+            assert_eq!(manifest().to_xml_string(&options).unwrap(), doc_ref);
+        }
+    }
+
+    mod normalized {
+        use treexml::{Document, ElementBuilder as E, SortKey, WriteOptions};
+
+        #[test]
+        fn reproducible() {
+            let a = Document::build(
+                E::new("project")
+                    .attr("version", "1")
+                    .attr("name", "x")
+                    .children(vec![
+                        E::new("dependencies").children(vec![
+                            E::new("dependency").attr("id", "b").text("  two\n  words "),
+                            E::new("dependency").attr("id", "a"),
+                        ]),
+                        E::new("p")
+                            .text(" mixed\t")
+                            .children(vec![&mut E::new("br")]),
+                        E::new("pre").attr("xml:space", "preserve").text(" as  is "),
+                    ]),
+            );
+            let b = Document::build(
+                E::new("project")
+                    .attr("name", "x")
+                    .attr("version", "1")
+                    .children(vec![
+                        E::new("dependencies").children(vec![
+                            E::new("dependency").attr("id", "a"),
+                            E::new("dependency").attr("id", "b").text("two words"),
+                        ]),
+                        E::new("p")
+                            .text(" mixed ")
+                            .children(vec![&mut E::new("br")]),
+                        E::new("pre").attr("xml:space", "preserve").text(" as  is "),
+                    ]),
+            );
+
+            let mut options = WriteOptions::normalized();
+            options
+                .document_decl(false)
+                .attribute_priority("version")
+                .sort_children("dependencies", SortKey::Attribute("id".to_owned()));
+
+            let doc_ref = concat!(
+                "<project version=\"1\" name=\"x\">\n",
+                "  <dependencies>\n",
+                "    <dependency id=\"a\" />\n",
+                "    <dependency id=\"b\">two words</dependency>\n",
+                "  </dependencies>\n",
+                "  <p> mixed <br /></p>\n",
+                "  <pre xml:space=\"preserve\"> as  is </pre>\n",
+                "</project>"
+            );
+            assert_eq!(a.to_xml_string(&options).unwrap(), doc_ref);
+            assert_eq!(b.to_xml_string(&options).unwrap(), doc_ref);
+        }
+    }
+
+    mod escaping {
+        use treexml::{
+            Document, Element, ElementBuilder as E, EscapeOptions, TreeWriter, TreexmlError,
+            WriteOptions,
+        };
+
+        #[test]
+        fn policies() {
+            let el = E::new("q")
+                .attr("title", "it's \"café\" > 1")
+                .text("it's \"café\" > 1 & <2>")
+                .element();
+
+            assert_eq!(
+                el.to_string(),
+                "<q title=\"it&apos;s &quot;café&quot; &gt; 1\">it's \"café\" > 1 &amp; &lt;2></q>"
+            );
+
+            let mut options = WriteOptions::new();
+            options
+                .document_decl(false)
+                .escape_text(
+                    EscapeOptions::text()
+                        .quotes(true)
+                        .apostrophes(true)
+                        .greater_than(true)
+                        .non_ascii(true),
+                )
+                .escape_attributes(
+                    EscapeOptions::attribute()
+                        .quotes(false)
+                        .apostrophes(false)
+                        .greater_than(false),
+                );
+            assert_eq!(
+                el.to_xml_string(&options).unwrap(),
+                "<q title=\"it's &quot;café&quot; > 1\">it&apos;s &quot;caf&#xE9;&quot; &gt; 1 &amp; &lt;2&gt;</q>"
+            );
+        }
+
+        #[test]
+        fn cdata_end_in_text() {
+            let el = E::new("t").attr("a", "]]>").text("a]]>b ]>]]]>").element();
+            let written = el.to_string();
+            assert_eq!(written, "<t a=\"]]&gt;\">a]]&gt;b ]>]]]&gt;</t>");
+            let read = Document::parse(written.as_bytes()).unwrap().root.unwrap();
+            assert_eq!(read.text.as_deref(), Some("a]]>b ]>]]]>"));
+
+            let mut out = Vec::new();
+            let mut writer = TreeWriter::new(&mut out, WriteOptions::new().document_decl(false));
+            writer.start_element(&Element::new("t")).unwrap();
+            for text in &["a]", "]", ">b"] {
+                writer.write_text(text).unwrap();
+            }
+            writer.finish().unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), "<t>a]]&gt;b</t>");
+        }
+
+        #[test]
+        fn ascii_only() {
+            let mut options = WriteOptions::new();
+            options
+                .document_decl(false)
+                .escape_text(EscapeOptions::text().non_ascii(true));
+
+            let el = E::new("q").cdata("café <1>").element();
+            assert_eq!(
+                el.to_xml_string(&options).unwrap(),
+                "<q>caf&#xE9; &lt;1></q>"
+            );
+            let el = E::new("q").cdata("cafe <1>").element();
+            assert_eq!(
+                el.to_xml_string(&options).unwrap(),
+                "<q><![CDATA[cafe <1>]]></q>"
+            );
+
+            for el in &[
+                E::new("café").element(),
+                E::new("q").attr("café", 1).element(),
+            ] {
+                match el.to_xml_string(&options) {
+                    Err(TreexmlError::NonAsciiName { name }) => assert_eq!(name, "café"),
+                    other => panic!("Non-ASCII name should have failed, got {:?}", other),
+                }
+                assert!(el
+                    .to_xml_string(WriteOptions::new().document_decl(false))
+                    .is_ok());
+            }
+        }
+    }
+
+    mod stream {
+        use treexml::{
+            Document, Element, ElementBuilder as E, TreeWriter, TreexmlError, WriteOptions,
+            XmlVersion,
+        };
+
+        #[test]
+        fn mixed_events_and_elements() {
+            let mut out = Vec::new();
+            let mut writer = TreeWriter::new(&mut out, &WriteOptions::new());
+            writer
+                .start_document(XmlVersion::Version11, "UTF-8")
+                .unwrap();
+            writer
+                .start_element(&E::new("export").attr("count", 2).element())
+                .unwrap();
+            writer.start_element(&Element::new("records")).unwrap();
+            for i in 0..2 {
+                writer
+                    .write_element(&E::new("record").attr("id", i).text("a & b").element())
+                    .unwrap();
+            }
+            writer.end_element().unwrap();
+            writer.start_element(&Element::new("footer")).unwrap();
+            writer.write_text("done").unwrap();
+            writer.finish().unwrap();
+
+            let doc_ref = concat!(
+                "<?xml version=\"1.1\" encoding=\"UTF-8\"?>\n",
+                "<export count=\"2\">\n",
+                "  <records>\n",
+                "    <record id=\"0\">a &amp; b</record>\n",
+                "    <record id=\"1\">a &amp; b</record>\n",
+                "  </records>\n",
+                "  <footer>done</footer>\n",
+                "</export>"
+            );
+
+            assert_eq!(String::from_utf8(out).unwrap(), doc_ref);
+        }
+
+        #[test]
+        fn unbalanced_end() {
+            let mut writer = TreeWriter::new(Vec::new(), WriteOptions::new().document_decl(false));
+            writer.write_element(&Element::new("a")).unwrap();
+            match writer.end_element() {
+                Err(TreexmlError::NoOpenElement) => {}
+                other => panic!("Error should have been NoOpenElement, got {:?}", other),
+            }
+            assert_eq!(writer.finish().unwrap(), b"<a />");
+        }
+
+        #[test]
+        fn single_root() {
+            let mut writer = TreeWriter::new(Vec::new(), WriteOptions::new().document_decl(false));
+            writer.start_element(&Element::new("a")).unwrap();
+            writer.write_element(&Element::new("b")).unwrap();
+            writer.end_element().unwrap();
+            for result in &[
+                writer.write_element(&Element::new("second")),
+                writer.start_element(&Element::new("second")),
+            ] {
+                match result {
+                    Err(TreexmlError::MultipleRoots) => {}
+                    other => panic!("Error should have been MultipleRoots, got {:?}", other),
+                }
+            }
+            assert_eq!(writer.finish().unwrap(), b"<a>\n  <b />\n</a>");
+        }
+
+        #[test]
+        fn cdata_end_marker() {
+            let mut writer = TreeWriter::new(Vec::new(), WriteOptions::new().document_decl(false));
+            writer.start_element(&Element::new("a")).unwrap();
+            writer.write_cdata("x]]>y").unwrap();
+            assert_eq!(
+                writer.finish().unwrap(),
+                b"<a><![CDATA[x]]]]><![CDATA[>y]]></a>"
+            );
+        }
+
+        #[test]
+        fn encoding() {
+            let mut writer = TreeWriter::new(Vec::new(), &WriteOptions::new());
+            match writer.start_document(XmlVersion::Version10, "ISO-8859-1") {
+                Err(TreexmlError::UnsupportedEncoding { encoding }) => {
+                    assert_eq!(encoding, "ISO-8859-1")
+                }
+                other => panic!(
+                    "Error should have been UnsupportedEncoding, got {:?}",
+                    other
+                ),
+            }
+
+            let doc = Document {
+                encoding: "ISO-8859-1".to_owned(),
+                root: Some(Element::new("a")),
+                ..Document::default()
+            };
+            assert_eq!(
+                doc.to_string(),
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<a />"
+            );
+        }
+    }
 }
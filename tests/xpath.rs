@@ -0,0 +1,174 @@
+extern crate treexml;
+
+mod xpath {
+
+    use treexml::{Document, Element, TreexmlError, XPath, XPathContext, XPathNode, XPathValue};
+
+    fn library() -> Element {
+        let doc_raw = r#"
+        <library xmlns:b="urn:books">
+            <shelf id="a">
+                <b:book id="1" lang="en">First</b:book>
+                <b:book id="2" lang="fr">Second</b:book>
+            </shelf>
+            <shelf id="b">
+                <b:book id="3" lang="en">Third</b:book>
+                <magazine id="4">Fourth</magazine>
+            </shelf>
+        </library>
+        "#;
+
+        Document::parse(doc_raw.as_bytes()).unwrap().root.unwrap()
+    }
+
+    fn texts(nodes: &[XPathNode]) -> Vec<String> {
+        nodes.iter().map(XPathNode::string_value).collect()
+    }
+
+    #[test]
+    fn child_and_descendant() {
+        let root = library();
+
+        assert_eq!(root.xpath("shelf").unwrap().len(), 2);
+        assert_eq!(
+            texts(&root.xpath("//b:book").unwrap()),
+            vec!["First", "Second", "Third"]
+        );
+        assert_eq!(
+            texts(&root.xpath("/library/shelf/*").unwrap()),
+            vec!["First", "Second", "Third", "Fourth"]
+        );
+        assert_eq!(
+            texts(&root.xpath("descendant::magazine").unwrap()),
+            vec!["Fourth"]
+        );
+    }
+
+    #[test]
+    fn predicates() {
+        let root = library();
+
+        assert_eq!(
+            texts(&root.xpath("shelf[@id='b']/*[1]").unwrap()),
+            vec!["Third"]
+        );
+        assert_eq!(
+            texts(&root.xpath("shelf/*[last()]").unwrap()),
+            vec!["Second", "Fourth"]
+        );
+        assert_eq!(texts(&root.xpath("(//b:book)[2]").unwrap()), vec!["Second"]);
+        assert_eq!(
+            texts(&root.xpath("//*[@lang='en' and @id > 1]").unwrap()),
+            vec!["Third"]
+        );
+    }
+
+    #[test]
+    fn parent_and_sibling_axes() {
+        let root = library();
+
+        let shelves = root.xpath("//magazine/../@id").unwrap();
+        match shelves[..] {
+            [XPathNode::Attribute { name, value, .. }] => {
+                assert_eq!((name, value), ("id", "b"));
+            }
+            _ => panic!("Expected a single attribute, got {:?}", shelves),
+        }
+
+        assert_eq!(
+            texts(&root.xpath("shelf/*[@id='1']/following-sibling::*").unwrap()),
+            vec!["Second"]
+        );
+        assert_eq!(
+            texts(
+                &root
+                    .xpath("shelf[2]/preceding-sibling::shelf/*[1]")
+                    .unwrap()
+            ),
+            vec!["First"]
+        );
+    }
+
+    #[test]
+    fn text_nodes() {
+        let root = library();
+
+        assert_eq!(
+            root.xpath("shelf[1]/*[2]/text()").unwrap(),
+            vec![XPathNode::Text("Second")]
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let root = library();
+        let context = XPathContext::new();
+        let eval = |expr: &str| {
+            XPath::compile(expr)
+                .unwrap()
+                .evaluate(&root, &context)
+                .unwrap()
+        };
+
+        assert_eq!(eval("count(//@id)"), XPathValue::Number(6.0));
+        assert_eq!(eval("sum(shelf/*/@id) div 2"), XPathValue::Number(5.0));
+        assert_eq!(
+            eval("concat(name(shelf/*[1]), '|', local-name(shelf/*[1]))"),
+            XPathValue::String("b:book|book".to_owned())
+        );
+        assert_eq!(
+            eval("substring-after(normalize-space('  a  b '), ' ')"),
+            XPathValue::String("b".to_owned())
+        );
+        assert_eq!(
+            eval("translate(substring('12345', 1.5, 2.6), '234', 'ab')"),
+            XPathValue::String("ab".to_owned())
+        );
+        assert_eq!(eval("not(//video)"), XPathValue::Boolean(true));
+        assert_eq!(eval("round(-2.5) = -2"), XPathValue::Boolean(true));
+        assert_eq!(
+            eval("string(1 div 0)"),
+            XPathValue::String("Infinity".to_owned())
+        );
+    }
+
+    #[test]
+    fn namespace_bindings() {
+        let root = library();
+
+        let mut context = XPathContext::new();
+        context.namespace("bk", "urn:books");
+        let books = XPath::compile("//bk:book").unwrap();
+        assert_eq!(books.select(&root, &context).unwrap().len(), 3);
+
+        let mut context = XPathContext::new();
+        context.namespace("b", "urn:other");
+        assert!(books.select(&root, &context).unwrap().is_empty());
+        assert!(XPath::compile("//b:book")
+            .unwrap()
+            .select(&root, &context)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn syntax_errors() {
+        match XPath::compile("shelf[@id='a'").expect_err("Should have errored") {
+            TreexmlError::XPathSyntax { pos, .. } => assert_eq!(pos, 13),
+            _ => panic!("Error should have been XPathSyntax"),
+        }
+
+        match XPath::compile("shelf/frobnicate(1)").expect_err("Should have errored") {
+            TreexmlError::XPathSyntax { pos, .. } => assert_eq!(pos, 6),
+            _ => panic!("Error should have been XPathSyntax"),
+        }
+
+        match library()
+            .xpath("count(shelf)")
+            .expect_err("Should have errored")
+        {
+            TreexmlError::XPathEvaluation { .. } => {}
+            _ => panic!("Error should have been XPathEvaluation"),
+        }
+    }
+}